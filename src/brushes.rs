@@ -1,4 +1,5 @@
-use crate::channels::ViewMut;
+use crate::channels::{View, ViewMut};
+use crate::utils::blend;
 
 pub(crate) struct BasicBrush {
     size: u32,
@@ -9,18 +10,13 @@ impl BasicBrush {
     pub(crate) fn new(size: u32, value: u8) -> Self {
         BasicBrush { size, value }
     }
-}
-
-pub(crate) trait Brush {
-    fn apply(&self, image: ViewMut<'_, u8>, x: u32, y: u32);
-}
 
-impl Brush for BasicBrush {
-    fn apply(&self, mut image: ViewMut<'_, u8>, x: u32, y: u32) {
+    /// Visits every pixel covered by the brush centered at (x, y).
+    fn for_each_pixel(&self, width: u32, height: u32, x: u32, y: u32, mut f: impl FnMut(u32, u32)) {
         let brush_size = self.size as i32;
 
-        let width = image.width() as i32;
-        let height = image.height() as i32;
+        let width = width as i32;
+        let height = height as i32;
 
         let x0 = x as i32;
         let y0 = y as i32;
@@ -39,9 +35,46 @@ impl Brush for BasicBrush {
                 let dist = (x_squared + y_squared).sqrt();
 
                 if dist <= brush_size as f64 / 2.0 {
-                    image.set(x as u32, y as u32, self.value);
+                    f(x as u32, y as u32);
                 }
             }
         }
     }
 }
+
+pub(crate) trait Brush {
+    fn apply(&self, image: ViewMut<'_, u8>, x: u32, y: u32);
+
+    /// Like `apply`, but every pixel is blended between its value from `origin`
+    /// and the brush value according to `mask`. Painting over the same pixel
+    /// twice has no additional effect as long as `origin` stays the same.
+    fn apply_masked(
+        &self,
+        image: ViewMut<'_, u8>,
+        origin: View<'_, u8>,
+        mask: View<'_, u8>,
+        x: u32,
+        y: u32,
+    );
+}
+
+impl Brush for BasicBrush {
+    fn apply(&self, mut image: ViewMut<'_, u8>, x: u32, y: u32) {
+        self.for_each_pixel(image.width(), image.height(), x, y, |x, y| {
+            image.set(x, y, self.value);
+        });
+    }
+
+    fn apply_masked(
+        &self,
+        mut image: ViewMut<'_, u8>,
+        origin: View<'_, u8>,
+        mask: View<'_, u8>,
+        x: u32,
+        y: u32,
+    ) {
+        self.for_each_pixel(image.width(), image.height(), x, y, |x, y| {
+            image.set(x, y, blend(origin.get(x, y), self.value, mask.get(x, y)));
+        });
+    }
+}
@@ -0,0 +1,142 @@
+use druid::{Data, Lens};
+
+use crate::channels::{Matrix, View};
+use crate::ops;
+
+/// Output channel made of the input channels, all values are in percent.
+#[derive(Clone, Copy, PartialEq, Data, Debug, Lens)]
+pub(crate) struct Mix {
+    pub(crate) red: f64,
+    pub(crate) green: f64,
+    pub(crate) blue: f64,
+    /// Added to the output, 100 is white.
+    pub(crate) constant: f64,
+}
+
+impl Mix {
+    const fn new(red: f64, green: f64, blue: f64) -> Self {
+        Self {
+            red,
+            green,
+            blue,
+            constant: 0.0,
+        }
+    }
+
+    fn apply(&self, r: f32, g: f32, b: f32) -> f32 {
+        ((self.red as f32 * r + self.green as f32 * g + self.blue as f32 * b) / 100.0)
+            + self.constant as f32 / 100.0
+    }
+}
+
+/// Output channel the mixer dialog is editing.
+#[derive(Clone, Copy, PartialEq, Eq, Data, Debug)]
+pub(crate) enum MixerOutput {
+    Red,
+    Green,
+    Blue,
+    /// Used for all channels in the monochrome mode.
+    Gray,
+}
+
+#[derive(Clone, Copy, PartialEq, Data, Debug, Lens)]
+pub(crate) struct ChannelMixerParams {
+    pub(crate) red: Mix,
+    pub(crate) green: Mix,
+    pub(crate) blue: Mix,
+    pub(crate) gray: Mix,
+    /// Gives a grayscale image made by the gray mix.
+    pub(crate) monochrome: bool,
+}
+
+impl ChannelMixerParams {
+    pub(crate) const IDENTITY: ChannelMixerParams = ChannelMixerParams {
+        red: Mix::new(100.0, 0.0, 0.0),
+        green: Mix::new(0.0, 100.0, 0.0),
+        blue: Mix::new(0.0, 0.0, 100.0),
+        gray: Mix::new(40.0, 40.0, 20.0),
+        monochrome: false,
+    };
+
+    pub(crate) fn get(&self, output: MixerOutput) -> &Mix {
+        match output {
+            MixerOutput::Red => &self.red,
+            MixerOutput::Green => &self.green,
+            MixerOutput::Blue => &self.blue,
+            MixerOutput::Gray => &self.gray,
+        }
+    }
+
+    pub(crate) fn get_mut(&mut self, output: MixerOutput) -> &mut Mix {
+        match output {
+            MixerOutput::Red => &mut self.red,
+            MixerOutput::Green => &mut self.green,
+            MixerOutput::Blue => &mut self.blue,
+            MixerOutput::Gray => &mut self.gray,
+        }
+    }
+
+    pub(crate) fn apply(&self, channels: [View<'_, u8>; 3]) -> [Matrix<u8>; 3] {
+        ops::map_pixels(channels, |r, g, b| {
+            if self.monochrome {
+                let gray = self.gray.apply(r, g, b);
+                return (gray, gray, gray);
+            }
+            (
+                self.red.apply(r, g, b),
+                self.green.apply(r, g, b),
+                self.blue.apply(r, g, b),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Applies the mixer to one row of pixels.
+    fn mix(params: &ChannelMixerParams, pixels: &[[u8; 3]]) -> Vec<[u8; 3]> {
+        let mut channels = [0, 1, 2].map(|_| Matrix::new(pixels.len() as u32, 1));
+        for (x, pixel) in pixels.iter().enumerate() {
+            for (channel, &value) in channels.iter_mut().zip(pixel.iter()) {
+                channel.set(x as u32, 0, value);
+            }
+        }
+        let [r, g, b] = &channels;
+        let [r, g, b] = params.apply([r.as_view(), g.as_view(), b.as_view()]);
+        (0..pixels.len() as u32)
+            .map(|x| [r.get(x, 0), g.get(x, 0), b.get(x, 0)])
+            .collect()
+    }
+
+    const PIXELS: [[u8; 3]; 4] = [[0, 0, 0], [255, 255, 255], [200, 40, 10], [17, 128, 240]];
+
+    #[test]
+    fn identity_keeps_colors() {
+        assert_eq!(mix(&ChannelMixerParams::IDENTITY, &PIXELS), PIXELS);
+    }
+
+    #[test]
+    fn swaps_channels() {
+        let params = ChannelMixerParams {
+            red: Mix::new(0.0, 0.0, 100.0),
+            blue: Mix::new(100.0, 0.0, 0.0),
+            ..ChannelMixerParams::IDENTITY
+        };
+        assert_eq!(mix(&params, &PIXELS), PIXELS.map(|[r, g, b]| [b, g, r]));
+    }
+
+    #[test]
+    fn monochrome_uses_the_gray_mix() {
+        let params = ChannelMixerParams {
+            gray: Mix {
+                constant: 20.0,
+                ..Mix::new(0.0, 50.0, 0.0)
+            },
+            monochrome: true,
+            ..ChannelMixerParams::IDENTITY
+        };
+        assert_eq!(mix(&params, &[[255, 100, 0]]), [[101, 101, 101]]);
+    }
+}
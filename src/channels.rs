@@ -143,6 +143,20 @@ impl<'a, T> View<'a, T> {
         }
     }
 
+    /// Copies a rectangular part of the view into a new matrix.
+    pub(crate) fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Matrix<T>
+    where
+        T: Copy + Default,
+    {
+        let mut matrix = Matrix::new(width, height);
+        for dy in 0..height {
+            for dx in 0..width {
+                matrix.set(dx, dy, self.get(x + dx, y + dy));
+            }
+        }
+        matrix
+    }
+
     pub(crate) fn to_matrix(&self) -> Matrix<T>
     where
         T: Copy + Default,
@@ -0,0 +1,123 @@
+use std::io::Cursor;
+
+use druid::{Application, ClipboardFormat, FormatId};
+
+use crate::channels::Matrix;
+use crate::contours::{bounding_box, BoundingBox};
+use crate::image_buffer::ImageBuffer;
+use crate::state::ChannelKind;
+use crate::utils::blend;
+
+#[cfg(target_os = "macos")]
+const PNG: FormatId = "public.png";
+#[cfg(target_os = "windows")]
+const PNG: FormatId = "PNG";
+#[cfg(not(any(target_os = "macos", target_os = "windows")))]
+const PNG: FormatId = "image/png";
+
+/// Pixels taken from a layer along with the position they were taken from.
+#[derive(Clone)]
+pub(crate) struct Clip {
+    pub(crate) image: ImageBuffer,
+    pub(crate) x: i32,
+    pub(crate) y: i32,
+}
+
+/// Internal clipboard, mirrored to the system one as PNG.
+#[derive(Default)]
+pub(crate) struct Clipboard {
+    clip: Option<Clip>,
+    png: Option<Vec<u8>>,
+}
+
+impl Clipboard {
+    pub(crate) fn put(&mut self, clip: Clip) {
+        self.png = put_system(&clip);
+        self.clip = Some(clip);
+    }
+
+    /// An image put on the system clipboard by another application takes precedence
+    /// over the internal clip, which remembers where the pixels came from.
+    pub(crate) fn get(&self) -> Option<Clip> {
+        match get_system() {
+            Some(png) if self.png.as_ref() != Some(&png) => {
+                let image = image::load_from_memory(&png).ok()?;
+                Some(Clip {
+                    image: ImageBuffer::from_dynamic_image(image),
+                    x: 0,
+                    y: 0,
+                })
+            }
+            _ => self.clip.clone(),
+        }
+    }
+}
+
+/// Copies the pixels within the bounding box of the selection, partially selected
+/// pixels become partially transparent. Without a selection the whole layer is copied.
+pub(crate) fn copy(image: &ImageBuffer) -> Clip {
+    let (width, height) = image.size();
+    let selected = bounding_box(image.channel(ChannelKind::Selection));
+    let bounds = selected.unwrap_or(BoundingBox {
+        x: 0,
+        y: 0,
+        width,
+        height,
+    });
+
+    let crop = |kind| {
+        image
+            .channel(kind)
+            .crop(bounds.x, bounds.y, bounds.width, bounds.height)
+    };
+
+    let mut alpha = crop(ChannelKind::Alpha);
+    if selected.is_some() {
+        let selection = image.channel(ChannelKind::Selection);
+        for y in 0..bounds.height {
+            for x in 0..bounds.width {
+                let mask = selection.get(bounds.x + x, bounds.y + y);
+                alpha.set(x, y, blend(0, alpha.get(x, y), mask));
+            }
+        }
+    }
+
+    Clip {
+        image: ImageBuffer::from_channels([
+            crop(ChannelKind::Red),
+            crop(ChannelKind::Green),
+            crop(ChannelKind::Blue),
+            alpha,
+        ]),
+        x: bounds.x as i32,
+        y: bounds.y as i32,
+    }
+}
+
+/// Copies the selected pixels and leaves transparency behind.
+pub(crate) fn cut(image: &mut ImageBuffer) -> Clip {
+    let clip = copy(image);
+    let (width, height) = image.size();
+    image.apply_masked(ChannelKind::Alpha, Matrix::new(width, height));
+    clip
+}
+
+fn put_system(clip: &Clip) -> Option<Vec<u8>> {
+    let (width, height) = clip.image.size();
+    let rgba = image::RgbaImage::from_raw(width, height, clip.image.to_rgba())?;
+    let mut png = Vec::new();
+    image::DynamicImage::ImageRgba8(rgba)
+        .write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)
+        .ok()?;
+
+    Application::global()
+        .clipboard()
+        .put_formats(&[ClipboardFormat::new(PNG, png.clone())]);
+    Some(png)
+}
+
+fn get_system() -> Option<Vec<u8>> {
+    let clipboard = Application::global().clipboard();
+    let format = clipboard.preferred_format(&[PNG])?;
+    clipboard.get_format(format)
+}
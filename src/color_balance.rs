@@ -0,0 +1,192 @@
+use druid::{Data, Lens};
+
+use crate::channels::{Matrix, View};
+use crate::ops;
+
+/// Shift of the colors towards red, green and blue (positive) or towards
+/// cyan, magenta and yellow (negative), in percent from -100 to 100.
+#[derive(Clone, Copy, PartialEq, Data, Debug, Lens)]
+pub(crate) struct Balance {
+    pub(crate) cyan_red: f64,
+    pub(crate) magenta_green: f64,
+    pub(crate) yellow_blue: f64,
+}
+
+impl Balance {
+    pub(crate) const NONE: Balance = Balance {
+        cyan_red: 0.0,
+        magenta_green: 0.0,
+        yellow_blue: 0.0,
+    };
+
+    fn shifts(&self) -> [f32; 3] {
+        [self.cyan_red, self.magenta_green, self.yellow_blue].map(|amount| amount as f32 / 100.0)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Data, Debug)]
+pub(crate) enum TonalRange {
+    Shadows,
+    Midtones,
+    Highlights,
+}
+
+/// A full shift moves a channel by this fraction of the range.
+const MAX_SHIFT: f32 = 0.25;
+
+#[derive(Clone, Copy, PartialEq, Data, Debug, Lens)]
+pub(crate) struct ColorBalanceParams {
+    pub(crate) shadows: Balance,
+    pub(crate) midtones: Balance,
+    pub(crate) highlights: Balance,
+    pub(crate) preserve_luminosity: bool,
+}
+
+impl ColorBalanceParams {
+    pub(crate) const NONE: ColorBalanceParams = ColorBalanceParams {
+        shadows: Balance::NONE,
+        midtones: Balance::NONE,
+        highlights: Balance::NONE,
+        preserve_luminosity: true,
+    };
+
+    pub(crate) fn get(&self, range: TonalRange) -> &Balance {
+        match range {
+            TonalRange::Shadows => &self.shadows,
+            TonalRange::Midtones => &self.midtones,
+            TonalRange::Highlights => &self.highlights,
+        }
+    }
+
+    pub(crate) fn get_mut(&mut self, range: TonalRange) -> &mut Balance {
+        match range {
+            TonalRange::Shadows => &mut self.shadows,
+            TonalRange::Midtones => &mut self.midtones,
+            TonalRange::Highlights => &mut self.highlights,
+        }
+    }
+
+    pub(crate) fn apply(&self, channels: [View<'_, u8>; 3]) -> [Matrix<u8>; 3] {
+        let [shadows, midtones, highlights] =
+            [self.shadows, self.midtones, self.highlights].map(|balance| balance.shifts());
+        ops::map_pixels(channels, |r, g, b| {
+            let luma = ops::luma(r, g, b);
+            // Shadows fade out towards the middle gray, highlights fade in after it.
+            let shadow = (1.0 - 2.0 * luma).max(0.0);
+            let highlight = (2.0 * luma - 1.0).max(0.0);
+            let midtone = 1.0 - shadow - highlight;
+
+            let mut color = [r, g, b];
+            for (i, value) in color.iter_mut().enumerate() {
+                let shift = shadow * shadows[i] + midtone * midtones[i] + highlight * highlights[i];
+                *value += shift * MAX_SHIFT;
+            }
+            if self.preserve_luminosity {
+                color = keep_luma(color, luma);
+            }
+            (color[0], color[1], color[2])
+        })
+    }
+}
+
+/// Moves all channels by the same amount so the color gets the given luma back.
+fn keep_luma(color: [f32; 3], luma: f32) -> [f32; 3] {
+    let difference = luma - ops::luma(color[0], color[1], color[2]);
+    color.map(|value| value + difference)
+}
+
+/// Colors of the photo filter.
+#[derive(Clone, Copy, PartialEq, Eq, Data, Debug)]
+pub(crate) enum FilterColor {
+    Warming,
+    Cooling,
+    Sepia,
+    /// The current brush color.
+    Brush,
+}
+
+impl FilterColor {
+    /// Color of the filter, `brush` is used by `FilterColor::Brush`.
+    pub(crate) fn rgb(self, brush: [u8; 3]) -> [u8; 3] {
+        match self {
+            FilterColor::Warming => [236, 138, 0],
+            FilterColor::Cooling => [0, 109, 255],
+            FilterColor::Sepia => [172, 122, 51],
+            FilterColor::Brush => brush,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Data, Debug, Lens)]
+pub(crate) struct PhotoFilterParams {
+    pub(crate) color: FilterColor,
+    /// In percent.
+    pub(crate) density: f64,
+    pub(crate) preserve_luminosity: bool,
+}
+
+/// Looks through a colored glass: mixes the colors multiplied by the filter color
+/// into the image, optionally keeping the brightness of every pixel.
+pub(crate) fn photo_filter(
+    channels: [View<'_, u8>; 3],
+    color: [u8; 3],
+    density: f64,
+    preserve_luminosity: bool,
+) -> [Matrix<u8>; 3] {
+    let filter = color.map(|value| value as f32 / 255.0);
+    let density = (density / 100.0) as f32;
+    ops::map_pixels(channels, |r, g, b| {
+        let original = [r, g, b];
+        let mut color = [0.0; 3];
+        for (i, value) in color.iter_mut().enumerate() {
+            let filtered = original[i] * filter[i];
+            *value = original[i] + (filtered - original[i]) * density;
+        }
+        if preserve_luminosity {
+            color = keep_luma(color, ops::luma(r, g, b));
+        }
+        (color[0], color[1], color[2])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keep_luma_restores_the_luma() {
+        for &(color, luma) in [
+            ([0.9, 0.2, 0.1], 0.4),
+            ([0.0, 0.0, 0.0], 0.25),
+            ([0.3, 0.6, 0.9], 0.55),
+        ]
+        .iter()
+        {
+            let [r, g, b] = keep_luma(color, luma);
+            assert!((ops::luma(r, g, b) - luma).abs() < 1e-6);
+            // The differences between the channels stay the same.
+            assert!(((r - g) - (color[0] - color[1])).abs() < 1e-6);
+            assert!(((g - b) - (color[1] - color[2])).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn preserve_luminosity_keeps_the_brightness() {
+        let mut channels = [0, 1, 2].map(|_| Matrix::new(1, 1));
+        for (channel, &value) in channels.iter_mut().zip([120u8, 100, 80].iter()) {
+            channel.set(0, 0, value);
+        }
+        let params = ColorBalanceParams {
+            midtones: Balance {
+                cyan_red: 60.0,
+                ..Balance::NONE
+            },
+            ..ColorBalanceParams::NONE
+        };
+        let [r, g, b] = &channels;
+        let out = params.apply([r.as_view(), g.as_view(), b.as_view()]);
+        let [r, g, b] = [&out[0], &out[1], &out[2]].map(|channel| channel.get(0, 0) as f32 / 255.0);
+        assert!(r > 120.0 / 255.0 + 0.05);
+        assert!((ops::luma(r, g, b) - ops::luma(120.0, 100.0, 80.0) / 255.0).abs() < 2.0 / 255.0);
+    }
+}
@@ -0,0 +1,38 @@
+use druid::{Selector, UnitPoint};
+
+use crate::dialogs::Dialog;
+use crate::edges::EdgeParams;
+use crate::effects::Effect;
+use crate::resample::Filter;
+use crate::selection::Refinement;
+use crate::state::ToolKind;
+use crate::transform::{Axis, Scope};
+
+pub(crate) const CUT: Selector = Selector::new("bbe.cut");
+pub(crate) const COPY: Selector = Selector::new("bbe.copy");
+pub(crate) const PASTE: Selector = Selector::new("bbe.paste");
+pub(crate) const PASTE_INTO: Selector = Selector::new("bbe.paste-into");
+pub(crate) const PASTE_AS_LAYER: Selector = Selector::new("bbe.paste-as-layer");
+
+pub(crate) const SELECT_ALL: Selector = Selector::new("bbe.select-all");
+pub(crate) const DESELECT: Selector = Selector::new("bbe.deselect");
+pub(crate) const INVERT_SELECTION: Selector = Selector::new("bbe.invert-selection");
+pub(crate) const REFINE_SELECTION: Selector<(Refinement, f64)> =
+    Selector::new("bbe.refine-selection");
+
+pub(crate) const CROP: Selector = Selector::new("bbe.crop");
+pub(crate) const COMMIT_TRANSFORM: Selector = Selector::new("bbe.commit-transform");
+pub(crate) const CANCEL_TRANSFORM: Selector = Selector::new("bbe.cancel-transform");
+/// Tool that was active before the user picked another one.
+pub(crate) const TOOL_CHANGED: Selector<ToolKind> = Selector::new("bbe.tool-changed");
+pub(crate) const RESIZE_CANVAS: Selector<(u32, u32, UnitPoint, [u8; 4])> =
+    Selector::new("bbe.resize-canvas");
+pub(crate) const RESIZE_IMAGE: Selector<(u32, u32, Filter)> = Selector::new("bbe.resize-image");
+/// Clockwise angle in degrees.
+pub(crate) const ROTATE: Selector<(f64, Filter, Scope)> = Selector::new("bbe.rotate");
+pub(crate) const FLIP: Selector<(Axis, Scope)> = Selector::new("bbe.flip");
+
+pub(crate) const APPLY_EFFECT: Selector<Effect> = Selector::new("bbe.apply-effect");
+pub(crate) const DETECT_EDGES: Selector<EdgeParams> = Selector::new("bbe.detect-edges");
+
+pub(crate) const SHOW_DIALOG: Selector<Dialog> = Selector::new("bbe.show-dialog");
@@ -22,6 +22,40 @@ impl<T> Point<T> {
     }
 }
 
+/// Axis-aligned rectangle in pixel coordinates.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct BoundingBox {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Smallest rectangle enclosing all nonzero pixels, `None` if there are none.
+pub(crate) fn bounding_box(source: View<'_, u8>) -> Option<BoundingBox> {
+    let mut min = Point::new(u32::MAX, u32::MAX);
+    let mut max = Point::new(0, 0);
+    for y in 0..source.height() {
+        for x in 0..source.width() {
+            if source.get(x, y) != 0 {
+                min = Point::new(min.x.min(x), min.y.min(y));
+                max = Point::new(max.x.max(x), max.y.max(y));
+            }
+        }
+    }
+
+    if min.x > max.x {
+        return None;
+    }
+
+    Some(BoundingBox {
+        x: min.x,
+        y: min.y,
+        width: max.x - min.x + 1,
+        height: max.y - min.y + 1,
+    })
+}
+
 #[allow(unused)]
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 enum BorderType {
@@ -211,18 +245,16 @@ fn trace_border(
 //     contours
 // }
 
-#[allow(unused)]
+/// Traces borders of the regions with values of at least 128, the points are reported
+/// in the coordinates of `source`.
 pub(crate) fn find_contours(source: View<'_, u8>) -> Vec<Contour> {
-    let s = std::time::Instant::now();
-
     // some kind of 1 pixel border is required to avoid out of area access
     let mut mat = Matrix::new(source.width() + 2, source.height() + 2);
-    for y in 1..source.height() {
-        for x in 1..source.width() {
-            mat.set(x, y, (source.get(x, y) == 255) as i8);
+    for y in 0..source.height() {
+        for x in 0..source.width() {
+            mat.set(x + 1, y + 1, (source.get(x, y) >= 128) as i8);
         }
     }
-    dbg!(s.elapsed());
 
     let mut contours = vec![];
 
@@ -272,8 +304,7 @@ pub(crate) fn find_contours(source: View<'_, u8>) -> Vec<Contour> {
                 match first_nonzero {
                     Some(i1j1) => {
                         trace_border(Point::new(x, y), i1j1, &mut mat, |point, examined, mat| {
-                            points.push(Point::new(point.x - 1 + 1, point.y - 1 + 1));
-                            // dbg!(point.x, point.y);
+                            points.push(Point::new(point.x - 1, point.y - 1));
                             // (3.4)
                             if examined && mat.get(point.x + 1, point.y) == 0 {
                                 // (a)
@@ -286,7 +317,7 @@ pub(crate) fn find_contours(source: View<'_, u8>) -> Vec<Contour> {
                     }
                     None => {
                         // got single pixel
-                        points.push(Point::new(x - 1 + 1, y - 1 + 1));
+                        points.push(Point::new(x - 1, y - 1));
                         mat.set(x, y, -2);
                     }
                 }
@@ -301,7 +332,6 @@ pub(crate) fn find_contours(source: View<'_, u8>) -> Vec<Contour> {
             }
         }
     }
-    dbg!(s.elapsed());
     contours
 }
 
@@ -0,0 +1,259 @@
+use std::sync::Arc;
+
+use druid::Data;
+
+use crate::channels::Matrix;
+
+/// How samples outside of the image are obtained.
+#[derive(Clone, Copy, PartialEq, Eq, Data, Debug)]
+pub(crate) enum EdgeMode {
+    /// Repeat the nearest edge pixel.
+    Clamp,
+    /// Continue from the opposite edge.
+    Wrap,
+    /// Reflect the image across the edge.
+    Mirror,
+    /// Treat everything outside as zero.
+    Zero,
+}
+
+impl EdgeMode {
+    /// Index of the pixel sampled at `i`, or None when it reads as zero.
+    fn resolve(self, i: i64, len: i64) -> Option<i64> {
+        if (0..len).contains(&i) {
+            return Some(i);
+        }
+        match self {
+            EdgeMode::Clamp => Some(i.max(0).min(len - 1)),
+            EdgeMode::Wrap => Some(i.rem_euclid(len)),
+            EdgeMode::Mirror => {
+                // The edge pixel is not repeated: -1 maps to 1 and len to len - 2.
+                let period = 2 * (len - 1);
+                if period == 0 {
+                    return Some(0);
+                }
+                let i = i.rem_euclid(period);
+                Some(if i < len { i } else { period - i })
+            }
+            EdgeMode::Zero => None,
+        }
+    }
+}
+
+/// Convolution kernel with the weights stored row by row. Its center lies at
+/// (width / 2, height / 2). Results are divided by `divisor` and shifted by `offset`.
+#[derive(Clone, PartialEq, Data, Debug)]
+pub(crate) struct Kernel {
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    pub(crate) weights: Arc<Vec<f32>>,
+    pub(crate) divisor: f32,
+    pub(crate) offset: f32,
+}
+
+impl Kernel {
+    /// Kernel dividing by the sum of its weights, so the brightness is kept.
+    pub(crate) fn new(width: u32, height: u32, weights: Vec<f32>) -> Self {
+        assert_eq!(weights.len(), width as usize * height as usize);
+        let sum: f32 = weights.iter().sum();
+        Self {
+            width,
+            height,
+            weights: Arc::new(weights),
+            divisor: if sum == 0.0 { 1.0 } else { sum },
+            offset: 0.0,
+        }
+    }
+
+    pub(crate) fn identity() -> Self {
+        Self::new(1, 1, vec![1.0])
+    }
+
+    /// Averages a row of pixels reaching `radius` to each side. Running it on
+    /// the columns afterwards gives a box blur.
+    pub(crate) fn box_blur(radius: u32) -> Self {
+        let size = 2 * radius + 1;
+        Self::new(size, 1, vec![1.0; size as usize])
+    }
+
+    /// Whether flat areas come out as they were, as with blurring or sharpening.
+    pub(crate) fn keeps_flat_areas(&self) -> bool {
+        let sum: f32 = self.weights.iter().sum();
+        self.offset == 0.0 && (sum - self.divisor).abs() <= 1e-4 * self.divisor.abs()
+    }
+
+    /// Relief lit from the top left, flat areas become middle gray.
+    pub(crate) fn emboss() -> Self {
+        Self {
+            offset: 128.0,
+            ..Self::new(3, 3, vec![-1.0, -1.0, 0.0, -1.0, 0.0, 1.0, 0.0, 1.0, 1.0])
+        }
+    }
+
+    pub(crate) fn edge_enhance() -> Self {
+        Self::new(
+            3,
+            3,
+            vec![-1.0, -1.0, -1.0, -1.0, 10.0, -1.0, -1.0, -1.0, -1.0],
+        )
+    }
+
+    /// Same kernel flipped along its diagonal.
+    pub(crate) fn transposed(&self) -> Self {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let weights = (0..width)
+            .flat_map(|x| (0..height).map(move |y| (x, y)))
+            .map(|(x, y)| self.weights[y * width + x])
+            .collect();
+        Self {
+            width: self.height,
+            height: self.width,
+            weights: Arc::new(weights),
+            ..*self
+        }
+    }
+
+    /// Reads rows of numbers separated by whitespace or commas, one row per line
+    /// or separated by semicolons. A zero divisor divides by the sum of the weights.
+    pub(crate) fn parse(text: &str, divisor: f32, offset: f32) -> Result<Self, String> {
+        let rows = text
+            .split(|c| c == '\n' || c == ';')
+            .map(|row| {
+                row.split(|c: char| c.is_whitespace() || c == ',')
+                    .filter(|value| !value.is_empty())
+                    .map(|value| {
+                        value
+                            .parse::<f32>()
+                            .map_err(|_| format!("\"{}\" is not a number", value))
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .filter(|row| !matches!(row, Ok(row) if row.is_empty()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let width = rows.first().map_or(0, |row| row.len());
+        if width == 0 {
+            return Err("The kernel is empty".to_string());
+        }
+        if rows.iter().any(|row| row.len() != width) {
+            return Err("All rows need the same number of values".to_string());
+        }
+
+        let mut kernel = Self::new(width as u32, rows.len() as u32, rows.concat());
+        if divisor != 0.0 {
+            kernel.divisor = divisor;
+        }
+        kernel.offset = offset;
+        Ok(kernel)
+    }
+}
+
+/// Convolves the plane with the kernel.
+pub(crate) fn convolve(src: &Matrix<f32>, kernel: &Kernel, edges: EdgeMode) -> Matrix<f32> {
+    let width = src.width() as i64;
+    let height = src.height() as i64;
+    let (cx, cy) = ((kernel.width / 2) as i64, (kernel.height / 2) as i64);
+
+    let mut out = Matrix::new(src.width(), src.height());
+    if width == 0 || height == 0 {
+        return out;
+    }
+
+    for y in 0..height {
+        for x in 0..width {
+            let mut acc = 0.0;
+            for ky in 0..kernel.height as i64 {
+                let sy = match edges.resolve(y + ky - cy, height) {
+                    Some(sy) => sy,
+                    None => continue,
+                };
+                for kx in 0..kernel.width as i64 {
+                    let sx = match edges.resolve(x + kx - cx, width) {
+                        Some(sx) => sx,
+                        None => continue,
+                    };
+                    let weight = kernel.weights[(ky * kernel.width as i64 + kx) as usize];
+                    acc += weight * src.get(sx as u32, sy as u32);
+                }
+            }
+            out.set(x as u32, y as u32, acc / kernel.divisor + kernel.offset);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_inside_keeps_the_index() {
+        for &mode in &[
+            EdgeMode::Clamp,
+            EdgeMode::Wrap,
+            EdgeMode::Mirror,
+            EdgeMode::Zero,
+        ] {
+            assert_eq!(mode.resolve(0, 5), Some(0));
+            assert_eq!(mode.resolve(4, 5), Some(4));
+        }
+    }
+
+    #[test]
+    fn resolve_outside() {
+        assert_eq!(EdgeMode::Clamp.resolve(-1, 5), Some(0));
+        assert_eq!(EdgeMode::Clamp.resolve(5, 5), Some(4));
+        assert_eq!(EdgeMode::Wrap.resolve(-1, 5), Some(4));
+        assert_eq!(EdgeMode::Wrap.resolve(5, 5), Some(0));
+        assert_eq!(EdgeMode::Mirror.resolve(-1, 5), Some(1));
+        assert_eq!(EdgeMode::Mirror.resolve(5, 5), Some(3));
+        assert_eq!(EdgeMode::Mirror.resolve(-2, 5), Some(2));
+        assert_eq!(EdgeMode::Mirror.resolve(0, 1), Some(0));
+        assert_eq!(EdgeMode::Mirror.resolve(-1, 1), Some(0));
+        assert_eq!(EdgeMode::Zero.resolve(-1, 5), None);
+        assert_eq!(EdgeMode::Zero.resolve(5, 5), None);
+    }
+
+    #[test]
+    fn parse_accepts_rows_separated_by_lines_or_semicolons() {
+        let kernel = Kernel::parse("1 2 1; 2 4 2\n1,2,1\n", 0.0, 0.0).unwrap();
+        assert_eq!((kernel.width, kernel.height), (3, 3));
+        assert_eq!(
+            *kernel.weights,
+            vec![1.0, 2.0, 1.0, 2.0, 4.0, 2.0, 1.0, 2.0, 1.0]
+        );
+        assert_eq!(kernel.divisor, 16.0);
+        assert_eq!(kernel.offset, 0.0);
+    }
+
+    #[test]
+    fn parse_uses_the_given_divisor_and_offset() {
+        let kernel = Kernel::parse("-1 0 1", 2.0, 128.0).unwrap();
+        assert_eq!((kernel.width, kernel.height), (3, 1));
+        assert_eq!(kernel.divisor, 2.0);
+        assert_eq!(kernel.offset, 128.0);
+
+        // Weights summing to zero are not divided.
+        assert_eq!(Kernel::parse("-1 0 1", 0.0, 0.0).unwrap().divisor, 1.0);
+    }
+
+    #[test]
+    fn parse_rejects_bad_kernels() {
+        assert!(Kernel::parse("", 0.0, 0.0).is_err());
+        assert!(Kernel::parse("1 2\n3", 0.0, 0.0).is_err());
+        assert!(Kernel::parse("1 a", 0.0, 0.0).is_err());
+    }
+
+    #[test]
+    fn kernels_that_keep_flat_areas() {
+        assert!(Kernel::box_blur(2).keeps_flat_areas());
+        assert!(Kernel::edge_enhance().keeps_flat_areas());
+        assert!(!Kernel::emboss().keeps_flat_areas());
+        assert!(!Kernel::parse("-1 0 1", 0.0, 0.0)
+            .unwrap()
+            .keeps_flat_areas());
+        assert!(!Kernel::parse("1 1 1", 0.0, 10.0)
+            .unwrap()
+            .keeps_flat_areas());
+    }
+}
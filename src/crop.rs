@@ -0,0 +1,155 @@
+use druid::{Data, Point, Rect, Vec2};
+
+/// Shape constraint of the crop rectangle.
+#[derive(Clone, Copy, PartialEq, Eq, Data, Debug)]
+pub(crate) enum CropAspect {
+    Free,
+    /// Same proportions as the document.
+    Original,
+    /// Width to height.
+    Ratio(u32, u32),
+    /// Exact size in pixels taken from the tool options.
+    FixedSize,
+}
+
+impl CropAspect {
+    /// Width divided by height, if the rectangle has to keep it.
+    pub(crate) fn ratio(self, document: (u32, u32), fixed: (u32, u32)) -> Option<f64> {
+        let (width, height) = match self {
+            CropAspect::Free => return None,
+            CropAspect::Original => document,
+            CropAspect::Ratio(width, height) => (width, height),
+            CropAspect::FixedSize => fixed,
+        };
+        if width == 0 || height == 0 {
+            return None;
+        }
+        Some(width as f64 / height as f64)
+    }
+}
+
+/// Composition guides drawn inside the crop rectangle.
+#[derive(Clone, Copy, PartialEq, Eq, Data, Debug)]
+pub(crate) enum CropGuide {
+    None,
+    RuleOfThirds,
+    GoldenRatio,
+}
+
+impl CropGuide {
+    /// Positions of the guide lines as fractions of the rectangle size.
+    pub(crate) fn fractions(self) -> &'static [f64] {
+        match self {
+            CropGuide::None => &[],
+            CropGuide::RuleOfThirds => &[1.0 / 3.0, 2.0 / 3.0],
+            // 1/phi^2 and 1/phi
+            CropGuide::GoldenRatio => &[0.381_966, 0.618_034],
+        }
+    }
+}
+
+/// Which edges of the crop rectangle follow the pointer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Edge {
+    Min,
+    Max,
+    None,
+}
+
+/// Grip on the outline of the crop rectangle, described by its horizontal and vertical edge.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) struct Handle {
+    pub(crate) x: Edge,
+    pub(crate) y: Edge,
+}
+
+impl Handle {
+    pub(crate) const ALL: [Handle; 8] = [
+        Handle::new(Edge::Min, Edge::Min),
+        Handle::new(Edge::None, Edge::Min),
+        Handle::new(Edge::Max, Edge::Min),
+        Handle::new(Edge::Max, Edge::None),
+        Handle::new(Edge::Max, Edge::Max),
+        Handle::new(Edge::None, Edge::Max),
+        Handle::new(Edge::Min, Edge::Max),
+        Handle::new(Edge::Min, Edge::None),
+    ];
+
+    pub(crate) const CORNERS: [Handle; 4] = [
+        Handle::new(Edge::Min, Edge::Min),
+        Handle::new(Edge::Max, Edge::Min),
+        Handle::new(Edge::Max, Edge::Max),
+        Handle::new(Edge::Min, Edge::Max),
+    ];
+
+    const fn new(x: Edge, y: Edge) -> Self {
+        Self { x, y }
+    }
+
+    pub(crate) fn is_corner(self) -> bool {
+        self.x != Edge::None && self.y != Edge::None
+    }
+
+    pub(crate) fn position(self, rect: Rect) -> Point {
+        let pick = |edge: Edge, min: f64, max: f64| match edge {
+            Edge::Min => min,
+            Edge::Max => max,
+            Edge::None => (min + max) / 2.0,
+        };
+        Point::new(
+            pick(self.x, rect.x0, rect.x1),
+            pick(self.y, rect.y0, rect.y1),
+        )
+    }
+
+    /// Corner that stays in place while this one is dragged.
+    pub(crate) fn opposite(self, rect: Rect) -> Point {
+        let flip = |edge: Edge| match edge {
+            Edge::Min => Edge::Max,
+            Edge::Max => Edge::Min,
+            Edge::None => Edge::None,
+        };
+        Handle::new(flip(self.x), flip(self.y)).position(rect)
+    }
+
+    /// Moves the edges of `rect` picked by the handle to `pos`.
+    pub(crate) fn drag(self, rect: Rect, pos: Point) -> Rect {
+        let mut rect = rect;
+        match self.x {
+            Edge::Min => rect.x0 = pos.x,
+            Edge::Max => rect.x1 = pos.x,
+            Edge::None => (),
+        }
+        match self.y {
+            Edge::Min => rect.y0 = pos.y,
+            Edge::Max => rect.y1 = pos.y,
+            Edge::None => (),
+        }
+        rect.abs()
+    }
+}
+
+/// Rectangle spanned from `anchor` towards `pos`, shrunk along one axis to keep `ratio`.
+pub(crate) fn constrain(anchor: Point, pos: Point, ratio: f64) -> Rect {
+    let dx = pos.x - anchor.x;
+    let dy = pos.y - anchor.y;
+    let (dx, dy) = if dx.abs() / ratio < dy.abs() {
+        (dx, dx.abs() / ratio * dy.signum())
+    } else {
+        (dy.abs() * ratio * dx.signum(), dy)
+    };
+    Rect::from_points(anchor, anchor + Vec2::new(dx, dy))
+}
+
+/// Shifts `rect` so it lies within `bounds` as much as possible.
+pub(crate) fn keep_inside(rect: Rect, bounds: Rect) -> Rect {
+    let dx = (bounds.x0 - rect.x0)
+        .max(0.0)
+        .min(bounds.x1 - rect.x1)
+        .max(bounds.x0 - rect.x0);
+    let dy = (bounds.y0 - rect.y0)
+        .max(0.0)
+        .min(bounds.y1 - rect.y1)
+        .max(bounds.y0 - rect.y0);
+    rect + Vec2::new(dx, dy)
+}
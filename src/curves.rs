@@ -0,0 +1,520 @@
+use std::sync::Arc;
+
+use druid::kurbo::{BezPath, Circle, Line};
+use druid::{
+    BoxConstraints, Color, Data, Env, Event, EventCtx, LayoutCtx, Lens, LifeCycle, LifeCycleCtx,
+    PaintCtx, Point, RenderContext, Size, UpdateCtx, Widget,
+};
+
+use crate::levels::ToneChannel;
+
+/// Smallest distance between the input values of two points of a curve.
+const MIN_GAP: f64 = 1.0;
+
+/// Tone curve passing through control points, both coordinates are in the 0-255 range.
+#[derive(Clone, PartialEq, Data, Debug)]
+pub(crate) struct Curve {
+    /// Sorted by the input value, at least `MIN_GAP` apart.
+    points: Arc<Vec<Point>>,
+}
+
+impl Curve {
+    pub(crate) fn linear() -> Self {
+        Self::new(vec![Point::new(0.0, 0.0), Point::new(255.0, 255.0)])
+    }
+
+    pub(crate) fn new(mut points: Vec<Point>) -> Self {
+        points.sort_by(|a, b| a.x.partial_cmp(&b.x).unwrap());
+        points.dedup_by(|a, b| a.x == b.x);
+        Self {
+            points: Arc::new(points),
+        }
+    }
+
+    pub(crate) fn points(&self) -> &[Point] {
+        &self.points
+    }
+
+    /// Natural cubic spline through the points, flat before the first one and after the last one.
+    fn spline(&self) -> impl Fn(f64) -> f64 + '_ {
+        let points = &self.points;
+        let n = points.len();
+
+        // Second derivatives at the points, solved with the Thomas algorithm.
+        let mut second = vec![0.0; n];
+        if n > 2 {
+            let mut upper = vec![0.0; n];
+            let mut rhs = vec![0.0; n];
+            for i in 1..n - 1 {
+                let (h0, h1) = (points[i].x - points[i - 1].x, points[i + 1].x - points[i].x);
+                let slope =
+                    (points[i + 1].y - points[i].y) / h1 - (points[i].y - points[i - 1].y) / h0;
+                let diagonal = 2.0 * (h0 + h1) - h0 * upper[i - 1];
+                upper[i] = h1 / diagonal;
+                rhs[i] = (6.0 * slope - h0 * rhs[i - 1]) / diagonal;
+            }
+            for i in (1..n - 1).rev() {
+                second[i] = rhs[i] - upper[i] * second[i + 1];
+            }
+        }
+
+        move |x: f64| {
+            if n == 1 {
+                return points[0].y;
+            }
+            if x <= points[0].x {
+                return points[0].y;
+            }
+            if x >= points[n - 1].x {
+                return points[n - 1].y;
+            }
+            let i = points.iter().rposition(|p| p.x <= x).unwrap().min(n - 2);
+            let (p0, p1) = (points[i], points[i + 1]);
+            let h = p1.x - p0.x;
+            let a = (p1.x - x) / h;
+            let b = (x - p0.x) / h;
+            a * p0.y
+                + b * p1.y
+                + ((a * a * a - a) * second[i] + (b * b * b - b) * second[i + 1]) * h * h / 6.0
+        }
+    }
+
+    pub(crate) fn lut(&self) -> [u8; 256] {
+        let spline = self.spline();
+        let mut lut = [0u8; 256];
+        for (value, out) in lut.iter_mut().enumerate() {
+            *out = spline(value as f64).round().max(0.0).min(255.0) as u8;
+        }
+        lut
+    }
+
+    /// Adds a point, keeping the points sorted. Returns its index.
+    fn insert(&mut self, point: Point) -> Option<usize> {
+        if self.points.iter().any(|p| (p.x - point.x).abs() < MIN_GAP) {
+            return None;
+        }
+        let index = self
+            .points
+            .iter()
+            .position(|p| p.x > point.x)
+            .unwrap_or(self.points.len());
+        Arc::make_mut(&mut self.points).insert(index, point);
+        Some(index)
+    }
+
+    /// Moves a point, keeping it strictly between its neighbours. Without room
+    /// between them only its output value changes.
+    fn move_point(&mut self, index: usize, point: Point) {
+        let points = Arc::make_mut(&mut self.points);
+        let min = if index > 0 {
+            points[index - 1].x + MIN_GAP
+        } else {
+            0.0
+        };
+        let max = match points.get(index + 1) {
+            Some(next) => next.x - MIN_GAP,
+            None => 255.0,
+        };
+        let x = if min <= max {
+            point.x.max(min).min(max)
+        } else {
+            points[index].x
+        };
+        points[index] = Point::new(x, point.y.max(0.0).min(255.0));
+    }
+
+    fn remove(&mut self, index: usize) {
+        Arc::make_mut(&mut self.points).remove(index);
+    }
+}
+
+/// Ready made curves.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Preset {
+    Linear,
+    IncreaseContrast,
+    DecreaseContrast,
+    Lighter,
+    Darker,
+    Negative,
+}
+
+impl Preset {
+    pub(crate) const ALL: [Preset; 6] = [
+        Preset::Linear,
+        Preset::IncreaseContrast,
+        Preset::DecreaseContrast,
+        Preset::Lighter,
+        Preset::Darker,
+        Preset::Negative,
+    ];
+
+    pub(crate) fn title(self) -> &'static str {
+        match self {
+            Preset::Linear => "Linear",
+            Preset::IncreaseContrast => "More contrast",
+            Preset::DecreaseContrast => "Less contrast",
+            Preset::Lighter => "Lighter",
+            Preset::Darker => "Darker",
+            Preset::Negative => "Negative",
+        }
+    }
+
+    pub(crate) fn curve(self) -> Curve {
+        let points: &[(u8, u8)] = match self {
+            Preset::Linear => &[(0, 0), (255, 255)],
+            Preset::IncreaseContrast => &[(0, 0), (64, 48), (192, 208), (255, 255)],
+            Preset::DecreaseContrast => &[(0, 0), (64, 80), (192, 176), (255, 255)],
+            Preset::Lighter => &[(0, 0), (128, 160), (255, 255)],
+            Preset::Darker => &[(0, 0), (128, 96), (255, 255)],
+            Preset::Negative => &[(0, 255), (255, 0)],
+        };
+        Curve::new(
+            points
+                .iter()
+                .map(|&(x, y)| Point::new(x as f64, y as f64))
+                .collect(),
+        )
+    }
+}
+
+/// Curves of the individual channels, applied before the composite one.
+#[derive(Clone, PartialEq, Data, Debug, Lens)]
+pub(crate) struct CurvesParams {
+    pub(crate) composite: Curve,
+    pub(crate) red: Curve,
+    pub(crate) green: Curve,
+    pub(crate) blue: Curve,
+}
+
+impl CurvesParams {
+    pub(crate) fn linear() -> Self {
+        Self {
+            composite: Curve::linear(),
+            red: Curve::linear(),
+            green: Curve::linear(),
+            blue: Curve::linear(),
+        }
+    }
+
+    pub(crate) fn get(&self, channel: ToneChannel) -> &Curve {
+        match channel {
+            ToneChannel::Composite => &self.composite,
+            ToneChannel::Red => &self.red,
+            ToneChannel::Green => &self.green,
+            ToneChannel::Blue => &self.blue,
+        }
+    }
+
+    pub(crate) fn get_mut(&mut self, channel: ToneChannel) -> &mut Curve {
+        match channel {
+            ToneChannel::Composite => &mut self.composite,
+            ToneChannel::Red => &mut self.red,
+            ToneChannel::Green => &mut self.green,
+            ToneChannel::Blue => &mut self.blue,
+        }
+    }
+
+    /// Lookup tables of the red, green and blue channel.
+    pub(crate) fn luts(&self) -> [[u8; 256]; 3] {
+        let composite = self.composite.lut();
+        [&self.red, &self.green, &self.blue].map(|curve| curve.lut().map(|v| composite[v as usize]))
+    }
+
+    /// Text with one line per channel: its name followed by the x y coordinates of the points.
+    pub(crate) fn to_text(&self) -> String {
+        let mut text = String::new();
+        for (name, curve) in self.named() {
+            text.push_str(name);
+            for p in curve.points() {
+                text.push_str(&format!(" {} {}", p.x, p.y));
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Reads the output of `to_text`, channels that are missing stay linear.
+    pub(crate) fn parse(text: &str) -> Result<Self, String> {
+        let mut params = Self::linear();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            let mut words = line.split_whitespace();
+            let name = words.next().unwrap_or_default();
+            let channel = match name {
+                "rgb" => ToneChannel::Composite,
+                "red" => ToneChannel::Red,
+                "green" => ToneChannel::Green,
+                "blue" => ToneChannel::Blue,
+                _ => return Err(format!("Unknown channel \"{}\"", name)),
+            };
+            let values = words
+                .map(|word| {
+                    word.parse::<f64>()
+                        .ok()
+                        .filter(|value| (0.0..=255.0).contains(value))
+                        .ok_or_else(|| format!("\"{}\" is not a value between 0 and 255", word))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if values.len() < 4 || values.len() % 2 != 0 {
+                return Err(format!("The {} curve needs at least two points", name));
+            }
+            let curve = Curve::new(values.chunks(2).map(|p| Point::new(p[0], p[1])).collect());
+            let too_close = curve.points().windows(2).any(|w| w[1].x - w[0].x < MIN_GAP);
+            if too_close || curve.points().len() * 2 != values.len() {
+                return Err(format!(
+                    "The points of the {} curve need to be at least {} apart",
+                    name, MIN_GAP
+                ));
+            }
+            *params.get_mut(channel) = curve;
+        }
+        Ok(params)
+    }
+
+    fn named(&self) -> [(&'static str, &Curve); 4] {
+        [
+            ("rgb", &self.composite),
+            ("red", &self.red),
+            ("green", &self.green),
+            ("blue", &self.blue),
+        ]
+    }
+}
+
+/// Graph of a curve whose points can be dragged around. Clicking next to the
+/// points adds a new one, dragging a point out of the graph removes it.
+pub(crate) struct CurveEditor {
+    color: Color,
+    dragged: Option<usize>,
+}
+
+impl CurveEditor {
+    const POINT_RADIUS: f64 = 5.0;
+    /// How far outside of the graph a point has to be dragged to be removed.
+    const REMOVE_DISTANCE: f64 = 20.0;
+
+    pub(crate) fn new(color: Color) -> Self {
+        Self {
+            color,
+            dragged: None,
+        }
+    }
+
+    /// Graph coordinates of a point in the widget, the y axis points up.
+    fn to_curve(size: Size, pos: Point) -> Point {
+        Point::new(
+            pos.x / size.width * 255.0,
+            (1.0 - pos.y / size.height) * 255.0,
+        )
+    }
+
+    fn to_widget(size: Size, point: Point) -> Point {
+        Point::new(
+            point.x / 255.0 * size.width,
+            (1.0 - point.y / 255.0) * size.height,
+        )
+    }
+}
+
+impl Widget<Curve> for CurveEditor {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut Curve, _env: &Env) {
+        let size = ctx.size();
+        match event {
+            Event::MouseDown(e) => {
+                let hit = data
+                    .points()
+                    .iter()
+                    .map(|&p| Self::to_widget(size, p))
+                    .position(|p| p.distance(e.pos) <= Self::POINT_RADIUS * 1.5);
+                let pos = Self::to_curve(size, e.pos);
+                self.dragged = hit.or_else(|| data.insert(Point::new(pos.x.round(), pos.y)));
+                ctx.set_active(true);
+            }
+            Event::MouseMove(e) if ctx.is_active() => {
+                if let Some(index) = self.dragged {
+                    let outside = e.pos.x < -Self::REMOVE_DISTANCE
+                        || e.pos.y < -Self::REMOVE_DISTANCE
+                        || e.pos.x > size.width + Self::REMOVE_DISTANCE
+                        || e.pos.y > size.height + Self::REMOVE_DISTANCE;
+                    if outside && data.points().len() > 2 {
+                        data.remove(index);
+                        self.dragged = None;
+                    } else {
+                        let pos = Self::to_curve(size, e.pos);
+                        data.move_point(index, Point::new(pos.x.round(), pos.y));
+                    }
+                }
+            }
+            Event::MouseUp(_) if ctx.is_active() => {
+                self.dragged = None;
+                ctx.set_active(false);
+            }
+            _ => (),
+        }
+    }
+
+    fn lifecycle(
+        &mut self,
+        _ctx: &mut LifeCycleCtx,
+        _event: &LifeCycle,
+        _data: &Curve,
+        _env: &Env,
+    ) {
+    }
+
+    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &Curve, data: &Curve, _env: &Env) {
+        if !old_data.same(data) {
+            ctx.request_paint();
+        }
+    }
+
+    fn layout(
+        &mut self,
+        _ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        _data: &Curve,
+        _env: &Env,
+    ) -> Size {
+        bc.constrain(Size::new(256.0, 256.0))
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &Curve, _env: &Env) {
+        let size = ctx.size();
+        ctx.fill(size.to_rect(), &Color::WHITE);
+
+        let grid = Color::grey8(220);
+        for i in 1..4 {
+            let t = i as f64 / 4.0;
+            ctx.stroke(
+                Line::new((t * size.width, 0.0), (t * size.width, size.height)),
+                &grid,
+                1.0,
+            );
+            ctx.stroke(
+                Line::new((0.0, t * size.height), (size.width, t * size.height)),
+                &grid,
+                1.0,
+            );
+        }
+        ctx.stroke(
+            Line::new((0.0, size.height), (size.width, 0.0)),
+            &Color::grey8(180),
+            1.0,
+        );
+
+        let spline = data.spline();
+        let mut path = BezPath::new();
+        for value in 0..=255 {
+            let y = spline(value as f64).max(0.0).min(255.0);
+            let p = Self::to_widget(size, Point::new(value as f64, y));
+            if value == 0 {
+                path.move_to(p);
+            } else {
+                path.line_to(p);
+            }
+        }
+        ctx.stroke(path, &self.color, 1.5);
+
+        for &point in data.points() {
+            let circle = Circle::new(Self::to_widget(size, point), Self::POINT_RADIUS);
+            ctx.fill(circle, &Color::WHITE);
+            ctx.stroke(circle, &self.color, 1.5);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curve(points: &[(f64, f64)]) -> Curve {
+        Curve::new(points.iter().map(|&(x, y)| Point::new(x, y)).collect())
+    }
+
+    #[test]
+    fn linear_curve_is_identity() {
+        let lut = Curve::linear().lut();
+        for (value, &out) in lut.iter().enumerate() {
+            assert_eq!(out as usize, value);
+        }
+    }
+
+    #[test]
+    fn spline_passes_through_points() {
+        let curve = curve(&[(0.0, 10.0), (60.0, 120.0), (128.0, 90.0), (255.0, 240.0)]);
+        let spline = curve.spline();
+        for p in curve.points() {
+            assert!((spline(p.x) - p.y).abs() < 1e-9, "{:?}", p);
+        }
+    }
+
+    #[test]
+    fn spline_is_flat_outside_points() {
+        let curve = curve(&[(50.0, 30.0), (100.0, 200.0), (200.0, 220.0)]);
+        let lut = curve.lut();
+        assert!(lut[..=50].iter().all(|&v| v == 30));
+        assert!(lut[200..].iter().all(|&v| v == 220));
+    }
+
+    #[test]
+    fn new_sorts_and_drops_duplicates() {
+        let curve = curve(&[(255.0, 0.0), (0.0, 255.0), (255.0, 100.0)]);
+        assert_eq!(
+            curve.points(),
+            &[Point::new(0.0, 255.0), Point::new(255.0, 0.0)]
+        );
+    }
+
+    #[test]
+    fn edit_points() {
+        let mut curve = Curve::linear();
+        assert_eq!(curve.insert(Point::new(100.0, 50.0)), Some(1));
+        assert_eq!(curve.insert(Point::new(100.0, 80.0)), None);
+        assert_eq!(curve.insert(Point::new(100.5, 80.0)), None);
+        curve.move_point(1, Point::new(300.0, -10.0));
+        assert_eq!(curve.points()[1], Point::new(254.0, 0.0));
+        curve.move_point(0, Point::new(-5.0, 20.0));
+        assert_eq!(curve.points()[0], Point::new(0.0, 20.0));
+        curve.remove(1);
+        assert_eq!(curve.points().len(), 2);
+    }
+
+    #[test]
+    fn move_point_without_room_keeps_input() {
+        let mut curve = curve(&[(0.0, 0.0), (10.0, 10.0), (10.5, 20.0), (11.0, 30.0)]);
+        curve.move_point(2, Point::new(50.0, 40.0));
+        assert_eq!(curve.points()[2], Point::new(10.5, 40.0));
+    }
+
+    #[test]
+    fn parse_reads_to_text() {
+        let mut params = CurvesParams::linear();
+        params.red = curve(&[(0.0, 0.0), (64.5, 100.0), (255.0, 200.0)]);
+        params.blue = curve(&[(10.0, 255.0), (245.0, 0.0)]);
+        assert_eq!(CurvesParams::parse(&params.to_text()), Ok(params));
+    }
+
+    #[test]
+    fn parse_keeps_missing_channels_linear() {
+        let params = CurvesParams::parse("\ngreen 0 20 255 235\n").unwrap();
+        assert_eq!(params.green, curve(&[(0.0, 20.0), (255.0, 235.0)]));
+        assert_eq!(params.composite, Curve::linear());
+        assert_eq!(params.red, Curve::linear());
+        assert_eq!(params.blue, Curve::linear());
+    }
+
+    #[test]
+    fn parse_errors() {
+        for text in [
+            "alpha 0 0 255 255",
+            "rgb 0 0 255",
+            "rgb 0 0",
+            "rgb 0 0 255 256",
+            "red 0 0 x 255",
+            "rgb 0 0 10 5 10 9 255 255",
+            "rgb 0 0 10 5 10.5 9 255 255",
+        ] {
+            assert!(CurvesParams::parse(text).is_err(), "{}", text);
+        }
+    }
+}
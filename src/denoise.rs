@@ -0,0 +1,265 @@
+use crate::channels::{Matrix, View};
+use crate::convolution::{self, EdgeMode, Kernel};
+use crate::ops;
+
+/// Replaces every pixel with the median of the square reaching `radius` to each side.
+///
+/// Keeps a histogram of every column of the window and slides the histogram of
+/// the whole window along the row by adding and removing whole columns
+/// (Perreault and Hébert), so the cost does not grow with the radius.
+pub(crate) fn median(src: View<'_, u8>, radius: u32) -> Matrix<u8> {
+    let width = src.width() as i64;
+    let height = src.height() as i64;
+    let mut out = Matrix::new(src.width(), src.height());
+    if width == 0 || height == 0 {
+        return out;
+    }
+
+    let r = radius as i64;
+    let clamp_x = |x: i64| x.max(0).min(width - 1) as u32;
+    let clamp_y = |y: i64| y.max(0).min(height - 1) as u32;
+    let half = ((2 * r + 1) * (2 * r + 1) / 2) as u32;
+
+    // Column histograms of rows y - r ..= y + r.
+    let mut columns = vec![[0u32; 256]; width as usize];
+    for (x, column) in columns.iter_mut().enumerate() {
+        for y in -r..=r {
+            column[src.get(x as u32, clamp_y(y)) as usize] += 1;
+        }
+    }
+
+    for y in 0..height {
+        if y > 0 {
+            let (removed, added) = (clamp_y(y - r - 1), clamp_y(y + r));
+            for (x, column) in columns.iter_mut().enumerate() {
+                column[src.get(x as u32, removed) as usize] -= 1;
+                column[src.get(x as u32, added) as usize] += 1;
+            }
+        }
+
+        let mut window = [0u32; 256];
+        for x in -r..=r {
+            add(&mut window, &columns[clamp_x(x) as usize]);
+        }
+
+        for x in 0..width {
+            if x > 0 {
+                subtract(&mut window, &columns[clamp_x(x - r - 1) as usize]);
+                add(&mut window, &columns[clamp_x(x + r) as usize]);
+            }
+
+            let mut count = 0;
+            let median = window
+                .iter()
+                .position(|&n| {
+                    count += n;
+                    count > half
+                })
+                .unwrap_or(255);
+            out.set(x as u32, y as u32, median as u8);
+        }
+    }
+    out
+}
+
+fn add(histogram: &mut [u32; 256], other: &[u32; 256]) {
+    for (a, b) in histogram.iter_mut().zip(other.iter()) {
+        *a += b;
+    }
+}
+
+fn subtract(histogram: &mut [u32; 256], other: &[u32; 256]) {
+    for (a, b) in histogram.iter_mut().zip(other.iter()) {
+        *a -= b;
+    }
+}
+
+/// Averages the neighbourhood weighted by both the distance and the difference
+/// in color, so that edges stay sharp. The color difference is measured over
+/// all channels at once to avoid fringes.
+pub(crate) fn bilateral<const N: usize>(
+    channels: [View<'_, u8>; N],
+    sigma_space: f64,
+    sigma_range: f64,
+) -> [Matrix<u8>; N] {
+    let width = channels[0].width() as i64;
+    let height = channels[0].height() as i64;
+    let radius = (sigma_space * 2.0).ceil().max(1.0) as i64;
+
+    let spatial: Vec<f32> = (-radius..=radius)
+        .flat_map(|dy| (-radius..=radius).map(move |dx| (dx * dx + dy * dy) as f64))
+        .map(|d| (-d / (2.0 * sigma_space * sigma_space)).exp() as f32)
+        .collect();
+    // Indexed by the squared color distance.
+    let range: Vec<f32> = (0..=N * 255 * 255)
+        .map(|d| (-(d as f64) / (2.0 * sigma_range * sigma_range)).exp() as f32)
+        .collect();
+
+    let pixel = |sx: u32, sy: u32| {
+        let mut color = [0i32; N];
+        for (value, channel) in color.iter_mut().zip(channels.iter()) {
+            *value = channel.get(sx, sy) as i32;
+        }
+        color
+    };
+
+    let mut out = [(); N].map(|_| Matrix::new(width as u32, height as u32));
+    for y in 0..height {
+        for x in 0..width {
+            let center = pixel(x as u32, y as u32);
+            let mut acc = [0.0f32; N];
+            let mut total = 0.0;
+            for dy in -radius..=radius {
+                let sy = (y + dy).max(0).min(height - 1) as u32;
+                for dx in -radius..=radius {
+                    let sx = (x + dx).max(0).min(width - 1) as u32;
+                    let color = pixel(sx, sy);
+                    let distance: i32 = color
+                        .iter()
+                        .zip(center.iter())
+                        .map(|(a, b)| (a - b).pow(2))
+                        .sum();
+                    let weight = spatial[((dy + radius) * (2 * radius + 1) + dx + radius) as usize]
+                        * range[distance as usize];
+                    for (acc, &value) in acc.iter_mut().zip(color.iter()) {
+                        *acc += weight * value as f32;
+                    }
+                    total += weight;
+                }
+            }
+            for (out, &acc) in out.iter_mut().zip(acc.iter()) {
+                out.set(x as u32, y as u32, (acc / total).round() as u8);
+            }
+        }
+    }
+    out
+}
+
+/// Non-local means: averages pixels of the search window weighted by how similar
+/// the patches around them are to the patch around the pixel being denoised.
+///
+/// Works one offset of the search window at a time, so that patch distances
+/// of all pixels are box filtered at once instead of compared patch by patch.
+pub(crate) fn non_local_means<const N: usize>(
+    channels: [View<'_, u8>; N],
+    strength: f64,
+    search_radius: u32,
+    patch_radius: u32,
+) -> [Matrix<u8>; N] {
+    let width = channels[0].width();
+    let height = channels[0].height();
+    let planes = channels.map(|channel| ops::to_f32(&channel));
+    let h2 = (strength * strength).max(f64::EPSILON) as f32;
+    let patch_row = Kernel::box_blur(patch_radius);
+    let patch_column = patch_row.transposed();
+
+    let mut acc = [(); N].map(|_| Matrix::<f32>::new(width, height));
+    let mut total = Matrix::<f32>::new(width, height);
+
+    let r = search_radius as i64;
+    for dy in -r..=r {
+        for dx in -r..=r {
+            let shifted: Vec<_> = planes.iter().map(|plane| shift(plane, dx, dy)).collect();
+
+            let mut difference = Matrix::new(width, height);
+            for (i, out) in difference.as_slice_mut().iter_mut().enumerate() {
+                *out = planes
+                    .iter()
+                    .zip(shifted.iter())
+                    .map(|(plane, shifted)| (plane.as_slice()[i] - shifted.as_slice()[i]).powi(2))
+                    .sum::<f32>()
+                    / N as f32;
+            }
+            let distance = convolution::convolve(
+                &convolution::convolve(&difference, &patch_row, EdgeMode::Clamp),
+                &patch_column,
+                EdgeMode::Clamp,
+            );
+
+            for (i, &distance) in distance.as_slice().iter().enumerate() {
+                let weight = (-distance / h2).exp();
+                for (acc, shifted) in acc.iter_mut().zip(shifted.iter()) {
+                    acc.as_slice_mut()[i] += weight * shifted.as_slice()[i];
+                }
+                total.as_slice_mut()[i] += weight;
+            }
+        }
+    }
+
+    acc.map(|mut acc| {
+        for (value, &total) in acc.as_slice_mut().iter_mut().zip(total.as_slice()) {
+            *value /= total;
+        }
+        ops::to_u8(&acc)
+    })
+}
+
+/// Plane moved so that the pixel at (x + dx, y + dy) lands at (x, y), clamped at the edges.
+fn shift(src: &Matrix<f32>, dx: i64, dy: i64) -> Matrix<f32> {
+    let width = src.width() as i64;
+    let height = src.height() as i64;
+    let mut out = Matrix::new(src.width(), src.height());
+    for y in 0..height {
+        let sy = (y + dy).max(0).min(height - 1) as u32;
+        for x in 0..width {
+            let sx = (x + dx).max(0).min(width - 1) as u32;
+            out.set(x as u32, y as u32, src.get(sx, sy));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(width: u32, height: u32) -> Matrix<u8> {
+        let mut m = Matrix::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                m.set(x, y, ((x * 53 + y * 29) ^ (x * y * 7)) as u8);
+            }
+        }
+        m
+    }
+
+    #[test]
+    fn median_matches_sorted_window() {
+        let src = pattern(9, 7);
+        let (width, height) = (src.width() as i64, src.height() as i64);
+        for radius in 0..=3 {
+            let r = radius as i64;
+            let out = median(src.as_view(), radius);
+            for y in 0..height {
+                for x in 0..width {
+                    let mut window = Vec::new();
+                    for dy in -r..=r {
+                        for dx in -r..=r {
+                            let sx = (x + dx).max(0).min(width - 1) as u32;
+                            let sy = (y + dy).max(0).min(height - 1) as u32;
+                            window.push(src.get(sx, sy));
+                        }
+                    }
+                    window.sort_unstable();
+                    assert_eq!(
+                        out.get(x as u32, y as u32),
+                        window[window.len() / 2],
+                        "radius {} at ({}, {})",
+                        radius,
+                        x,
+                        y
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn median_removes_a_single_outlier() {
+        let mut src = Matrix::new(5, 5);
+        src.as_slice_mut().fill(100);
+        src.set(2, 2, 255);
+        let out = median(src.as_view(), 1);
+        assert!(out.as_slice().iter().all(|&value| value == 100));
+    }
+}
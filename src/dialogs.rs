@@ -0,0 +1,1260 @@
+use druid::text::ParseFormatter;
+use druid::widget::{
+    Button, Checkbox, Controller, CrossAxisAlignment, Flex, Label, Painter, RadioGroup, Slider,
+    TextBox, ViewSwitcher,
+};
+use druid::{
+    commands as sys_commands, lens, Color, Env, Event, EventCtx, FileDialogOptions, FileSpec, Lens,
+    Rect, RenderContext, Target, UnitPoint, Widget, WidgetExt, WindowDesc,
+};
+
+use crate::channel_mixer::{ChannelMixerParams, Mix, MixerOutput};
+use crate::color_balance::{
+    Balance, ColorBalanceParams, FilterColor, PhotoFilterParams, TonalRange,
+};
+use crate::commands;
+use crate::convolution::{EdgeMode, Kernel};
+use crate::curves::{CurveEditor, CurvesParams, Preset};
+use crate::edges::{EdgeDetector, EdgeParams, EdgeTarget};
+use crate::effects::Effect;
+use crate::gradient_map::{GradientMapParams, GradientPreset};
+use crate::histogram::{self, LevelsHistogram, ThresholdHistogram};
+use crate::hue_saturation::{HslShift, HueRange, HueSaturationParams};
+use crate::image_buffer::ImageBuffer;
+use crate::levels::{self, Levels, LevelsParams, ToneChannel};
+use crate::morphology::{Element, Operation, Planes, Shape};
+use crate::resample::Filter;
+use crate::selection::Refinement;
+use crate::state::{
+    AppData, CanvasFill, CanvasParams, ChannelKind, ResizeParams, RotateParams, SizeUnit,
+};
+use crate::threshold;
+use crate::transform::Scope;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Dialog {
+    RefineSelection(Refinement),
+    ResizeImage,
+    ResizeCanvas,
+    Rotate(Scope),
+    GaussianBlur,
+    BoxBlur,
+    UnsharpMask,
+    Median,
+    Bilateral,
+    ReduceNoise,
+    DetectEdges,
+    Morphology,
+    CustomFilter,
+    Levels,
+    Curves,
+    AutoContrast,
+    AdaptiveEqualize,
+    BrightnessContrast,
+    Exposure,
+    Gamma,
+    ShadowsHighlights,
+    HueSaturation,
+    Vibrance,
+    ColorBalance,
+    ChannelMixer,
+    PhotoFilter,
+    Threshold,
+    Posterize,
+    GradientMap,
+}
+
+/// Opens the dialog in a new window, preparing its settings from the document first.
+pub(crate) fn open(ctx: &mut EventCtx, dialog: Dialog, data: &mut AppData) {
+    let (title, content, height) = match dialog {
+        Dialog::RefineSelection(refinement) => (
+            refinement.title(),
+            make_refine_selection(refinement).boxed(),
+            120.0,
+        ),
+        Dialog::ResizeImage => {
+            let (width, height) = data.document_size();
+            data.resize.reset(width, height);
+            ("Resize Image", make_resize_image().boxed(), 330.0)
+        }
+        Dialog::ResizeCanvas => {
+            let (width, height) = data.document_size();
+            data.canvas.reset(width, height);
+            ("Canvas Size", make_resize_canvas().boxed(), 330.0)
+        }
+        Dialog::Rotate(scope) => ("Rotate", make_rotate(scope).boxed(), 230.0),
+        Dialog::GaussianBlur => (
+            "Gaussian Blur",
+            make_effect(
+                make_slider("Radius", 0.1, 100.0, "px").lens(AppData::blur_radius),
+                |data| Effect::GaussianBlur {
+                    radius: data.blur_radius,
+                },
+            )
+            .boxed(),
+            130.0,
+        ),
+        Dialog::BoxBlur => (
+            "Box Blur",
+            make_effect(
+                make_slider("Radius", 1.0, 100.0, "px").lens(AppData::box_blur_radius),
+                |data| Effect::BoxBlur {
+                    radius: data.box_blur_radius,
+                },
+            )
+            .boxed(),
+            130.0,
+        ),
+        Dialog::UnsharpMask => (
+            "Unsharp Mask",
+            make_effect(make_unsharp_mask(), |data| Effect::UnsharpMask {
+                amount: data.usm_amount,
+                radius: data.usm_radius,
+                threshold: data.usm_threshold,
+            })
+            .boxed(),
+            190.0,
+        ),
+        Dialog::Median => (
+            "Median",
+            make_effect(
+                make_slider("Radius", 1.0, 100.0, "px").lens(AppData::median_radius),
+                |data| Effect::Median {
+                    radius: data.median_radius,
+                },
+            )
+            .boxed(),
+            130.0,
+        ),
+        Dialog::Bilateral => (
+            "Bilateral Filter",
+            make_effect(make_bilateral(), |data| Effect::Bilateral {
+                sigma_space: data.bilateral_sigma_space,
+                sigma_range: data.bilateral_sigma_range,
+            })
+            .boxed(),
+            160.0,
+        ),
+        Dialog::ReduceNoise => (
+            "Reduce Noise",
+            make_effect(make_reduce_noise(), |data| Effect::NonLocalMeans {
+                strength: data.nlm_strength,
+                search_radius: data.nlm_search_radius,
+                patch_radius: data.nlm_patch_radius,
+            })
+            .boxed(),
+            190.0,
+        ),
+        Dialog::DetectEdges => ("Detect Edges", make_detect_edges().boxed(), 260.0),
+        Dialog::Morphology => (
+            "Morphology",
+            make_effect(make_morphology(), |data| Effect::Morphology {
+                operation: data.morphology_operation,
+                element: morphology_element(data).unwrap_or_else(|_| Element::square(0)),
+                planes: data.morphology_planes,
+            })
+            .boxed(),
+            400.0,
+        ),
+        Dialog::CustomFilter => (
+            "Custom Filter",
+            make_effect(make_custom_filter(), |data| Effect::Convolve {
+                // Until the text parses, the layer is shown unchanged.
+                kernel: custom_kernel(data).unwrap_or_else(|_| Kernel::identity()),
+                edges: data.custom_edges,
+            })
+            .boxed(),
+            360.0,
+        ),
+        Dialog::Levels => {
+            data.levels = LevelsParams::IDENTITY;
+            data.levels_channel = ToneChannel::Composite;
+            (
+                "Levels",
+                make_effect(make_levels(), |data| Effect::Levels(data.levels)).boxed(),
+                420.0,
+            )
+        }
+        Dialog::Curves => {
+            data.curves = CurvesParams::linear();
+            data.curves_channel = ToneChannel::Composite;
+            data.curves_file_error = None;
+            (
+                "Curves",
+                make_effect(make_curves(), |data| Effect::Curves(data.curves.clone())).boxed(),
+                540.0,
+            )
+        }
+        Dialog::AutoContrast => (
+            "Auto Contrast",
+            make_effect(
+                make_decimal_slider("Clip (%)", 0.0, 5.0).lens(AppData::auto_contrast_clip),
+                |data| Effect::AutoContrast {
+                    clip: data.auto_contrast_clip,
+                },
+            )
+            .boxed(),
+            130.0,
+        ),
+        Dialog::AdaptiveEqualize => (
+            "Adaptive Equalization",
+            make_effect(make_adaptive_equalize(), |data| Effect::Clahe {
+                tile_size: data.clahe_tile_size.round() as u32,
+                clip_limit: data.clahe_clip_limit,
+            })
+            .boxed(),
+            160.0,
+        ),
+        Dialog::BrightnessContrast => {
+            data.brightness = 0.0;
+            data.contrast = 0.0;
+            (
+                "Brightness/Contrast",
+                make_effect(make_brightness_contrast(), |data| {
+                    Effect::BrightnessContrast {
+                        brightness: data.brightness,
+                        contrast: data.contrast,
+                    }
+                })
+                .boxed(),
+                160.0,
+            )
+        }
+        Dialog::Exposure => {
+            data.exposure = 0.0;
+            data.exposure_offset = 0.0;
+            data.exposure_gamma = 1.0;
+            (
+                "Exposure",
+                make_effect(make_exposure(), |data| Effect::Exposure {
+                    exposure: data.exposure,
+                    offset: data.exposure_offset,
+                    gamma: data.exposure_gamma,
+                })
+                .boxed(),
+                190.0,
+            )
+        }
+        Dialog::Gamma => {
+            data.gamma = 1.0;
+            (
+                "Gamma",
+                make_effect(
+                    make_decimal_slider("Gamma", 0.1, 10.0).lens(AppData::gamma),
+                    |data| Effect::Gamma { gamma: data.gamma },
+                )
+                .boxed(),
+                130.0,
+            )
+        }
+        Dialog::ShadowsHighlights => (
+            "Shadows/Highlights",
+            make_effect(make_shadows_highlights(), |data| {
+                Effect::ShadowsHighlights {
+                    shadows: data.shadows,
+                    highlights: data.highlights,
+                    radius: data.shadows_highlights_radius,
+                }
+            })
+            .boxed(),
+            190.0,
+        ),
+        Dialog::HueSaturation => {
+            data.hue_saturation = HueSaturationParams::NONE;
+            data.hue_range = HueRange::Master;
+            (
+                "Hue/Saturation",
+                make_effect(make_hue_saturation(), |data| {
+                    Effect::HueSaturation(data.hue_saturation)
+                })
+                .boxed(),
+                300.0,
+            )
+        }
+        Dialog::Vibrance => {
+            data.vibrance = 0.0;
+            data.vibrance_saturation = 0.0;
+            (
+                "Vibrance",
+                make_effect(make_vibrance(), |data| Effect::Vibrance {
+                    vibrance: data.vibrance,
+                    saturation: data.vibrance_saturation,
+                })
+                .boxed(),
+                160.0,
+            )
+        }
+        Dialog::ColorBalance => {
+            data.color_balance = ColorBalanceParams::NONE;
+            data.color_balance_range = TonalRange::Midtones;
+            (
+                "Color Balance",
+                make_effect(make_color_balance(), |data| {
+                    Effect::ColorBalance(data.color_balance)
+                })
+                .boxed(),
+                250.0,
+            )
+        }
+        Dialog::ChannelMixer => {
+            data.channel_mixer = ChannelMixerParams::IDENTITY;
+            data.mixer_output = MixerOutput::Red;
+            (
+                "Channel Mixer",
+                make_effect(make_channel_mixer(), |data| {
+                    Effect::ChannelMixer(data.channel_mixer)
+                })
+                .boxed(),
+                270.0,
+            )
+        }
+        Dialog::PhotoFilter => (
+            "Photo Filter",
+            make_effect(make_photo_filter(), |data| Effect::PhotoFilter {
+                color: data.photo_filter.color.rgb(brush_rgb(data)),
+                density: data.photo_filter.density,
+                preserve_luminosity: data.photo_filter.preserve_luminosity,
+            })
+            .boxed(),
+            270.0,
+        ),
+        Dialog::Threshold => {
+            data.threshold = 128.0;
+            (
+                "Threshold",
+                make_effect(make_threshold(), |data| Effect::Threshold {
+                    level: data.threshold.round() as u8,
+                })
+                .boxed(),
+                250.0,
+            )
+        }
+        Dialog::Posterize => {
+            data.posterize_levels = 4.0;
+            (
+                "Posterize",
+                make_effect(
+                    make_slider("Levels", 2.0, 32.0, "").lens(AppData::posterize_levels),
+                    |data| Effect::Posterize {
+                        levels: data.posterize_levels.round() as u32,
+                    },
+                )
+                .boxed(),
+                130.0,
+            )
+        }
+        Dialog::GradientMap => {
+            data.gradient_map = GradientMapParams {
+                preset: GradientPreset::BlackWhite,
+                reverse: false,
+            };
+            (
+                "Gradient Map",
+                make_effect(make_gradient_map(), |data| {
+                    Effect::GradientMap(data.gradient_map.gradient(brush_rgb(data)))
+                })
+                .boxed(),
+                300.0,
+            )
+        }
+    };
+
+    ctx.new_window(
+        WindowDesc::new(content)
+            .title(title)
+            .window_size((360.0, height))
+            .resizable(false),
+    );
+}
+
+fn make_refine_selection(refinement: Refinement) -> impl Widget<AppData> {
+    let label = match refinement {
+        Refinement::Border => "Width",
+        _ => "Radius",
+    };
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(make_slider(label, 1.0, 100.0, "px").lens(AppData::selection_radius))
+        .with_spacer(10.0)
+        .with_child(make_buttons(move |ctx, data| {
+            ctx.submit_command(
+                commands::REFINE_SELECTION
+                    .with((refinement, data.selection_radius))
+                    .to(Target::Global),
+            )
+        }))
+        .padding(10.0)
+}
+
+fn make_resize_image() -> impl Widget<AppData> {
+    let size_field =
+        |label: &'static str, get: fn(&ResizeParams) -> f64, put: fn(&mut ResizeParams, f64)| {
+            Flex::row()
+                .with_child(Label::new(label).fix_width(80.0))
+                .with_child(
+                    TextBox::new()
+                        .with_formatter(ParseFormatter::new())
+                        .update_data_while_editing(true)
+                        .fix_width(100.0)
+                        .lens(lens::Map::new(get, put)),
+                )
+        };
+
+    let params = Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(size_field("Width", |p| p.width, ResizeParams::set_width))
+        .with_spacer(5.0)
+        .with_child(size_field("Height", |p| p.height, ResizeParams::set_height))
+        .with_spacer(5.0)
+        .with_child(
+            RadioGroup::row(vec![
+                ("Pixels", SizeUnit::Pixels),
+                ("Percent", SizeUnit::Percent),
+            ])
+            .lens(lens::Map::new(
+                |p: &ResizeParams| p.unit,
+                ResizeParams::set_unit,
+            )),
+        )
+        .with_child(Checkbox::new("Keep aspect ratio").lens(ResizeParams::keep_aspect))
+        .with_spacer(5.0)
+        .with_child(make_filter_picker().lens(ResizeParams::filter))
+        .with_spacer(5.0)
+        .with_child(Label::new(|p: &ResizeParams, _env: &_| {
+            let (width, height) = p.pixel_size();
+            format!(
+                "{} × {} px, originally {} × {} px",
+                width, height, p.original_width, p.original_height
+            )
+        }))
+        .lens(AppData::resize);
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(params)
+        .with_spacer(10.0)
+        .with_child(make_buttons(|ctx, data| {
+            let (width, height) = data.resize.pixel_size();
+            ctx.submit_command(
+                commands::RESIZE_IMAGE
+                    .with((width, height, data.resize.filter))
+                    .to(Target::Global),
+            )
+        }))
+        .padding(10.0)
+}
+
+fn make_resize_canvas() -> impl Widget<AppData> {
+    let size_field = |label: &'static str| {
+        Flex::row()
+            .with_child(Label::new(label).fix_width(80.0))
+            .with_child(
+                TextBox::new()
+                    .with_formatter(ParseFormatter::new())
+                    .update_data_while_editing(true)
+                    .fix_width(100.0),
+            )
+            .with_child(Label::new("px").padding((5.0, 0.0)))
+    };
+
+    let params = Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(size_field("Width").lens(CanvasParams::width))
+        .with_spacer(5.0)
+        .with_child(size_field("Height").lens(CanvasParams::height))
+        .with_spacer(5.0)
+        .with_child(
+            Flex::row()
+                .cross_axis_alignment(CrossAxisAlignment::Start)
+                .with_child(Label::new("Anchor").fix_width(80.0))
+                .with_child(make_anchor_picker().lens(CanvasParams::anchor)),
+        )
+        .with_spacer(5.0)
+        .with_child(
+            RadioGroup::column(vec![
+                ("Transparent", CanvasFill::Transparent),
+                ("Brush color", CanvasFill::BrushColor),
+                ("White", CanvasFill::White),
+                ("Black", CanvasFill::Black),
+            ])
+            .lens(CanvasParams::fill),
+        )
+        .with_child(Label::new(|p: &CanvasParams, _env: &_| {
+            format!("Currently {} × {} px", p.original_width, p.original_height)
+        }))
+        .lens(AppData::canvas);
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(params)
+        .with_spacer(10.0)
+        .with_child(make_buttons(|ctx, data| {
+            let canvas = &data.canvas;
+            if canvas.width == 0 || canvas.height == 0 {
+                return;
+            }
+            let fill = data.canvas_fill_color(canvas.fill);
+            ctx.submit_command(
+                commands::RESIZE_CANVAS
+                    .with((canvas.width, canvas.height, canvas.anchor, fill))
+                    .to(Target::Global),
+            )
+        }))
+        .padding(10.0)
+}
+
+fn make_rotate(scope: Scope) -> impl Widget<AppData> {
+    let angle = Flex::row()
+        .with_child(Label::new("Angle").fix_width(80.0))
+        .with_flex_child(Slider::new().with_range(-180.0, 180.0).expand_width(), 1.0)
+        .with_child(
+            TextBox::new()
+                .with_formatter(ParseFormatter::new())
+                .update_data_while_editing(true)
+                .fix_width(60.0),
+        )
+        .with_child(Label::new("°").padding((5.0, 0.0)));
+
+    let params = Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(angle.lens(RotateParams::angle))
+        .with_spacer(5.0)
+        .with_child(make_filter_picker().lens(RotateParams::filter))
+        .lens(AppData::rotate);
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(params)
+        .with_spacer(10.0)
+        .with_child(make_buttons(move |ctx, data| {
+            ctx.submit_command(
+                commands::ROTATE
+                    .with((data.rotate.angle, data.rotate.filter, scope))
+                    .to(Target::Global),
+            )
+        }))
+        .padding(10.0)
+}
+
+fn make_filter_picker() -> impl Widget<Filter> {
+    RadioGroup::column(vec![
+        ("Nearest neighbor", Filter::Nearest),
+        ("Bilinear", Filter::Bilinear),
+        ("Bicubic", Filter::Bicubic),
+        ("Lanczos3", Filter::Lanczos3),
+    ])
+}
+
+/// Three by three grid of buttons picking one of the nine anchor points.
+fn make_anchor_picker() -> impl Widget<UnitPoint> {
+    let cell = |anchor: UnitPoint| {
+        Button::dynamic(move |current: &UnitPoint, _env: &_| {
+            if *current == anchor { "●" } else { "○" }.to_string()
+        })
+        .on_click(move |_ctx, current: &mut UnitPoint, _env| *current = anchor)
+        .fix_size(28.0, 28.0)
+    };
+    let row = |anchors: [UnitPoint; 3]| {
+        Flex::row()
+            .with_child(cell(anchors[0]))
+            .with_child(cell(anchors[1]))
+            .with_child(cell(anchors[2]))
+    };
+
+    Flex::column()
+        .with_child(row([
+            UnitPoint::TOP_LEFT,
+            UnitPoint::TOP,
+            UnitPoint::TOP_RIGHT,
+        ]))
+        .with_child(row([UnitPoint::LEFT, UnitPoint::CENTER, UnitPoint::RIGHT]))
+        .with_child(row([
+            UnitPoint::BOTTOM_LEFT,
+            UnitPoint::BOTTOM,
+            UnitPoint::BOTTOM_RIGHT,
+        ]))
+}
+
+fn make_unsharp_mask() -> impl Widget<AppData> {
+    Flex::column()
+        .with_child(make_slider("Amount", 1.0, 500.0, "%").lens(AppData::usm_amount))
+        .with_child(make_slider("Radius", 0.1, 100.0, "px").lens(AppData::usm_radius))
+        .with_child(make_slider("Threshold", 0.0, 255.0, "levels").lens(AppData::usm_threshold))
+}
+
+fn make_bilateral() -> impl Widget<AppData> {
+    Flex::column()
+        .with_child(
+            make_slider("Spatial sigma", 1.0, 20.0, "px").lens(AppData::bilateral_sigma_space),
+        )
+        .with_child(
+            make_slider("Tolerance", 1.0, 100.0, "levels").lens(AppData::bilateral_sigma_range),
+        )
+}
+
+fn make_reduce_noise() -> impl Widget<AppData> {
+    Flex::column()
+        .with_child(make_slider("Strength", 1.0, 50.0, "").lens(AppData::nlm_strength))
+        .with_child(make_slider("Search", 1.0, 15.0, "px").lens(AppData::nlm_search_radius))
+        .with_child(make_slider("Detail", 1.0, 5.0, "px").lens(AppData::nlm_patch_radius))
+}
+
+fn make_detect_edges() -> impl Widget<AppData> {
+    let params = Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            RadioGroup::row(vec![
+                ("Sobel", EdgeDetector::Sobel),
+                ("Prewitt", EdgeDetector::Prewitt),
+                ("Laplacian", EdgeDetector::Laplacian),
+                ("Canny", EdgeDetector::Canny),
+            ])
+            .lens(EdgeParams::detector),
+        )
+        .with_spacer(5.0)
+        .with_child(Label::new("Canny"))
+        .with_child(make_slider("Blur", 0.0, 10.0, "px").lens(EdgeParams::blur))
+        .with_child(make_slider("Low", 0.0, 255.0, "levels").lens(EdgeParams::low))
+        .with_child(make_slider("High", 0.0, 255.0, "levels").lens(EdgeParams::high))
+        .with_spacer(5.0)
+        .with_child(
+            RadioGroup::row(vec![
+                ("New layer", EdgeTarget::NewLayer),
+                ("Selection", EdgeTarget::Selection),
+            ])
+            .lens(EdgeParams::target),
+        )
+        .lens(AppData::edges);
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(params)
+        .with_spacer(10.0)
+        .with_child(make_buttons(|ctx, data| {
+            ctx.submit_command(commands::DETECT_EDGES.with(data.edges).to(Target::Global))
+        }))
+        .padding(10.0)
+}
+
+fn morphology_element(data: &AppData) -> Result<Element, String> {
+    let radius = data.morphology_radius.round() as u32;
+    match data.morphology_shape {
+        Shape::Square => Ok(Element::square(radius)),
+        Shape::Disc => Ok(Element::disc(radius)),
+        Shape::Custom => Element::parse(&data.morphology_element),
+    }
+}
+
+fn make_morphology() -> impl Widget<AppData> {
+    let operation = RadioGroup::column(vec![
+        ("Erode", Operation::Erode),
+        ("Dilate", Operation::Dilate),
+        ("Open", Operation::Open),
+        ("Close", Operation::Close),
+        ("Top-hat", Operation::TopHat),
+        ("Gradient", Operation::Gradient),
+    ])
+    .lens(AppData::morphology_operation);
+    let shape = RadioGroup::column(vec![
+        ("Square", Shape::Square),
+        ("Disc", Shape::Disc),
+        ("Custom", Shape::Custom),
+    ])
+    .lens(AppData::morphology_shape);
+    let planes = RadioGroup::column(vec![
+        ("RGB", Planes::Colors),
+        ("Red", Planes::Single(ChannelKind::Red)),
+        ("Green", Planes::Single(ChannelKind::Green)),
+        ("Blue", Planes::Single(ChannelKind::Blue)),
+        ("Alpha", Planes::Single(ChannelKind::Alpha)),
+        ("Selection", Planes::Single(ChannelKind::Selection)),
+    ])
+    .lens(AppData::morphology_planes);
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Flex::row()
+                .cross_axis_alignment(CrossAxisAlignment::Start)
+                .with_flex_child(operation, 1.0)
+                .with_flex_child(shape, 1.0)
+                .with_flex_child(planes, 1.0),
+        )
+        .with_spacer(5.0)
+        .with_child(make_slider("Radius", 0.0, 50.0, "px").lens(AppData::morphology_radius))
+        .with_spacer(5.0)
+        .with_child(Label::new("Custom element (1 inside, 0 outside)"))
+        .with_child(
+            TextBox::multiline()
+                .expand_width()
+                .fix_height(80.0)
+                .lens(AppData::morphology_element),
+        )
+        .with_child(
+            Label::new(|data: &AppData, _env: &_| {
+                morphology_element(data).err().unwrap_or_default()
+            })
+            .with_text_color(Color::rgb8(200, 40, 40)),
+        )
+}
+
+fn make_levels() -> impl Widget<AppData> {
+    let selected = selected(
+        AppData::levels,
+        AppData::levels_channel,
+        LevelsParams::get,
+        LevelsParams::get_mut,
+    );
+    let sliders = Flex::column()
+        .with_child(make_slider("Black", 0.0, 255.0, "").lens(Levels::input_black))
+        .with_child(make_decimal_slider("Gamma", 0.1, 10.0).lens(Levels::gamma))
+        .with_child(make_slider("White", 0.0, 255.0, "").lens(Levels::input_white))
+        .with_spacer(5.0)
+        .with_child(make_slider("Output black", 0.0, 255.0, "").lens(Levels::output_black))
+        .with_child(make_slider("Output white", 0.0, 255.0, "").lens(Levels::output_white))
+        .lens(selected);
+
+    let auto = |title: &'static str, compute: fn(&ImageBuffer, f64) -> LevelsParams| {
+        Button::new(title).on_click(move |_ctx, data: &mut AppData, _env| {
+            let params = {
+                let layer = data.layers[data.active_layer()].borrow();
+                compute(layer.data.as_buffer().unwrap(), levels::AUTO_CLIP)
+            };
+            data.levels = params;
+        })
+    };
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            RadioGroup::row(vec![
+                ("RGB", ToneChannel::Composite),
+                ("Red", ToneChannel::Red),
+                ("Green", ToneChannel::Green),
+                ("Blue", ToneChannel::Blue),
+            ])
+            .lens(AppData::levels_channel),
+        )
+        .with_spacer(5.0)
+        .with_child(LevelsHistogram::default().expand_width().fix_height(100.0))
+        .with_spacer(5.0)
+        .with_child(sliders)
+        .with_spacer(5.0)
+        .with_child(
+            Flex::row()
+                .with_child(auto("Auto Color", LevelsParams::auto_color))
+                .with_spacer(5.0)
+                .with_child(auto("Auto Contrast", LevelsParams::auto_contrast))
+                .with_spacer(5.0)
+                .with_child(
+                    Button::new("Reset").on_click(|_ctx, data: &mut AppData, _env| {
+                        data.levels = LevelsParams::IDENTITY
+                    }),
+                ),
+        )
+}
+
+fn make_adaptive_equalize() -> impl Widget<AppData> {
+    Flex::column()
+        .with_child(make_slider("Tile size", 8.0, 256.0, "px").lens(AppData::clahe_tile_size))
+        .with_child(make_decimal_slider("Clip limit", 1.0, 10.0).lens(AppData::clahe_clip_limit))
+}
+
+fn make_brightness_contrast() -> impl Widget<AppData> {
+    Flex::column()
+        .with_child(make_slider("Brightness", -100.0, 100.0, "%").lens(AppData::brightness))
+        .with_child(make_slider("Contrast", -100.0, 100.0, "%").lens(AppData::contrast))
+}
+
+fn make_exposure() -> impl Widget<AppData> {
+    Flex::column()
+        .with_child(make_decimal_slider("Exposure", -5.0, 5.0).lens(AppData::exposure))
+        .with_child(make_decimal_slider("Offset", -0.5, 0.5).lens(AppData::exposure_offset))
+        .with_child(make_decimal_slider("Gamma", 0.1, 10.0).lens(AppData::exposure_gamma))
+}
+
+fn make_shadows_highlights() -> impl Widget<AppData> {
+    Flex::column()
+        .with_child(make_slider("Shadows", 0.0, 100.0, "%").lens(AppData::shadows))
+        .with_child(make_slider("Highlights", 0.0, 100.0, "%").lens(AppData::highlights))
+        .with_child(
+            make_slider("Radius", 1.0, 200.0, "px").lens(AppData::shadows_highlights_radius),
+        )
+}
+
+fn make_hue_saturation() -> impl Widget<AppData> {
+    let ranges = RadioGroup::column(
+        HueRange::ALL
+            .iter()
+            .map(|&range| (range.title(), range))
+            .collect::<Vec<_>>(),
+    )
+    .lens(AppData::hue_range);
+
+    let selected = selected(
+        AppData::hue_saturation,
+        AppData::hue_range,
+        HueSaturationParams::get,
+        HueSaturationParams::get_mut,
+    );
+    let sliders = Flex::column()
+        .with_child(make_slider("Hue", -180.0, 180.0, "°").lens(HslShift::hue))
+        .with_child(make_slider("Saturation", -100.0, 100.0, "%").lens(HslShift::saturation))
+        .with_child(make_slider("Lightness", -100.0, 100.0, "%").lens(HslShift::lightness))
+        .lens(selected);
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            Flex::row()
+                .cross_axis_alignment(CrossAxisAlignment::Start)
+                .with_child(ranges)
+                .with_spacer(5.0)
+                .with_flex_child(sliders, 1.0),
+        )
+        .with_spacer(5.0)
+        .with_child(
+            Checkbox::new("Colorize")
+                .lens(HueSaturationParams::colorize)
+                .lens(AppData::hue_saturation),
+        )
+}
+
+fn make_vibrance() -> impl Widget<AppData> {
+    Flex::column()
+        .with_child(make_slider("Vibrance", -100.0, 100.0, "%").lens(AppData::vibrance))
+        .with_child(
+            make_slider("Saturation", -100.0, 100.0, "%").lens(AppData::vibrance_saturation),
+        )
+}
+
+fn make_color_balance() -> impl Widget<AppData> {
+    let selected = selected(
+        AppData::color_balance,
+        AppData::color_balance_range,
+        ColorBalanceParams::get,
+        ColorBalanceParams::get_mut,
+    );
+    let sliders = Flex::column()
+        .with_child(make_slider("Red", -100.0, 100.0, "%").lens(Balance::cyan_red))
+        .with_child(make_slider("Green", -100.0, 100.0, "%").lens(Balance::magenta_green))
+        .with_child(make_slider("Blue", -100.0, 100.0, "%").lens(Balance::yellow_blue))
+        .lens(selected);
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            RadioGroup::row(vec![
+                ("Shadows", TonalRange::Shadows),
+                ("Midtones", TonalRange::Midtones),
+                ("Highlights", TonalRange::Highlights),
+            ])
+            .lens(AppData::color_balance_range),
+        )
+        .with_spacer(5.0)
+        .with_child(sliders)
+        .with_child(Label::new(
+            "Negative values shift to cyan, magenta and yellow.",
+        ))
+        .with_spacer(5.0)
+        .with_child(
+            Checkbox::new("Preserve luminosity")
+                .lens(ColorBalanceParams::preserve_luminosity)
+                .lens(AppData::color_balance),
+        )
+}
+
+fn make_channel_mixer() -> impl Widget<AppData> {
+    let selected = selected(
+        AppData::channel_mixer,
+        AppData::mixer_output,
+        ChannelMixerParams::get,
+        ChannelMixerParams::get_mut,
+    );
+    let sliders = Flex::column()
+        .with_child(make_slider("Red", -200.0, 200.0, "%").lens(Mix::red))
+        .with_child(make_slider("Green", -200.0, 200.0, "%").lens(Mix::green))
+        .with_child(make_slider("Blue", -200.0, 200.0, "%").lens(Mix::blue))
+        .with_child(make_slider("Constant", -100.0, 100.0, "%").lens(Mix::constant))
+        .lens(selected);
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            RadioGroup::row(vec![
+                ("Red", MixerOutput::Red),
+                ("Green", MixerOutput::Green),
+                ("Blue", MixerOutput::Blue),
+                ("Gray", MixerOutput::Gray),
+            ])
+            .lens(AppData::mixer_output),
+        )
+        .with_spacer(5.0)
+        .with_child(sliders)
+        .with_spacer(5.0)
+        .with_child(
+            Checkbox::new("Monochrome (uses the gray mix)")
+                .lens(ChannelMixerParams::monochrome)
+                .lens(AppData::channel_mixer),
+        )
+}
+
+fn make_photo_filter() -> impl Widget<AppData> {
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            RadioGroup::column(vec![
+                ("Warming", FilterColor::Warming),
+                ("Cooling", FilterColor::Cooling),
+                ("Sepia", FilterColor::Sepia),
+                ("Brush color", FilterColor::Brush),
+            ])
+            .lens(PhotoFilterParams::color),
+        )
+        .with_spacer(5.0)
+        .with_child(make_slider("Density", 0.0, 100.0, "%").lens(PhotoFilterParams::density))
+        .with_spacer(5.0)
+        .with_child(
+            Checkbox::new("Preserve luminosity").lens(PhotoFilterParams::preserve_luminosity),
+        )
+        .lens(AppData::photo_filter)
+}
+
+fn make_threshold() -> impl Widget<AppData> {
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            ThresholdHistogram::default()
+                .expand_width()
+                .fix_height(100.0),
+        )
+        .with_spacer(5.0)
+        .with_child(make_slider("Level", 0.0, 255.0, "").lens(AppData::threshold))
+        .with_spacer(5.0)
+        .with_child(
+            Button::new("Auto").on_click(|_ctx, data: &mut AppData, _env| {
+                let histogram = {
+                    let layer = data.layers[data.active_layer()].borrow();
+                    histogram::count_luminance(layer.data.as_buffer().unwrap())
+                };
+                data.threshold = threshold::otsu(&histogram) as f64;
+            }),
+        )
+}
+
+fn make_gradient_map() -> impl Widget<AppData> {
+    let strip = Painter::new(|ctx, data: &AppData, _env| {
+        let gradient = data.gradient_map.gradient(brush_rgb(data));
+        let size = ctx.size();
+        let width = size.width.max(1.0);
+        for x in 0..width.ceil() as u32 {
+            let [r, g, b] = gradient.color_at(x as f64 / (width - 1.0).max(1.0));
+            let column = Rect::new(x as f64, 0.0, x as f64 + 1.0, size.height);
+            ctx.fill(column, &Color::rgb8(r, g, b));
+        }
+    });
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(strip.expand_width().fix_height(24.0))
+        .with_spacer(5.0)
+        .with_child(
+            Flex::column()
+                .cross_axis_alignment(CrossAxisAlignment::Start)
+                .with_child(
+                    RadioGroup::column(
+                        GradientPreset::ALL
+                            .iter()
+                            .map(|&preset| (preset.title(), preset))
+                            .collect::<Vec<_>>(),
+                    )
+                    .lens(GradientMapParams::preset),
+                )
+                .with_child(Checkbox::new("Reverse").lens(GradientMapParams::reverse))
+                .lens(AppData::gradient_map),
+        )
+}
+
+/// Brush color as used by the adjustments that offer it.
+fn brush_rgb(data: &AppData) -> [u8; 3] {
+    [data.brush_color.r, data.brush_color.g, data.brush_color.b]
+}
+
+const CURVES_FILE: FileSpec = FileSpec::new("Curves", &["curves"]);
+
+fn make_curves() -> impl Widget<AppData> {
+    let editor = ViewSwitcher::new(
+        |data: &AppData, _env| data.curves_channel,
+        |&channel, _data, _env| {
+            let color = match channel {
+                ToneChannel::Composite => Color::grey8(40),
+                ToneChannel::Red => Color::rgb8(200, 0, 0),
+                ToneChannel::Green => Color::rgb8(0, 160, 0),
+                ToneChannel::Blue => Color::rgb8(0, 0, 200),
+            };
+            CurveEditor::new(color)
+                .lens(selected(
+                    AppData::curves,
+                    AppData::curves_channel,
+                    CurvesParams::get,
+                    CurvesParams::get_mut,
+                ))
+                .boxed()
+        },
+    );
+
+    let preset = |preset: Preset| {
+        Button::new(preset.title()).on_click(move |_ctx, data: &mut AppData, _env| {
+            *data.curves.get_mut(data.curves_channel) = preset.curve()
+        })
+    };
+    let mut presets = Flex::column().cross_axis_alignment(CrossAxisAlignment::Start);
+    for row in Preset::ALL.chunks(3) {
+        let mut buttons = Flex::row();
+        for &p in row {
+            buttons.add_child(preset(p));
+            buttons.add_spacer(5.0);
+        }
+        presets.add_child(buttons);
+        presets.add_spacer(5.0);
+    }
+
+    let files = Flex::row()
+        .with_child(
+            Button::new("Load...").on_click(|ctx, _data: &mut AppData, _env| {
+                let options = FileDialogOptions::new().allowed_types(vec![CURVES_FILE]);
+                ctx.submit_command(sys_commands::SHOW_OPEN_PANEL.with(options))
+            }),
+        )
+        .with_spacer(5.0)
+        .with_child(
+            Button::new("Save...").on_click(|ctx, _data: &mut AppData, _env| {
+                let options = FileDialogOptions::new()
+                    .allowed_types(vec![CURVES_FILE])
+                    .default_type(CURVES_FILE)
+                    .default_name("untitled.curves");
+                ctx.submit_command(sys_commands::SHOW_SAVE_PANEL.with(options))
+            }),
+        )
+        .with_spacer(5.0)
+        .with_child(
+            Label::new(|data: &AppData, _env: &_| {
+                data.curves_file_error.clone().unwrap_or_default()
+            })
+            .with_text_color(Color::rgb8(200, 40, 40)),
+        );
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            RadioGroup::row(vec![
+                ("RGB", ToneChannel::Composite),
+                ("Red", ToneChannel::Red),
+                ("Green", ToneChannel::Green),
+                ("Blue", ToneChannel::Blue),
+            ])
+            .lens(AppData::curves_channel),
+        )
+        .with_spacer(5.0)
+        .with_child(editor)
+        .with_spacer(10.0)
+        .with_child(presets)
+        .with_child(files)
+        .controller(CurvesFileController)
+}
+
+/// Writes and reads the curves picked in the file dialogs of the Curves dialog.
+struct CurvesFileController;
+
+impl<W: Widget<AppData>> Controller<AppData, W> for CurvesFileController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut AppData,
+        env: &Env,
+    ) {
+        if let Event::Command(cmd) = event {
+            if let Some(file) = cmd.get(sys_commands::SAVE_FILE_AS) {
+                data.curves_file_error = std::fs::write(file.path(), data.curves.to_text())
+                    .err()
+                    .map(|error| error.to_string());
+                ctx.set_handled();
+                return;
+            }
+            if let Some(file) = cmd.get(sys_commands::OPEN_FILE) {
+                let curves = std::fs::read_to_string(file.path())
+                    .map_err(|error| error.to_string())
+                    .and_then(|text| CurvesParams::parse(&text));
+                match curves {
+                    Ok(curves) => {
+                        data.curves = curves;
+                        data.curves_file_error = None;
+                    }
+                    Err(error) => data.curves_file_error = Some(error),
+                }
+                ctx.set_handled();
+                return;
+            }
+        }
+        child.event(ctx, event, data, env);
+    }
+}
+
+fn custom_kernel(data: &AppData) -> Result<Kernel, String> {
+    Kernel::parse(
+        &data.custom_kernel,
+        data.custom_divisor as f32,
+        data.custom_offset as f32,
+    )
+}
+
+fn make_custom_filter() -> impl Widget<AppData> {
+    let number_field = |label: &'static str| {
+        Flex::row()
+            .with_child(Label::new(label).fix_width(80.0))
+            .with_child(
+                TextBox::new()
+                    .with_formatter(ParseFormatter::new())
+                    .update_data_while_editing(true)
+                    .fix_width(100.0),
+            )
+    };
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(Label::new("Kernel (one row per line)"))
+        .with_spacer(5.0)
+        .with_child(
+            TextBox::multiline()
+                .with_placeholder("-1 -1 -1\n-1 9 -1\n-1 -1 -1")
+                .expand_width()
+                .fix_height(100.0)
+                .lens(AppData::custom_kernel),
+        )
+        .with_child(
+            Label::new(|data: &AppData, _env: &_| custom_kernel(data).err().unwrap_or_default())
+                .with_text_color(Color::rgb8(200, 40, 40)),
+        )
+        .with_spacer(5.0)
+        .with_child(number_field("Divisor").lens(AppData::custom_divisor))
+        .with_child(Label::new("Zero divides by the sum of the weights."))
+        .with_spacer(5.0)
+        .with_child(number_field("Offset").lens(AppData::custom_offset))
+        .with_spacer(5.0)
+        .with_child(
+            RadioGroup::row(vec![
+                ("Clamp", EdgeMode::Clamp),
+                ("Wrap", EdgeMode::Wrap),
+                ("Mirror", EdgeMode::Mirror),
+                ("Zero", EdgeMode::Zero),
+            ])
+            .lens(AppData::custom_edges),
+        )
+}
+
+/// Dialog of an effect with the given settings, a preview toggle and buttons.
+fn make_effect(
+    params: impl Widget<AppData> + 'static,
+    effect: fn(&AppData) -> Effect,
+) -> impl Widget<AppData> {
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(params)
+        .with_spacer(5.0)
+        .with_child(Checkbox::new("Preview").lens(AppData::show_preview))
+        .with_spacer(10.0)
+        .with_child(make_buttons(move |ctx, data| {
+            ctx.submit_command(commands::APPLY_EFFECT.with(effect(data)).to(Target::Global))
+        }))
+        .padding(10.0)
+        .controller(PreviewController { effect })
+}
+
+/// Keeps the preview in sync with the settings of the dialog and drops it
+/// once the dialog is closed.
+struct PreviewController {
+    effect: fn(&AppData) -> Effect,
+}
+
+impl<W: Widget<AppData>> Controller<AppData, W> for PreviewController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut AppData,
+        env: &Env,
+    ) {
+        child.event(ctx, event, data, env);
+
+        let preview = match event {
+            Event::WindowDisconnected => None,
+            _ if data.show_preview => Some((self.effect)(data)),
+            _ => None,
+        };
+        if data.preview != preview {
+            data.preview = preview;
+        }
+    }
+}
+
+/// Lens to the part of the `params` picked by `key`, such as the settings of
+/// the channel or the range chosen in the dialog.
+fn selected<P, K: Copy, V: Clone>(
+    params: impl Lens<AppData, P> + Clone,
+    key: impl Lens<AppData, K> + Clone,
+    get: fn(&P, K) -> &V,
+    get_mut: fn(&mut P, K) -> &mut V,
+) -> impl Lens<AppData, V> {
+    let (params_mut, key_mut) = (params.clone(), key.clone());
+    lens::Map::new(
+        move |data: &AppData| {
+            let key = key.with(data, |key| *key);
+            params.with(data, |params| get(params, key).clone())
+        },
+        move |data: &mut AppData, value: V| {
+            let key = key_mut.with(data, |key| *key);
+            params_mut.with_mut(data, |params| *get_mut(params, key) = value);
+        },
+    )
+}
+
+/// A labelled slider showing its current value in the given unit.
+fn make_slider(label: &'static str, min: f64, max: f64, unit: &'static str) -> impl Widget<f64> {
+    Flex::row()
+        .with_child(Label::new(label).fix_width(80.0))
+        .with_flex_child(Slider::new().with_range(min, max).expand_width(), 1.0)
+        .with_child(
+            Label::new(|value: &f64, _env: &_| format!("{:.0} {}", value, unit)).fix_width(50.0),
+        )
+}
+
+/// Like `make_slider`, for values that need two decimals.
+fn make_decimal_slider(label: &'static str, min: f64, max: f64) -> impl Widget<f64> {
+    Flex::row()
+        .with_child(Label::new(label).fix_width(80.0))
+        .with_flex_child(Slider::new().with_range(min, max).expand_width(), 1.0)
+        .with_child(Label::new(|value: &f64, _env: &_| format!("{:.2}", value)).fix_width(50.0))
+}
+
+/// OK and Cancel buttons, both of them close the dialog.
+fn make_buttons(on_accept: impl Fn(&mut EventCtx, &mut AppData) + 'static) -> impl Widget<AppData> {
+    Flex::row()
+        .with_flex_spacer(1.0)
+        .with_child(
+            Button::new("Cancel").on_click(|ctx, _data: &mut AppData, _env| {
+                ctx.submit_command(sys_commands::CLOSE_WINDOW)
+            }),
+        )
+        .with_spacer(5.0)
+        .with_child(
+            Button::new("OK").on_click(move |ctx, data: &mut AppData, _env| {
+                on_accept(ctx, data);
+                ctx.submit_command(sys_commands::CLOSE_WINDOW);
+            }),
+        )
+        .expand_width()
+}
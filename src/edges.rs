@@ -0,0 +1,251 @@
+use druid::{Data, Lens};
+
+use crate::channels::Matrix;
+use crate::convolution::{self, EdgeMode, Kernel};
+use crate::image_buffer::ImageBuffer;
+use crate::ops;
+use crate::state::ChannelKind;
+
+#[derive(Clone, Copy, PartialEq, Eq, Data, Debug)]
+pub(crate) enum EdgeDetector {
+    Sobel,
+    Prewitt,
+    Laplacian,
+    /// Thin edges traced with hysteresis, the result is either 0 or 255.
+    Canny,
+}
+
+/// Where the detected edges are stored.
+#[derive(Clone, Copy, PartialEq, Eq, Data, Debug)]
+pub(crate) enum EdgeTarget {
+    NewLayer,
+    Selection,
+}
+
+#[derive(Clone, Copy, PartialEq, Data, Debug, Lens)]
+pub(crate) struct EdgeParams {
+    pub(crate) detector: EdgeDetector,
+    /// Radius of the Gaussian blur applied before Canny, in pixels.
+    pub(crate) blur: f64,
+    /// Canny gradients above `high` start an edge, ones above `low` extend it.
+    pub(crate) low: f64,
+    pub(crate) high: f64,
+    pub(crate) target: EdgeTarget,
+}
+
+impl EdgeParams {
+    /// Edges of the image turned into grayscale first.
+    pub(crate) fn detect_in(&self, image: &ImageBuffer) -> Matrix<u8> {
+        self.detect(&ops::luminance(
+            &image.channel(ChannelKind::Red),
+            &image.channel(ChannelKind::Green),
+            &image.channel(ChannelKind::Blue),
+        ))
+    }
+
+    /// Strength of the edges in `src`, from black for flat areas to white.
+    pub(crate) fn detect(&self, src: &Matrix<f32>) -> Matrix<u8> {
+        match self.detector {
+            EdgeDetector::Sobel => magnitude(&gradient(src, &sobel())),
+            EdgeDetector::Prewitt => magnitude(&gradient(src, &prewitt())),
+            EdgeDetector::Laplacian => laplacian(src),
+            EdgeDetector::Canny => {
+                // A low threshold above the high one would not let any edge grow.
+                let low = self.low.min(self.high);
+                canny(src, self.blur, low as f32, self.high as f32)
+            }
+        }
+    }
+}
+
+/// Horizontal derivative, its transposition gives the vertical one.
+/// Divided so that a step from black to white gives 255.
+fn sobel() -> Kernel {
+    Kernel {
+        divisor: 4.0,
+        ..Kernel::new(3, 3, vec![-1.0, 0.0, 1.0, -2.0, 0.0, 2.0, -1.0, 0.0, 1.0])
+    }
+}
+
+fn prewitt() -> Kernel {
+    Kernel {
+        divisor: 3.0,
+        ..Kernel::new(3, 3, vec![-1.0, 0.0, 1.0, -1.0, 0.0, 1.0, -1.0, 0.0, 1.0])
+    }
+}
+
+/// Derivatives along the x and y axis.
+fn gradient(src: &Matrix<f32>, kernel: &Kernel) -> [Matrix<f32>; 2] {
+    [
+        convolution::convolve(src, kernel, EdgeMode::Clamp),
+        convolution::convolve(src, &kernel.transposed(), EdgeMode::Clamp),
+    ]
+}
+
+fn magnitude([gx, gy]: &[Matrix<f32>; 2]) -> Matrix<u8> {
+    let mut out = Matrix::new(gx.width(), gx.height());
+    for ((out, x), y) in out
+        .as_slice_mut()
+        .iter_mut()
+        .zip(gx.as_slice())
+        .zip(gy.as_slice())
+    {
+        *out = x.hypot(*y);
+    }
+    ops::to_u8(&out)
+}
+
+fn laplacian(src: &Matrix<f32>) -> Matrix<u8> {
+    let kernel = Kernel::new(3, 3, vec![0.0, 1.0, 0.0, 1.0, -4.0, 1.0, 0.0, 1.0, 0.0]);
+    let mut out = convolution::convolve(src, &kernel, EdgeMode::Clamp);
+    for value in out.as_slice_mut() {
+        *value = value.abs();
+    }
+    ops::to_u8(&out)
+}
+
+/// Canny edge detector: smoothing, Sobel gradient, non-maximum suppression along
+/// the gradient direction and hysteresis between the two thresholds.
+fn canny(src: &Matrix<f32>, blur: f64, low: f32, high: f32) -> Matrix<u8> {
+    let width = src.width() as i64;
+    let height = src.height() as i64;
+    let smooth = ops::convolve_separable(src, &ops::gaussian_kernel(blur));
+    let [gx, gy] = gradient(&smooth, &sobel());
+
+    let strength = |x: i64, y: i64| {
+        if x < 0 || y < 0 || x >= width || y >= height {
+            return 0.0;
+        }
+        gx.get(x as u32, y as u32).hypot(gy.get(x as u32, y as u32))
+    };
+
+    // Keep only the local maxima across the edge, quantized to four directions.
+    let mut thin = Matrix::new(src.width(), src.height());
+    for y in 0..height {
+        for x in 0..width {
+            let value = strength(x, y);
+            if value < low {
+                continue;
+            }
+            let angle = gy
+                .get(x as u32, y as u32)
+                .atan2(gx.get(x as u32, y as u32))
+                .to_degrees()
+                .rem_euclid(180.0);
+            let (dx, dy) = if !(22.5..157.5).contains(&angle) {
+                (1, 0)
+            } else if angle < 67.5 {
+                (1, 1)
+            } else if angle < 112.5 {
+                (0, 1)
+            } else {
+                (-1, 1)
+            };
+            if value >= strength(x + dx, y + dy) && value > strength(x - dx, y - dy) {
+                thin.set(x as u32, y as u32, value);
+            }
+        }
+    }
+
+    hysteresis(&thin, low, high)
+}
+
+/// Grows edges from the pixels of at least `high` through the connected ones of at least `low`.
+fn hysteresis(thin: &Matrix<f32>, low: f32, high: f32) -> Matrix<u8> {
+    let width = thin.width() as i64;
+    let height = thin.height() as i64;
+    let mut out = Matrix::new(thin.width(), thin.height());
+    let mut stack: Vec<(i64, i64)> = Vec::new();
+    let passes = |value: f32, threshold: f32| value > 0.0 && value >= threshold;
+    for y in 0..height {
+        for x in 0..width {
+            if passes(thin.get(x as u32, y as u32), high) && out.get(x as u32, y as u32) == 0 {
+                out.set(x as u32, y as u32, 255u8);
+                stack.push((x, y));
+            }
+            while let Some((cx, cy)) = stack.pop() {
+                for ny in (cy - 1).max(0)..=(cy + 1).min(height - 1) {
+                    for nx in (cx - 1).max(0)..=(cx + 1).min(width - 1) {
+                        let (nx, ny) = (nx as u32, ny as u32);
+                        if out.get(nx, ny) == 0 && passes(thin.get(nx, ny), low) {
+                            out.set(nx, ny, 255);
+                            stack.push((nx as i64, ny as i64));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Black on the left, white from `x` on.
+    fn step(width: u32, height: u32, x: u32) -> Matrix<f32> {
+        let mut m = Matrix::new(width, height);
+        for y in 0..height {
+            for sx in x..width {
+                m.set(sx, y, 255.0);
+            }
+        }
+        m
+    }
+
+    #[test]
+    fn hysteresis_keeps_weak_pixels_connected_to_strong_ones() {
+        let mut thin = Matrix::new(7, 3);
+        for (x, &value) in [0.0, 30.0, 30.0, 80.0, 30.0, 0.0, 30.0].iter().enumerate() {
+            thin.set(x as u32, 1, value);
+        }
+        // Diagonal neighbours are connected too.
+        thin.set(0, 0, 30.0);
+        let out = hysteresis(&thin, 20.0, 50.0);
+        assert_eq!(out.get(0, 0), 255);
+        for (x, &expected) in [0, 255, 255, 255, 255, 0, 0].iter().enumerate() {
+            assert_eq!(out.get(x as u32, 1), expected, "at {}", x);
+        }
+        assert!((0..7).all(|x| out.get(x, 2) == 0));
+    }
+
+    #[test]
+    fn hysteresis_drops_weak_edges_alone() {
+        let mut thin = Matrix::new(5, 1);
+        for x in 0..5 {
+            thin.set(x, 0, 40.0);
+        }
+        assert!(hysteresis(&thin, 20.0, 50.0)
+            .as_slice()
+            .iter()
+            .all(|&v| v == 0));
+    }
+
+    #[test]
+    fn canny_traces_a_thin_step() {
+        let out = canny(&step(16, 8, 8), 0.0, 20.0, 50.0);
+        for y in 0..8 {
+            for x in 0..16 {
+                let expected = if x == 7 { 255 } else { 0 };
+                assert_eq!(out.get(x, y), expected, "at ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn canny_low_threshold_is_limited_by_the_high_one() {
+        let src = step(16, 8, 8);
+        let params = EdgeParams {
+            detector: EdgeDetector::Canny,
+            blur: 0.0,
+            low: 200.0,
+            high: 50.0,
+            target: EdgeTarget::NewLayer,
+        };
+        assert_eq!(
+            params.detect(&src).as_slice(),
+            canny(&src, 0.0, 50.0, 50.0).as_slice()
+        );
+    }
+}
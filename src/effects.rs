@@ -0,0 +1,395 @@
+use druid::Data;
+
+use crate::channel_mixer::ChannelMixerParams;
+use crate::channels::{Matrix, View};
+use crate::color_balance::{self, ColorBalanceParams};
+use crate::convolution::{self, EdgeMode, Kernel};
+use crate::curves::CurvesParams;
+use crate::denoise;
+use crate::equalize;
+use crate::gradient_map::{self, Gradient};
+use crate::hue_saturation::{self, Desaturation, HueSaturationParams};
+use crate::image_buffer::ImageBuffer;
+use crate::levels::LevelsParams;
+use crate::morphology::{Element, Operation, Planes};
+use crate::ops;
+use crate::state::ChannelKind;
+use crate::threshold;
+use crate::tone;
+
+const COLOR_CHANNELS: [ChannelKind; 3] = [ChannelKind::Red, ChannelKind::Green, ChannelKind::Blue];
+
+/// Filters and adjustments applied to the active layer, limited to the selection if any.
+#[derive(Clone, PartialEq, Data, Debug)]
+pub(crate) enum Effect {
+    GaussianBlur {
+        radius: f64,
+    },
+    BoxBlur {
+        radius: f64,
+    },
+    /// Unsharp mask with settings that work for most photos.
+    Sharpen,
+    UnsharpMask {
+        /// In percent.
+        amount: f64,
+        radius: f64,
+        /// In levels.
+        threshold: f64,
+    },
+    Median {
+        radius: f64,
+    },
+    Bilateral {
+        /// Standard deviation of the distance, in pixels.
+        sigma_space: f64,
+        /// Standard deviation of the color difference, in levels.
+        sigma_range: f64,
+    },
+    NonLocalMeans {
+        strength: f64,
+        search_radius: f64,
+        patch_radius: f64,
+    },
+    Morphology {
+        operation: Operation,
+        element: Element,
+        planes: Planes,
+    },
+    Emboss,
+    EdgeEnhance,
+    Levels(LevelsParams),
+    Curves(CurvesParams),
+    AutoContrast {
+        /// Percentage of the darkest and the brightest pixels ignored.
+        clip: f64,
+    },
+    Equalize,
+    /// Contrast limited adaptive histogram equalization.
+    Clahe {
+        /// In pixels.
+        tile_size: u32,
+        /// Multiple of the average count where the histogram bins are cut off.
+        clip_limit: f64,
+    },
+    BrightnessContrast {
+        /// In percent.
+        brightness: f64,
+        /// In percent.
+        contrast: f64,
+    },
+    Exposure {
+        /// In stops.
+        exposure: f64,
+        offset: f64,
+        gamma: f64,
+    },
+    Gamma {
+        gamma: f64,
+    },
+    ShadowsHighlights {
+        /// In percent.
+        shadows: f64,
+        /// In percent.
+        highlights: f64,
+        radius: f64,
+    },
+    HueSaturation(HueSaturationParams),
+    ColorBalance(ColorBalanceParams),
+    ChannelMixer(ChannelMixerParams),
+    PhotoFilter {
+        color: [u8; 3],
+        /// In percent.
+        density: f64,
+        preserve_luminosity: bool,
+    },
+    Vibrance {
+        /// In percent.
+        vibrance: f64,
+        /// In percent.
+        saturation: f64,
+    },
+    Invert,
+    Desaturate(Desaturation),
+    Threshold {
+        level: u8,
+    },
+    Posterize {
+        levels: u32,
+    },
+    GradientMap(Gradient),
+    /// User defined kernel.
+    Convolve {
+        kernel: Kernel,
+        edges: EdgeMode,
+    },
+}
+
+impl Effect {
+    pub(crate) fn apply(&self, image: &mut ImageBuffer) {
+        match *self {
+            Effect::GaussianBlur { radius } => {
+                let results = map_premultiplied(image, |plane| {
+                    ops::convolve_separable(plane, &ops::gaussian_kernel(radius))
+                });
+                apply_all(image, results);
+            }
+            Effect::BoxBlur { radius } => {
+                let row = Kernel::box_blur(radius.round() as u32);
+                let column = row.transposed();
+                let results = map_premultiplied(image, |plane| {
+                    let plane = convolution::convolve(plane, &row, EdgeMode::Clamp);
+                    convolution::convolve(&plane, &column, EdgeMode::Clamp)
+                });
+                apply_all(image, results);
+            }
+            Effect::Sharpen => Effect::UnsharpMask {
+                amount: 50.0,
+                radius: 3.0,
+                threshold: 0.0,
+            }
+            .apply(image),
+            Effect::UnsharpMask {
+                amount,
+                radius,
+                threshold,
+            } => {
+                let results = map_premultiplied(image, |plane| {
+                    ops::unsharp_mask(plane, amount / 100.0, radius, threshold)
+                });
+                apply_all(image, results);
+            }
+            Effect::Median { radius } => {
+                let results = map_premultiplied(image, |plane| {
+                    let median =
+                        denoise::median(ops::to_u8(plane).as_view(), radius.round() as u32);
+                    ops::to_f32(&median.as_view())
+                });
+                apply_all(image, results);
+            }
+            Effect::Bilateral {
+                sigma_space,
+                sigma_range,
+            } => {
+                let results = map_rgba_premultiplied(image, |channels| {
+                    denoise::bilateral(channels, sigma_space, sigma_range)
+                });
+                apply_all(image, results);
+            }
+            Effect::NonLocalMeans {
+                strength,
+                search_radius,
+                patch_radius,
+            } => {
+                let results = map_rgba_premultiplied(image, |channels| {
+                    denoise::non_local_means(
+                        channels,
+                        strength,
+                        search_radius.round() as u32,
+                        patch_radius.round() as u32,
+                    )
+                });
+                apply_all(image, results);
+            }
+            Effect::Morphology {
+                operation,
+                ref element,
+                planes,
+            } => match planes {
+                Planes::Colors => map_colors(image, |channel| {
+                    operation.apply(&channel.to_matrix(), element)
+                }),
+                Planes::Single(kind) => {
+                    let result = operation.apply(&image.channel(kind).to_matrix(), element);
+                    // The selection is not masked by itself.
+                    if kind == ChannelKind::Selection {
+                        image.replace_channel(kind, result);
+                    } else {
+                        image.apply_masked(kind, result);
+                    }
+                }
+            },
+            Effect::Emboss => Effect::Convolve {
+                kernel: Kernel::emboss(),
+                edges: EdgeMode::Clamp,
+            }
+            .apply(image),
+            Effect::EdgeEnhance => Effect::Convolve {
+                kernel: Kernel::edge_enhance(),
+                edges: EdgeMode::Clamp,
+            }
+            .apply(image),
+            Effect::Levels(params) => map_luts(image, params.luts()),
+            Effect::Curves(ref params) => map_luts(image, params.luts()),
+            Effect::AutoContrast { clip } => {
+                let params = LevelsParams::auto_contrast(image, clip / 100.0);
+                map_luts(image, params.luts());
+            }
+            Effect::Equalize => map_rgb(image, equalize::equalize),
+            Effect::Clahe {
+                tile_size,
+                clip_limit,
+            } => map_rgb(image, |channels| {
+                equalize::clahe(channels, tile_size, clip_limit)
+            }),
+            Effect::BrightnessContrast {
+                brightness,
+                contrast,
+            } => map_luts(image, [tone::brightness_contrast(brightness, contrast); 3]),
+            Effect::Exposure {
+                exposure,
+                offset,
+                gamma,
+            } => map_luts(image, [tone::exposure(exposure, offset, gamma); 3]),
+            Effect::Gamma { gamma } => map_luts(image, [tone::gamma(gamma); 3]),
+            Effect::ShadowsHighlights {
+                shadows,
+                highlights,
+                radius,
+            } => map_rgb(image, |channels| {
+                tone::shadows_highlights(channels, shadows, highlights, radius)
+            }),
+            Effect::HueSaturation(params) => map_rgb(image, |channels| params.apply(channels)),
+            Effect::ColorBalance(params) => map_rgb(image, |channels| params.apply(channels)),
+            Effect::ChannelMixer(params) => map_rgb(image, |channels| params.apply(channels)),
+            Effect::PhotoFilter {
+                color,
+                density,
+                preserve_luminosity,
+            } => map_rgb(image, |channels| {
+                color_balance::photo_filter(channels, color, density, preserve_luminosity)
+            }),
+            Effect::Vibrance {
+                vibrance,
+                saturation,
+            } => map_rgb(image, |channels| {
+                hue_saturation::vibrance(channels, vibrance, saturation)
+            }),
+            Effect::Invert => map_luts(image, [tone::invert(); 3]),
+            Effect::Desaturate(method) => map_rgb(image, |channels| {
+                hue_saturation::desaturate(channels, method)
+            }),
+            Effect::Threshold { level } => {
+                map_rgb(image, |channels| threshold::threshold(channels, level))
+            }
+            Effect::Posterize { levels } => map_luts(image, [tone::posterize(levels); 3]),
+            Effect::GradientMap(ref gradient) => map_rgb(image, |channels| {
+                gradient_map::gradient_map(channels, gradient)
+            }),
+            Effect::Convolve { ref kernel, edges } => {
+                let results = if kernel.keeps_flat_areas() {
+                    map_premultiplied(image, |plane| convolution::convolve(plane, kernel, edges))
+                } else {
+                    // Premultiplied, the offset has to shrink with the alpha like the colors do.
+                    let alpha = ops::to_f32(&image.channel(ChannelKind::Alpha));
+                    let unbiased = Kernel {
+                        offset: 0.0,
+                        ..kernel.clone()
+                    };
+                    map_premultiplied_colors(image, |plane| {
+                        let mut out = convolution::convolve(plane, &unbiased, edges);
+                        for (out, a) in out.as_slice_mut().iter_mut().zip(alpha.as_slice()) {
+                            *out += kernel.offset * a / 255.0;
+                        }
+                        out
+                    })
+                };
+                apply_all(image, results);
+            }
+        }
+    }
+}
+
+/// Replaces the red, green and blue channels with the results of `f`, within the selection.
+fn map_colors(image: &mut ImageBuffer, f: impl Fn(View<'_, u8>) -> Matrix<u8>) {
+    for kind in COLOR_CHANNELS.iter() {
+        let result = f(image.channel(*kind));
+        image.apply_masked(*kind, result);
+    }
+}
+
+/// Passes the red, green and blue channels through their lookup tables, within the selection.
+fn map_luts(image: &mut ImageBuffer, luts: [[u8; 256]; 3]) {
+    for (kind, lut) in COLOR_CHANNELS.iter().zip(luts.iter()) {
+        let result = ops::lookup(image.channel(*kind), lut);
+        image.apply_masked(*kind, result);
+    }
+}
+
+/// Like `map_colors`, but `f` sees the red, green and blue channels together.
+fn map_rgb(image: &mut ImageBuffer, f: impl Fn([View<'_, u8>; 3]) -> [Matrix<u8>; 3]) {
+    let results = f(COLOR_CHANNELS.map(|kind| image.channel(kind)));
+    for (kind, result) in COLOR_CHANNELS.iter().zip(results) {
+        image.apply_masked(*kind, result);
+    }
+}
+
+/// Runs `f` on every channel with the colors premultiplied by alpha, so that
+/// the color of transparent pixels does not leak into their neighbours.
+/// Results are in the red, green, blue, alpha order.
+fn map_premultiplied(
+    image: &ImageBuffer,
+    f: impl Fn(&Matrix<f32>) -> Matrix<f32>,
+) -> [Matrix<u8>; 4] {
+    let alpha = ops::to_f32(&image.channel(ChannelKind::Alpha));
+    let new_alpha = f(&alpha);
+    let [r, g, b] = COLOR_CHANNELS.map(|kind| {
+        let color = ops::premultiply(&ops::to_f32(&image.channel(kind)), &alpha);
+        ops::to_u8(&ops::unpremultiply(&f(&color), &new_alpha))
+    });
+    [r, g, b, ops::to_u8(&new_alpha)]
+}
+
+/// Like `map_premultiplied`, for filters that need to see all four channels
+/// together. Channels are passed and returned in the red, green, blue, alpha order.
+fn map_rgba_premultiplied(
+    image: &ImageBuffer,
+    f: impl Fn([View<'_, u8>; 4]) -> [Matrix<u8>; 4],
+) -> [Matrix<u8>; 4] {
+    let alpha = image.channel(ChannelKind::Alpha).to_matrix();
+    let alpha_f32 = ops::to_f32(&alpha.as_view());
+    let [r, g, b] = COLOR_CHANNELS.map(|kind| {
+        ops::to_u8(&ops::premultiply(
+            &ops::to_f32(&image.channel(kind)),
+            &alpha_f32,
+        ))
+    });
+    let [r, g, b, a] = f([r.as_view(), g.as_view(), b.as_view(), alpha.as_view()]);
+
+    let new_alpha = ops::to_f32(&a.as_view());
+    let [r, g, b] = [r, g, b].map(|color| {
+        ops::to_u8(&ops::unpremultiply(
+            &ops::to_f32(&color.as_view()),
+            &new_alpha,
+        ))
+    });
+    [r, g, b, a]
+}
+
+/// Like `map_premultiplied`, but `f` only runs on the colors and the alpha
+/// stays as it is. Suits filters that would wipe the alpha out, such as embossing.
+fn map_premultiplied_colors(
+    image: &ImageBuffer,
+    f: impl Fn(&Matrix<f32>) -> Matrix<f32>,
+) -> [Matrix<u8>; 4] {
+    let alpha = image.channel(ChannelKind::Alpha).to_matrix();
+    let alpha_f32 = ops::to_f32(&alpha.as_view());
+    let [r, g, b] = COLOR_CHANNELS.map(|kind| {
+        let color = ops::premultiply(&ops::to_f32(&image.channel(kind)), &alpha_f32);
+        ops::to_u8(&ops::unpremultiply(&f(&color), &alpha_f32))
+    });
+    [r, g, b, alpha]
+}
+
+/// Stores new values of all four channels, restricted to the selection.
+fn apply_all(image: &mut ImageBuffer, results: [Matrix<u8>; 4]) {
+    let kinds = [
+        ChannelKind::Red,
+        ChannelKind::Green,
+        ChannelKind::Blue,
+        ChannelKind::Alpha,
+    ];
+    for (kind, result) in kinds.iter().zip(results) {
+        image.apply_masked(*kind, result);
+    }
+}
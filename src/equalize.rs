@@ -0,0 +1,194 @@
+use crate::channels::{Matrix, View};
+use crate::histogram;
+use crate::ops;
+
+/// Lookup table spreading the values so that their cumulative histogram
+/// becomes a straight line from black to white.
+pub(crate) fn equalization_lut(histogram: &[u32; 256]) -> [u8; 256] {
+    let total: u64 = histogram.iter().map(|&n| n as u64).sum();
+    let darkest = histogram.iter().find(|&&n| n > 0).copied().unwrap_or(0) as u64;
+    let mut lut = [0u8; 256];
+    if total == darkest {
+        // A single value, there is nothing to spread.
+        for (value, out) in lut.iter_mut().enumerate() {
+            *out = value as u8;
+        }
+        return lut;
+    }
+    let mut cumulative = 0u64;
+    for (out, &n) in lut.iter_mut().zip(histogram.iter()) {
+        cumulative += n as u64;
+        let spread = cumulative.saturating_sub(darkest) as f64 / (total - darkest) as f64;
+        *out = (spread * 255.0).round() as u8;
+    }
+    lut
+}
+
+/// Global histogram equalization of the luminance.
+pub(crate) fn equalize(channels: [View<'_, u8>; 3]) -> [Matrix<u8>; 3] {
+    let luminance = ops::to_u8(&ops::luminance(&channels[0], &channels[1], &channels[2]));
+    let lut = equalization_lut(&histogram::count(luminance.as_view()));
+    let equalized = ops::lookup(luminance.as_view(), &lut);
+    shift_luminance(channels, &luminance, &equalized)
+}
+
+/// Contrast limited adaptive histogram equalization of the luminance. Every
+/// tile of `tile_size` pixels is equalized on its own with the histogram bins
+/// limited to `clip_limit` times their average count, and the results of the
+/// neighbouring tiles are blended to hide the seams.
+pub(crate) fn clahe(
+    channels: [View<'_, u8>; 3],
+    tile_size: u32,
+    clip_limit: f64,
+) -> [Matrix<u8>; 3] {
+    let luminance = ops::to_u8(&ops::luminance(&channels[0], &channels[1], &channels[2]));
+    let equalized = equalize_tiles(luminance.as_view(), tile_size.max(8), clip_limit);
+    shift_luminance(channels, &luminance, &equalized)
+}
+
+fn equalize_tiles(src: View<'_, u8>, tile_size: u32, clip_limit: f64) -> Matrix<u8> {
+    let (width, height) = (src.width(), src.height());
+    let columns = (width.max(1) - 1) / tile_size + 1;
+    let rows = (height.max(1) - 1) / tile_size + 1;
+
+    let mut luts = Vec::with_capacity((columns * rows) as usize);
+    for row in 0..rows {
+        for column in 0..columns {
+            let (x, y) = (column * tile_size, row * tile_size);
+            let tile = src.crop(x, y, tile_size.min(width - x), tile_size.min(height - y));
+            let mut histogram = histogram::count(tile.as_view());
+            let average = (tile.width() * tile.height()) as f64 / 256.0;
+            clip(
+                &mut histogram,
+                (clip_limit * average).ceil().max(1.0) as u32,
+            );
+            luts.push(equalization_lut(&histogram));
+        }
+    }
+
+    // Tiles around the position with the weight of the second one, measured
+    // between the tile centers.
+    let neighbours = |position: u32, tiles: u32| {
+        let t = (position as f64 + 0.5) / tile_size as f64 - 0.5;
+        let t = t.max(0.0).min((tiles - 1) as f64);
+        let first = t.floor() as u32;
+        ((first, (first + 1).min(tiles - 1)), t - first as f64)
+    };
+
+    let mut out = Matrix::new(width, height);
+    for y in 0..height {
+        let ((top, bottom), wy) = neighbours(y, rows);
+        for x in 0..width {
+            let ((left, right), wx) = neighbours(x, columns);
+            let value = src.get(x, y) as usize;
+            let mapped =
+                |row: u32, column: u32| luts[(row * columns + column) as usize][value] as f64;
+            let upper = mapped(top, left) * (1.0 - wx) + mapped(top, right) * wx;
+            let lower = mapped(bottom, left) * (1.0 - wx) + mapped(bottom, right) * wx;
+            out.set(x, y, (upper * (1.0 - wy) + lower * wy).round() as u8);
+        }
+    }
+    out
+}
+
+/// Limits every bin to `limit` and spreads the excess evenly over all of them.
+fn clip(histogram: &mut [u32; 256], limit: u32) {
+    let mut excess = 0;
+    for n in histogram.iter_mut() {
+        if *n > limit {
+            excess += *n - limit;
+            *n = limit;
+        }
+    }
+    let (share, rest) = (excess / 256, (excess % 256) as usize);
+    for (value, n) in histogram.iter_mut().enumerate() {
+        *n += share + u32::from(value < rest);
+    }
+}
+
+/// Moves every color by the change of its luminance, which keeps the hue.
+fn shift_luminance(
+    channels: [View<'_, u8>; 3],
+    old: &Matrix<u8>,
+    new: &Matrix<u8>,
+) -> [Matrix<u8>; 3] {
+    channels.map(|channel| {
+        let mut out = Matrix::new(channel.width(), channel.height());
+        for y in 0..channel.height() {
+            for x in 0..channel.width() {
+                let shift = new.get(x, y) as i32 - old.get(x, y) as i32;
+                out.set(
+                    x,
+                    y,
+                    (channel.get(x, y) as i32 + shift).max(0).min(255) as u8,
+                );
+            }
+        }
+        out
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uniform_histogram_is_nearly_identity() {
+        let lut = equalization_lut(&[10; 256]);
+        for (value, &out) in lut.iter().enumerate() {
+            assert!(
+                (out as i32 - value as i32).abs() <= 1,
+                "{} -> {}",
+                value,
+                out
+            );
+        }
+    }
+
+    #[test]
+    fn single_value_is_identity() {
+        let mut histogram = [0u32; 256];
+        histogram[90] = 40;
+        let lut = equalization_lut(&histogram);
+        for (value, &out) in lut.iter().enumerate() {
+            assert_eq!(out as usize, value);
+        }
+    }
+
+    #[test]
+    fn spreads_from_black_to_white() {
+        let mut histogram = [0u32; 256];
+        histogram[100] = 30;
+        histogram[110] = 30;
+        histogram[120] = 30;
+        let lut = equalization_lut(&histogram);
+        assert_eq!([lut[100], lut[110], lut[120]], [0, 128, 255]);
+        assert!(lut.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn clip_caps_bins_and_keeps_total() {
+        let mut histogram = [0u32; 256];
+        histogram[0] = 1000;
+        histogram[10] = 320;
+        histogram[20] = 5;
+        clip(&mut histogram, 20);
+        // 1280 excess counts, 5 for every bin.
+        assert_eq!(histogram[0], 25);
+        assert_eq!(histogram[10], 25);
+        assert_eq!(histogram[20], 10);
+        assert_eq!(histogram[255], 5);
+        assert_eq!(histogram.iter().sum::<u32>(), 1325);
+    }
+
+    #[test]
+    fn clip_gives_remainder_to_first_bins() {
+        let mut histogram = [0u32; 256];
+        histogram[200] = 13;
+        clip(&mut histogram, 10);
+        assert!(histogram[..3].iter().all(|&n| n == 1));
+        assert!(histogram[3..200].iter().all(|&n| n == 0));
+        assert_eq!(histogram[200], 10);
+        assert_eq!(histogram.iter().sum::<u32>(), 13);
+    }
+}
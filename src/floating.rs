@@ -0,0 +1,67 @@
+use std::fmt;
+
+use druid::{Point, Rect};
+
+use crate::channels::Matrix;
+use crate::clipboard;
+use crate::image_buffer::ImageBuffer;
+
+/// Pixels hovering above the active layer until they get committed into it.
+#[derive(Clone)]
+pub(crate) struct Floating {
+    pub(crate) image: ImageBuffer,
+    pub(crate) x: i32,
+    pub(crate) y: i32,
+    /// Mask in the coordinates of the layer, limiting where the pixels land.
+    pub(crate) mask: Option<Matrix<u8>>,
+}
+
+impl Floating {
+    pub(crate) fn new(image: ImageBuffer, x: i32, y: i32) -> Self {
+        Self {
+            image,
+            x,
+            y,
+            mask: None,
+        }
+    }
+
+    /// Takes the selected pixels, or the whole layer when nothing is selected,
+    /// out of the image and leaves transparency behind.
+    pub(crate) fn lift(image: &mut ImageBuffer) -> Self {
+        let clip = clipboard::cut(image);
+        Self::new(clip.image, clip.x, clip.y)
+    }
+
+    /// Area covered in the layer coordinates.
+    pub(crate) fn bounds(&self) -> Rect {
+        let (width, height) = self.image.size();
+        Rect::from_origin_size(
+            (self.x as f64, self.y as f64),
+            (width as f64, height as f64),
+        )
+    }
+
+    pub(crate) fn contains(&self, p: Point) -> bool {
+        self.bounds().contains(p)
+    }
+
+    pub(crate) fn commit(self, target: &mut ImageBuffer) {
+        target.draw_over(
+            &self.image,
+            self.x,
+            self.y,
+            self.mask.as_ref().map(|mask| mask.as_view()),
+        );
+    }
+}
+
+impl fmt::Debug for Floating {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Floating")
+            .field("image", &self.image)
+            .field("x", &self.x)
+            .field("y", &self.y)
+            .finish()
+    }
+}
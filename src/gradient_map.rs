@@ -0,0 +1,149 @@
+use std::sync::Arc;
+
+use druid::{Data, Lens};
+
+use crate::channels::{Matrix, View};
+use crate::ops;
+
+/// Colors at positions from 0 to 1, sorted by the position.
+#[derive(Clone, PartialEq, Data, Debug)]
+pub(crate) struct Gradient {
+    stops: Arc<Vec<(f64, [u8; 3])>>,
+}
+
+impl Gradient {
+    pub(crate) fn new(mut stops: Vec<(f64, [u8; 3])>) -> Self {
+        stops.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        Self {
+            stops: Arc::new(stops),
+        }
+    }
+
+    /// The same colors running from the other end.
+    pub(crate) fn reversed(&self) -> Self {
+        Self::new(
+            self.stops
+                .iter()
+                .map(|&(position, color)| (1.0 - position, color))
+                .collect(),
+        )
+    }
+
+    /// Linearly interpolated color, outside of the stops it keeps the color of the nearest one.
+    pub(crate) fn color_at(&self, position: f64) -> [u8; 3] {
+        let (first, last) = match (self.stops.first(), self.stops.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return [0, 0, 0],
+        };
+        if position <= first.0 {
+            return first.1;
+        }
+        for pair in self.stops.windows(2) {
+            let ((from, a), (to, b)) = (pair[0], pair[1]);
+            if position <= to {
+                let t = if to > from {
+                    (position - from) / (to - from)
+                } else {
+                    1.0
+                };
+                return [0, 1, 2]
+                    .map(|c| (a[c] as f64 + (b[c] as f64 - a[c] as f64) * t).round() as u8);
+            }
+        }
+        last.1
+    }
+
+    /// Lookup tables from the luminance to the red, green and blue channel.
+    fn luts(&self) -> [[u8; 256]; 3] {
+        let colors: Vec<[u8; 3]> = (0..256)
+            .map(|value| self.color_at(value as f64 / 255.0))
+            .collect();
+        [0, 1, 2].map(|c| {
+            let mut lut = [0u8; 256];
+            for (out, color) in lut.iter_mut().zip(colors.iter()) {
+                *out = color[c];
+            }
+            lut
+        })
+    }
+}
+
+/// Gradients offered by the Gradient Map dialog.
+#[derive(Clone, Copy, PartialEq, Eq, Data, Debug)]
+pub(crate) enum GradientPreset {
+    BlackWhite,
+    /// From black through the brush color to white.
+    Brush,
+    Sepia,
+    Heat,
+    Ocean,
+}
+
+impl GradientPreset {
+    pub(crate) const ALL: [GradientPreset; 5] = [
+        GradientPreset::BlackWhite,
+        GradientPreset::Brush,
+        GradientPreset::Sepia,
+        GradientPreset::Heat,
+        GradientPreset::Ocean,
+    ];
+
+    pub(crate) fn title(self) -> &'static str {
+        match self {
+            GradientPreset::BlackWhite => "Black, white",
+            GradientPreset::Brush => "Black, brush color, white",
+            GradientPreset::Sepia => "Sepia",
+            GradientPreset::Heat => "Heat",
+            GradientPreset::Ocean => "Ocean",
+        }
+    }
+
+    pub(crate) fn gradient(self, brush: [u8; 3]) -> Gradient {
+        let stops = match self {
+            GradientPreset::BlackWhite => vec![(0.0, [0, 0, 0]), (1.0, [255, 255, 255])],
+            GradientPreset::Brush => vec![(0.0, [0, 0, 0]), (0.5, brush), (1.0, [255, 255, 255])],
+            GradientPreset::Sepia => vec![
+                (0.0, [20, 10, 0]),
+                (0.5, [160, 110, 60]),
+                (1.0, [255, 245, 225]),
+            ],
+            GradientPreset::Heat => vec![
+                (0.0, [0, 0, 0]),
+                (0.35, [190, 0, 0]),
+                (0.7, [255, 200, 0]),
+                (1.0, [255, 255, 255]),
+            ],
+            GradientPreset::Ocean => vec![
+                (0.0, [0, 10, 40]),
+                (0.5, [0, 110, 160]),
+                (1.0, [200, 255, 240]),
+            ],
+        };
+        Gradient::new(stops)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Data, Debug, Lens)]
+pub(crate) struct GradientMapParams {
+    pub(crate) preset: GradientPreset,
+    /// Maps the shadows to the end of the gradient.
+    pub(crate) reverse: bool,
+}
+
+impl GradientMapParams {
+    pub(crate) fn gradient(&self, brush: [u8; 3]) -> Gradient {
+        let gradient = self.preset.gradient(brush);
+        if self.reverse {
+            gradient.reversed()
+        } else {
+            gradient
+        }
+    }
+}
+
+/// Replaces every color with the color of the gradient at its luminance.
+pub(crate) fn gradient_map(channels: [View<'_, u8>; 3], gradient: &Gradient) -> [Matrix<u8>; 3] {
+    let luminance = ops::to_u8(&ops::luminance(&channels[0], &channels[1], &channels[2]));
+    let luts = gradient.luts();
+    [0, 1, 2].map(|c| ops::lookup(luminance.as_view(), &luts[c]))
+}
@@ -1,12 +1,47 @@
 use druid::piet::{ImageFormat, InterpolationMode};
 use druid::{
-    BoxConstraints, Env, Event, EventCtx, LayoutCtx, LifeCycle, LifeCycleCtx, PaintCtx, Point,
-    Rect, RenderContext, Size, UpdateCtx, Widget,
+    BoxConstraints, Color, Env, Event, EventCtx, LayoutCtx, LifeCycle, LifeCycleCtx, PaintCtx,
+    Point, Rect, RenderContext, Size, UpdateCtx, Widget,
 };
 
+use crate::channels::View;
 use crate::image_buffer::ImageBuffer;
+use crate::levels::ToneChannel;
+use crate::ops;
 use crate::state::{AppData, ChannelKind};
 
+/// Number of pixels of every value in the channel.
+pub(crate) fn count(channel: View<'_, u8>) -> [u32; 256] {
+    let mut histogram = [0u32; 256];
+    for value in channel.as_slice().unwrap().iter().copied() {
+        histogram[value as usize] += 1;
+    }
+    histogram
+}
+
+/// Histograms of the red, green and blue channel.
+pub(crate) fn count_colors(image: &ImageBuffer) -> [[u32; 256]; 3] {
+    [ChannelKind::Red, ChannelKind::Green, ChannelKind::Blue].map(|kind| count(image.channel(kind)))
+}
+
+/// Sum of the histograms of the red, green and blue channel.
+pub(crate) fn count_composite(image: &ImageBuffer) -> [u32; 256] {
+    let mut total = [0u32; 256];
+    for histogram in count_colors(image).iter() {
+        for (total, count) in total.iter_mut().zip(histogram.iter()) {
+            *total += count;
+        }
+    }
+    total
+}
+
+/// Histogram of the perceived brightness, as used by the threshold.
+pub(crate) fn count_luminance(image: &ImageBuffer) -> [u32; 256] {
+    let [r, g, b] =
+        [ChannelKind::Red, ChannelKind::Green, ChannelKind::Blue].map(|kind| image.channel(kind));
+    count(ops::to_u8(&ops::luminance(&r, &g, &b)).as_view())
+}
+
 pub struct Histogram {}
 
 impl Widget<AppData> for Histogram {
@@ -40,11 +75,7 @@ impl Widget<AppData> for Histogram {
             let mut normalized = [[0u8; 256]; 3];
 
             for channel in [ChannelKind::Red, ChannelKind::Green, ChannelKind::Blue] {
-                let mut histogram = [0u32; 256];
-                for value in image.channel(channel).as_slice().unwrap().iter().copied() {
-                    histogram[value as usize] += 1;
-                }
-
+                let histogram = count(image.channel(channel));
                 let max_count: usize = histogram.iter().map(|it| *it as usize).max().unwrap();
                 #[allow(clippy::needless_range_loop)]
                 for value in 0..256 {
@@ -66,8 +97,15 @@ impl Widget<AppData> for Histogram {
             result
         }
 
-        let image_data =
-            make_image_data(data.layers[0].borrow().data.as_buffer().unwrap(), 256, 128);
+        let image_data = make_image_data(
+            data.layers[data.active_layer()]
+                .borrow()
+                .data
+                .as_buffer()
+                .unwrap(),
+            256,
+            128,
+        );
         let image = ctx
             .make_image(256, 128, &image_data, ImageFormat::RgbaSeparate)
             .unwrap();
@@ -80,3 +118,218 @@ impl Widget<AppData> for Histogram {
         );
     }
 }
+
+/// Histogram of the channel edited in the Levels dialog with markers of its
+/// input black point, midtone and white point.
+#[derive(Default)]
+pub(crate) struct LevelsHistogram {
+    /// Histogram last counted, along with the layer, its version and the
+    /// channel it was counted for.
+    cache: Option<(HistogramKey, [u32; 256])>,
+}
+
+type HistogramKey = (usize, u64, ToneChannel);
+
+impl LevelsHistogram {
+    fn key(data: &AppData) -> HistogramKey {
+        (
+            data.active_layer(),
+            data.layers_version.get(),
+            data.levels_channel,
+        )
+    }
+
+    fn is_current(&self, data: &AppData) -> bool {
+        matches!(self.cache, Some((key, _)) if key == Self::key(data))
+    }
+
+    /// Counts the histogram again if the layer or the channel changed since.
+    fn histogram(&mut self, data: &AppData) -> &[u32; 256] {
+        if !self.is_current(data) {
+            let layer = data.layers[data.active_layer()].borrow();
+            let image = layer.data.as_buffer().unwrap();
+            let histogram = match data.levels_channel {
+                ToneChannel::Composite => count_composite(image),
+                ToneChannel::Red => count(image.channel(ChannelKind::Red)),
+                ToneChannel::Green => count(image.channel(ChannelKind::Green)),
+                ToneChannel::Blue => count(image.channel(ChannelKind::Blue)),
+            };
+            self.cache = Some((Self::key(data), histogram));
+        }
+        &self.cache.as_ref().unwrap().1
+    }
+}
+
+impl Widget<AppData> for LevelsHistogram {
+    fn event(&mut self, _ctx: &mut EventCtx, _event: &Event, _data: &mut AppData, _env: &Env) {}
+
+    fn lifecycle(
+        &mut self,
+        _ctx: &mut LifeCycleCtx,
+        _event: &LifeCycle,
+        _data: &AppData,
+        _env: &Env,
+    ) {
+    }
+
+    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &AppData, data: &AppData, _env: &Env) {
+        // Layers are edited in place, so their version tells whether the
+        // histogram is out of date.
+        if old_data.levels != data.levels || !self.is_current(data) {
+            ctx.request_paint();
+        }
+    }
+
+    fn layout(
+        &mut self,
+        _layout_ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        _data: &AppData,
+        _env: &Env,
+    ) -> Size {
+        bc.max()
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &AppData, _env: &Env) {
+        let color = match data.levels_channel {
+            ToneChannel::Composite => Color::grey8(80),
+            ToneChannel::Red => Color::rgb8(200, 0, 0),
+            ToneChannel::Green => Color::rgb8(0, 160, 0),
+            ToneChannel::Blue => Color::rgb8(0, 0, 200),
+        };
+        let histogram = *self.histogram(data);
+
+        let size = ctx.size();
+        ctx.fill(size.to_rect(), &Color::WHITE);
+
+        let max_count = histogram.iter().copied().max().unwrap_or(0).max(1) as f64;
+        let step = size.width / 256.0;
+        for (value, &n) in histogram.iter().enumerate() {
+            let height = n as f64 / max_count * size.height;
+            let bar = Rect::new(
+                value as f64 * step,
+                size.height - height,
+                (value + 1) as f64 * step,
+                size.height,
+            );
+            ctx.fill(bar, &color);
+        }
+
+        let levels = data.levels.get(data.levels_channel);
+        let markers = [
+            (levels.input_black, Color::BLACK),
+            (levels.midtone(), Color::grey8(128)),
+            (levels.input_white, Color::grey8(200)),
+        ];
+        for &(value, ref marker) in markers.iter() {
+            let x = (value + 0.5) * step;
+            let line = Rect::new(x - 1.0, 0.0, x + 1.0, size.height);
+            ctx.fill(line, marker);
+        }
+    }
+}
+
+/// Luminance histogram of the Threshold dialog, clicking or dragging on it
+/// moves the threshold level.
+#[derive(Default)]
+pub(crate) struct ThresholdHistogram {
+    /// Histogram last counted, along with the layer and its version.
+    cache: Option<((usize, u64), [u32; 256])>,
+}
+
+impl ThresholdHistogram {
+    fn key(data: &AppData) -> (usize, u64) {
+        (data.active_layer(), data.layers_version.get())
+    }
+
+    fn is_current(&self, data: &AppData) -> bool {
+        matches!(self.cache, Some((key, _)) if key == Self::key(data))
+    }
+
+    /// Counts the histogram again if the layer changed since.
+    fn histogram(&mut self, data: &AppData) -> &[u32; 256] {
+        if !self.is_current(data) {
+            let layer = data.layers[data.active_layer()].borrow();
+            let histogram = count_luminance(layer.data.as_buffer().unwrap());
+            self.cache = Some((Self::key(data), histogram));
+        }
+        &self.cache.as_ref().unwrap().1
+    }
+
+    fn set_level(ctx: &EventCtx, x: f64, data: &mut AppData) {
+        let level = (x / ctx.size().width * 256.0).floor();
+        data.threshold = level.max(0.0).min(255.0);
+    }
+}
+
+impl Widget<AppData> for ThresholdHistogram {
+    fn event(&mut self, ctx: &mut EventCtx, event: &Event, data: &mut AppData, _env: &Env) {
+        match event {
+            Event::MouseDown(e) => {
+                ctx.set_active(true);
+                Self::set_level(ctx, e.pos.x, data);
+            }
+            Event::MouseMove(e) if ctx.is_active() => Self::set_level(ctx, e.pos.x, data),
+            Event::MouseUp(_) => ctx.set_active(false),
+            _ => {}
+        }
+    }
+
+    fn lifecycle(
+        &mut self,
+        _ctx: &mut LifeCycleCtx,
+        _event: &LifeCycle,
+        _data: &AppData,
+        _env: &Env,
+    ) {
+    }
+
+    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &AppData, data: &AppData, _env: &Env) {
+        if old_data.threshold != data.threshold || !self.is_current(data) {
+            ctx.request_paint();
+        }
+    }
+
+    fn layout(
+        &mut self,
+        _layout_ctx: &mut LayoutCtx,
+        bc: &BoxConstraints,
+        _data: &AppData,
+        _env: &Env,
+    ) -> Size {
+        bc.max()
+    }
+
+    fn paint(&mut self, ctx: &mut PaintCtx, data: &AppData, _env: &Env) {
+        let histogram = *self.histogram(data);
+
+        let size = ctx.size();
+        ctx.fill(size.to_rect(), &Color::WHITE);
+
+        let max_count = histogram.iter().copied().max().unwrap_or(0).max(1) as f64;
+        let step = size.width / 256.0;
+        let level = data.threshold.round() as usize;
+        for (value, &n) in histogram.iter().enumerate() {
+            let height = n as f64 / max_count * size.height;
+            let bar = Rect::new(
+                value as f64 * step,
+                size.height - height,
+                (value + 1) as f64 * step,
+                size.height,
+            );
+            // Values that become white are drawn lighter.
+            let color = if value >= level {
+                Color::grey8(160)
+            } else {
+                Color::grey8(40)
+            };
+            ctx.fill(bar, &color);
+        }
+
+        let x = data.threshold.round() * step;
+        ctx.fill(
+            Rect::new(x - 1.0, 0.0, x + 1.0, size.height),
+            &Color::rgb8(200, 40, 40),
+        );
+    }
+}
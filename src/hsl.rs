@@ -0,0 +1,110 @@
+/// Hue is in degrees from 0 to 360, the other components and the colors are in the 0-1 range.
+pub(crate) fn rgb_to_hsl(r: f32, g: f32, b: f32) -> (f32, f32, f32) {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let lightness = (max + min) / 2.0;
+    let chroma = max - min;
+    if chroma == 0.0 {
+        return (0.0, 0.0, lightness);
+    }
+    let saturation = chroma / (1.0 - (2.0 * lightness - 1.0).abs());
+    (hue(r, g, b, max, chroma), saturation.min(1.0), lightness)
+}
+
+pub(crate) fn hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> (f32, f32, f32) {
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    from_chroma(hue, chroma, lightness - chroma / 2.0)
+}
+
+pub(crate) fn rgb_to_hsv(r: f32, g: f32, b: f32) -> (f32, f32, f32) {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let chroma = max - min;
+    if chroma == 0.0 {
+        return (0.0, 0.0, max);
+    }
+    (hue(r, g, b, max, chroma), chroma / max, max)
+}
+
+pub(crate) fn hsv_to_rgb(hue: f32, saturation: f32, value: f32) -> (f32, f32, f32) {
+    let chroma = value * saturation;
+    from_chroma(hue, chroma, value - chroma)
+}
+
+fn hue(r: f32, g: f32, b: f32, max: f32, chroma: f32) -> f32 {
+    let sector = if max == r {
+        (g - b) / chroma
+    } else if max == g {
+        (b - r) / chroma + 2.0
+    } else {
+        (r - g) / chroma + 4.0
+    };
+    (sector * 60.0).rem_euclid(360.0)
+}
+
+/// Color of the given hue and chroma, lifted by `min` in all channels.
+fn from_chroma(hue: f32, chroma: f32, min: f32) -> (f32, f32, f32) {
+    let sector = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+    let (r, g, b) = match sector as u32 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    (r + min, g + min, b + min)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close((r, g, b): (f32, f32, f32), (er, eg, eb): (f32, f32, f32)) {
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-5;
+        assert!(
+            close(r, er) && close(g, eg) && close(b, eb),
+            "{:?} != {:?}",
+            (r, g, b),
+            (er, eg, eb)
+        );
+    }
+
+    /// Colors on a coarse grid of the RGB cube, including the greys.
+    fn colors() -> impl Iterator<Item = (f32, f32, f32)> {
+        let steps = [0.0, 0.2, 0.5, 0.8, 1.0];
+        steps.iter().flat_map(move |&r| {
+            steps
+                .iter()
+                .flat_map(move |&g| steps.iter().map(move |&b| (r, g, b)))
+        })
+    }
+
+    #[test]
+    fn hsl_round_trip() {
+        for (r, g, b) in colors() {
+            let (h, s, l) = rgb_to_hsl(r, g, b);
+            assert_close(hsl_to_rgb(h, s, l), (r, g, b));
+        }
+    }
+
+    #[test]
+    fn hsv_round_trip() {
+        for (r, g, b) in colors() {
+            let (h, s, v) = rgb_to_hsv(r, g, b);
+            assert_close(hsv_to_rgb(h, s, v), (r, g, b));
+        }
+    }
+
+    #[test]
+    fn known_colors() {
+        assert_close(rgb_to_hsl(1.0, 0.0, 0.0), (0.0, 1.0, 0.5));
+        assert_close(rgb_to_hsl(0.0, 1.0, 0.0), (120.0, 1.0, 0.5));
+        assert_close(rgb_to_hsv(0.0, 0.0, 1.0), (240.0, 1.0, 1.0));
+        assert_close(rgb_to_hsv(0.5, 0.25, 0.5), (300.0, 0.5, 0.5));
+        assert_close(rgb_to_hsl(0.4, 0.4, 0.4), (0.0, 0.0, 0.4));
+        assert_close(hsl_to_rgb(60.0, 1.0, 0.5), (1.0, 1.0, 0.0));
+        assert_close(hsv_to_rgb(180.0, 1.0, 1.0), (0.0, 1.0, 1.0));
+    }
+}
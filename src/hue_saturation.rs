@@ -0,0 +1,198 @@
+use druid::{Data, Lens};
+
+use crate::channels::{Matrix, View};
+use crate::hsl;
+use crate::ops;
+
+/// Shift of hue, saturation and lightness.
+#[derive(Clone, Copy, PartialEq, Data, Debug, Lens)]
+pub(crate) struct HslShift {
+    /// In degrees, from -180 to 180.
+    pub(crate) hue: f64,
+    /// In percent, from -100 to 100.
+    pub(crate) saturation: f64,
+    /// In percent, from -100 to 100.
+    pub(crate) lightness: f64,
+}
+
+impl HslShift {
+    pub(crate) const NONE: HslShift = HslShift {
+        hue: 0.0,
+        saturation: 0.0,
+        lightness: 0.0,
+    };
+
+    fn scaled(self, weight: f64) -> HslShift {
+        HslShift {
+            hue: self.hue * weight,
+            saturation: self.saturation * weight,
+            lightness: self.lightness * weight,
+        }
+    }
+
+    fn add(self, other: HslShift) -> HslShift {
+        HslShift {
+            hue: self.hue + other.hue,
+            saturation: self.saturation + other.saturation,
+            lightness: self.lightness + other.lightness,
+        }
+    }
+}
+
+/// Colors whose shift the dialog is editing.
+#[derive(Clone, Copy, PartialEq, Eq, Data, Debug)]
+pub(crate) enum HueRange {
+    Master,
+    Reds,
+    Yellows,
+    Greens,
+    Cyans,
+    Blues,
+    Magentas,
+}
+
+impl HueRange {
+    pub(crate) const ALL: [HueRange; 7] = [
+        HueRange::Master,
+        HueRange::Reds,
+        HueRange::Yellows,
+        HueRange::Greens,
+        HueRange::Cyans,
+        HueRange::Blues,
+        HueRange::Magentas,
+    ];
+
+    pub(crate) fn title(self) -> &'static str {
+        match self {
+            HueRange::Master => "Master",
+            HueRange::Reds => "Reds",
+            HueRange::Yellows => "Yellows",
+            HueRange::Greens => "Greens",
+            HueRange::Cyans => "Cyans",
+            HueRange::Blues => "Blues",
+            HueRange::Magentas => "Magentas",
+        }
+    }
+}
+
+/// Hues within this distance from the center of a range are fully affected by it.
+const RANGE_WIDTH: f64 = 15.0;
+/// Distance over which the effect of a range fades out.
+const RANGE_FALLOFF: f64 = 30.0;
+
+#[derive(Clone, Copy, PartialEq, Data, Debug, Lens)]
+pub(crate) struct HueSaturationParams {
+    pub(crate) master: HslShift,
+    /// Shifts of the six ranges starting at red, 60 degrees apart.
+    pub(crate) ranges: [HslShift; 6],
+    /// Paints everything with a single hue taken from the master hue,
+    /// with the saturation given by the master saturation.
+    pub(crate) colorize: bool,
+}
+
+impl HueSaturationParams {
+    pub(crate) const NONE: HueSaturationParams = HueSaturationParams {
+        master: HslShift::NONE,
+        ranges: [HslShift::NONE; 6],
+        colorize: false,
+    };
+
+    pub(crate) fn get(&self, range: HueRange) -> &HslShift {
+        match range {
+            HueRange::Master => &self.master,
+            _ => &self.ranges[range as usize - 1],
+        }
+    }
+
+    pub(crate) fn get_mut(&mut self, range: HueRange) -> &mut HslShift {
+        match range {
+            HueRange::Master => &mut self.master,
+            _ => &mut self.ranges[range as usize - 1],
+        }
+    }
+
+    /// Total shift of a pixel of the given hue.
+    fn shift(&self, hue: f64) -> HslShift {
+        let mut shift = self.master;
+        for (i, range) in self.ranges.iter().enumerate() {
+            let distance = (hue - i as f64 * 60.0).rem_euclid(360.0);
+            let distance = distance.min(360.0 - distance);
+            let weight = 1.0 - ((distance - RANGE_WIDTH) / RANGE_FALLOFF).max(0.0).min(1.0);
+            if weight > 0.0 {
+                shift = shift.add(range.scaled(weight));
+            }
+        }
+        shift
+    }
+
+    pub(crate) fn apply(&self, channels: [View<'_, u8>; 3]) -> [Matrix<u8>; 3] {
+        ops::map_pixels(channels, |r, g, b| {
+            let (h, s, l) = hsl::rgb_to_hsl(r, g, b);
+            if self.colorize {
+                let hue = self.master.hue.rem_euclid(360.0) as f32;
+                let saturation = ((self.master.saturation + 100.0) / 200.0) as f32;
+                let lightness = shift_lightness(l, self.master.lightness);
+                return hsl::hsl_to_rgb(hue, saturation, lightness);
+            }
+
+            // Grays have no hue, only the master shift applies to them.
+            let shift = if s > 0.0 {
+                self.shift(h as f64)
+            } else {
+                self.master
+            };
+            let hue = h + shift.hue as f32;
+            let saturation = (s * (1.0 + shift.saturation as f32 / 100.0))
+                .max(0.0)
+                .min(1.0);
+            hsl::hsl_to_rgb(hue, saturation, shift_lightness(l, shift.lightness))
+        })
+    }
+}
+
+/// Moves the lightness towards white or black by the given percentage.
+fn shift_lightness(lightness: f32, amount: f64) -> f32 {
+    let amount = (amount / 100.0).max(-1.0).min(1.0) as f32;
+    if amount > 0.0 {
+        lightness + (1.0 - lightness) * amount
+    } else {
+        lightness * (1.0 + amount)
+    }
+}
+
+/// Raises the saturation of dull colors more than of the already saturated ones.
+/// Both amounts are in percent from -100 to 100.
+pub(crate) fn vibrance(
+    channels: [View<'_, u8>; 3],
+    vibrance: f64,
+    saturation: f64,
+) -> [Matrix<u8>; 3] {
+    let vibrance = (vibrance / 100.0) as f32;
+    let saturation = (saturation / 100.0) as f32;
+    ops::map_pixels(channels, |r, g, b| {
+        let (h, s, v) = hsl::rgb_to_hsv(r, g, b);
+        let s = s * (1.0 + vibrance * (1.0 - s)) * (1.0 + saturation);
+        hsl::hsv_to_rgb(h, s.max(0.0).min(1.0), v)
+    })
+}
+
+/// How a color is reduced to a single gray value.
+#[derive(Clone, Copy, PartialEq, Eq, Data, Debug)]
+pub(crate) enum Desaturation {
+    /// Weighted by the perceived brightness of the channels.
+    Luminosity,
+    Average,
+    /// Middle of the lowest and the highest channel, the HSL lightness.
+    Lightness,
+}
+
+pub(crate) fn desaturate(channels: [View<'_, u8>; 3], method: Desaturation) -> [Matrix<u8>; 3] {
+    ops::map_pixels(channels, |r, g, b| {
+        let gray = match method {
+            Desaturation::Luminosity => ops::luma(r, g, b),
+            Desaturation::Average => (r + g + b) / 3.0,
+            Desaturation::Lightness => (r.max(g).max(b) + r.min(g).min(b)) / 2.0,
+        };
+        (gray, gray, gray)
+    })
+}
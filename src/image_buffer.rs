@@ -4,10 +4,11 @@ use std::fmt;
 use std::path::Path;
 
 use druid::piet::{ImageFormat, InterpolationMode};
-use druid::{Affine, Color, Data, PaintCtx, RenderContext, Size};
+use druid::{Affine, Data, PaintCtx, RenderContext, Size};
 
 use crate::channels::{Matrix, View, ViewMut};
 use crate::state::ChannelKind;
+use crate::utils::blend;
 
 /// Stored Image data.
 #[derive(Clone, Data)]
@@ -19,6 +20,8 @@ pub struct ImageBuffer {
     #[data(ignore)]
     hot_selection: Matrix<u8>,
     #[data(ignore)]
+    selection_version: u64,
+    #[data(ignore)]
     pub(crate) interleaved: RefCell<Vec<u8>>,
     width: u32,
     height: u32,
@@ -39,6 +42,10 @@ impl ImageBuffer {
     }
 
     pub(crate) fn channel_mut(&mut self, kind: ChannelKind) -> ViewMut<'_, u8> {
+        if let ChannelKind::Selection | ChannelKind::HotSelection = kind {
+            self.selection_version += 1;
+        }
+
         match kind {
             ChannelKind::Red => self.pixels[0].as_view_mut(),
             ChannelKind::Green => self.pixels[1].as_view_mut(),
@@ -49,19 +56,144 @@ impl ImageBuffer {
         }
     }
 
+    /// Replaces the whole channel with a matrix of the same size.
+    pub(crate) fn replace_channel(&mut self, kind: ChannelKind, matrix: Matrix<u8>) {
+        assert_eq!((matrix.width(), matrix.height()), self.size());
+        if let ChannelKind::Selection | ChannelKind::HotSelection = kind {
+            self.selection_version += 1;
+        }
+
+        match kind {
+            ChannelKind::Red => self.pixels[0] = matrix,
+            ChannelKind::Green => self.pixels[1] = matrix,
+            ChannelKind::Blue => self.pixels[2] = matrix,
+            ChannelKind::Alpha => self.pixels[3] = matrix,
+            ChannelKind::Selection => self.selection = matrix,
+            ChannelKind::HotSelection => self.hot_selection = matrix,
+        }
+    }
+
+    /// Color channel along with the selection, so the latter can be used as a mask.
+    pub(crate) fn channel_and_selection_mut(
+        &mut self,
+        kind: ChannelKind,
+    ) -> (ViewMut<'_, u8>, View<'_, u8>) {
+        let index = match kind {
+            ChannelKind::Red => 0,
+            ChannelKind::Green => 1,
+            ChannelKind::Blue => 2,
+            ChannelKind::Alpha => 3,
+            ChannelKind::Selection | ChannelKind::HotSelection => {
+                panic!("{} is not a color channel", kind)
+            }
+        };
+        (self.pixels[index].as_view_mut(), self.selection.as_view())
+    }
+
+    /// Whether any pixel is at least partially selected.
+    pub(crate) fn has_selection(&self) -> bool {
+        self.selection.as_slice().iter().any(|&value| value != 0)
+    }
+
+    /// Stores `result` in the channel, restricted to the selection when there is any.
+    /// Partially selected pixels get a proportional share of the change.
+    pub(crate) fn apply_masked(&mut self, kind: ChannelKind, result: Matrix<u8>) {
+        if !self.has_selection() {
+            self.replace_channel(kind, result);
+            return;
+        }
+
+        let (mut channel, mask) = self.channel_and_selection_mut(kind);
+        for y in 0..channel.height() {
+            for x in 0..channel.width() {
+                let value = blend(channel.get(x, y), result.get(x, y), mask.get(x, y));
+                channel.set(x, y, value);
+            }
+        }
+    }
+
     pub(crate) fn selection_mut(&mut self) -> (ViewMut<'_, u8>, ViewMut<'_, u8>) {
+        self.selection_version += 1;
         (
             self.selection.as_view_mut(),
             self.hot_selection.as_view_mut(),
         )
     }
 
-    #[allow(unused)]
+    /// Rebuilds every plane, the selection included, with `f`. The planes may change
+    /// their size, but all of them have to end up with the same one.
+    pub(crate) fn map_planes(
+        &mut self,
+        mut f: impl FnMut(ChannelKind, View<'_, u8>) -> Matrix<u8>,
+    ) {
+        let kinds = [
+            ChannelKind::Red,
+            ChannelKind::Green,
+            ChannelKind::Blue,
+            ChannelKind::Alpha,
+        ];
+        for (plane, kind) in self.pixels.iter_mut().zip(kinds.iter()) {
+            *plane = f(*kind, plane.as_view());
+        }
+        self.selection = f(ChannelKind::Selection, self.selection.as_view());
+        self.hot_selection = f(ChannelKind::HotSelection, self.hot_selection.as_view());
+        self.selection_version += 1;
+
+        self.width = self.pixels[0].width();
+        self.height = self.pixels[0].height();
+        assert!(self
+            .pixels
+            .iter()
+            .chain([&self.selection, &self.hot_selection])
+            .all(|m| m.width() == self.width && m.height() == self.height));
+        self.interleaved = RefCell::new(vec![0; self.width as usize * self.height as usize * 4]);
+    }
+
+    /// Keeps only the given area of the image and of the selection.
+    pub(crate) fn crop(&mut self, x: u32, y: u32, width: u32, height: u32) {
+        assert!(x + width <= self.width && y + height <= self.height);
+        self.map_planes(|_, plane| plane.crop(x, y, width, height));
+    }
+
+    /// Changes the canvas to the given size with the current image placed at (x, y).
+    /// Uncovered pixels get the `fill` color and stay unselected.
+    pub(crate) fn resize_canvas(&mut self, width: u32, height: u32, x: i32, y: i32, fill: [u8; 4]) {
+        self.map_planes(|kind, plane| {
+            let value = match kind {
+                ChannelKind::Red => fill[0],
+                ChannelKind::Green => fill[1],
+                ChannelKind::Blue => fill[2],
+                ChannelKind::Alpha => fill[3],
+                ChannelKind::Selection | ChannelKind::HotSelection => 0,
+            };
+
+            let mut out = Matrix::new(width, height);
+            out.as_slice_mut().iter_mut().for_each(|v| *v = value);
+            for sy in 0..plane.height() {
+                let dy = y + sy as i32;
+                if dy < 0 || dy >= height as i32 {
+                    continue;
+                }
+                for sx in 0..plane.width() {
+                    let dx = x + sx as i32;
+                    if dx >= 0 && dx < width as i32 {
+                        out.set(dx as u32, dy as u32, plane.get(sx, sy));
+                    }
+                }
+            }
+            out
+        });
+    }
+
+    /// Changes every time the selection or the hot selection is borrowed mutably.
+    pub(crate) fn selection_version(&self) -> u64 {
+        self.selection_version
+    }
+
     pub(crate) fn width(&self) -> u32 {
         self.width
     }
 
-    #[allow(unused)]
     pub(crate) fn height(&self) -> u32 {
         self.height
     }
@@ -106,17 +238,46 @@ impl ImageBuffer {
             a.as_slice_mut()[i] = pix[3];
         }
 
+        Self::from_channels([r, g, b, a])
+    }
+
+    /// Fully transparent image.
+    pub fn new(width: u32, height: u32) -> ImageBuffer {
+        Self::from_channels([
+            Matrix::new(width, height),
+            Matrix::new(width, height),
+            Matrix::new(width, height),
+            Matrix::new(width, height),
+        ])
+    }
+
+    /// Build an image from red, green, blue and alpha channels of the same size.
+    pub(crate) fn from_channels(pixels: [Matrix<u8>; 4]) -> ImageBuffer {
+        let width = pixels[0].width();
+        let height = pixels[0].height();
+        assert!(pixels
+            .iter()
+            .all(|m| m.width() == width && m.height() == height));
+
         ImageBuffer {
-            interleaved: RefCell::new(vec![0; size_in_bytes]),
-            pixels: [r, g, b, a],
+            interleaved: RefCell::new(vec![0; width as usize * height as usize * 4]),
+            pixels,
             selection: Matrix::new(width, height),
             hot_selection: Matrix::new(width, height),
+            selection_version: 0,
             width,
             height,
             format: ImageFormat::RgbaSeparate,
         }
     }
 
+    /// Opaque image with the same value in all color channels.
+    pub(crate) fn from_gray(gray: Matrix<u8>) -> ImageBuffer {
+        let mut alpha = Matrix::new(gray.width(), gray.height());
+        alpha.as_slice_mut().fill(255);
+        Self::from_channels([gray.clone(), gray.clone(), gray, alpha])
+    }
+
     /// Attempt to load an image from the file at the provided path.
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
         let image_data = image::open(path).map_err(|e| e)?;
@@ -137,17 +298,13 @@ impl ImageBuffer {
     ) {
         ctx.with_save(|ctx| {
             let size = self.get_size();
-            // Background around the image
-            ctx.fill(size.to_rect(), &Color::rgb8(38, 38, 38));
-
             ctx.transform(offset_matrix);
             let im = ctx
                 .make_image(
                     size.width as usize,
                     size.height as usize,
                     &*self.interleaved.borrow(),
-                    // FIXME: hardcoded format... should be `self.format`
-                    ImageFormat::RgbaPremul,
+                    self.format,
                 )
                 .unwrap();
             ctx.draw_image(&im, size.to_rect(), interpolation);
@@ -155,6 +312,86 @@ impl ImageBuffer {
     }
 }
 
+impl ImageBuffer {
+    /// Interleaves the channels into the buffer used for drawing, hidden color
+    /// channels are replaced with zeros.
+    pub(crate) fn refresh_interleaved(&self, visible: [bool; 3]) {
+        let zeros = Matrix::new(self.width, self.height);
+        let channel = |index: usize| {
+            if visible[index] {
+                self.pixels[index].as_slice()
+            } else {
+                zeros.as_slice()
+            }
+        };
+
+        merge_channels(
+            channel(0),
+            channel(1),
+            channel(2),
+            self.pixels[3].as_slice(),
+            &mut self.interleaved.borrow_mut(),
+        );
+    }
+
+    /// Non-premultiplied RGBA bytes of the whole image.
+    pub(crate) fn to_rgba(&self) -> Vec<u8> {
+        let mut rgba = vec![0; self.width as usize * self.height as usize * 4];
+        merge_channels(
+            self.pixels[0].as_slice(),
+            self.pixels[1].as_slice(),
+            self.pixels[2].as_slice(),
+            self.pixels[3].as_slice(),
+            &mut rgba,
+        );
+        rgba
+    }
+
+    /// Composites `src` placed at (x, y) over this image. The optional mask is in the
+    /// coordinates of this image and scales the opacity of `src`.
+    pub(crate) fn draw_over(
+        &mut self,
+        src: &ImageBuffer,
+        x: i32,
+        y: i32,
+        mask: Option<View<'_, u8>>,
+    ) {
+        for sy in 0..src.height {
+            let dy = y + sy as i32;
+            if dy < 0 || dy >= self.height as i32 {
+                continue;
+            }
+
+            for sx in 0..src.width {
+                let dx = x + sx as i32;
+                if dx < 0 || dx >= self.width as i32 {
+                    continue;
+                }
+                let (dx, dy) = (dx as u32, dy as u32);
+
+                let mut sa = src.pixels[3].get(sx, sy) as u32;
+                if let Some(mask) = &mask {
+                    sa = sa * mask.get(dx, dy) as u32 / 255;
+                }
+                if sa == 0 {
+                    continue;
+                }
+
+                // Straight alpha "over" operator, alpha is scaled by 255 here.
+                let da = self.pixels[3].get(dx, dy) as u32;
+                let out_a = sa * 255 + da * (255 - sa);
+                for i in 0..3 {
+                    let sc = src.pixels[i].get(sx, sy) as u32;
+                    let dc = self.pixels[i].get(dx, dy) as u32;
+                    let c = (sc * sa * 255 + dc * da * (255 - sa) + out_a / 2) / out_a;
+                    self.pixels[i].set(dx, dy, c as u8);
+                }
+                self.pixels[3].set(dx, dy, ((out_a + 127) / 255) as u8);
+            }
+        }
+    }
+}
+
 #[inline(never)]
 pub fn merge_channels(r: &[u8], g: &[u8], b: &[u8], a: &[u8], rgba: &mut [u8]) {
     assert_eq!(r.len(), g.len());
@@ -214,7 +451,10 @@ pub fn merge_channels(r: &[u8], g: &[u8], b: &[u8], a: &[u8], rgba: &mut [u8]) {
     }
 
     if is_x86_feature_detected!("avx2") && is_x86_feature_detected!("avx") {
-        unsafe { merge_avx2(r, g, b, a, rgba) };
+        // The vectorized loop always handles 32 pixels at once, the rest is done one by one.
+        let n = r.len() / 32 * 32;
+        unsafe { merge_avx2(&r[..n], &g[..n], &b[..n], &a[..n], &mut rgba[..n * 4]) };
+        merge_scalar(&r[n..], &g[n..], &b[n..], &a[n..], &mut rgba[n * 4..]);
     } else {
         merge_scalar(r, g, b, a, rgba);
     }
@@ -1,12 +1,30 @@
-use druid::piet::InterpolationMode;
+use std::time::Duration;
+
+use druid::kurbo::BezPath;
+use druid::piet::{InterpolationMode, StrokeStyle};
 use druid::widget::Viewport;
 use druid::{
-    BoxConstraints, Code, Cursor, Env, Event, EventCtx, LayoutCtx, LifeCycle, LifeCycleCtx,
-    PaintCtx, Point, Rect, RenderContext, Size, UpdateCtx, Widget,
+    Affine, BoxConstraints, Code, Color, Command, Cursor, Data, Env, Event, EventCtx, LayoutCtx,
+    LifeCycle, LifeCycleCtx, PaintCtx, Point, Rect, RenderContext, Size, TimerToken, UpdateCtx,
+    Widget,
 };
 
-use crate::state::AppData;
-use crate::tools::{BrushSelectionTool, DrawTool, MovingTool, ShapeSelectionTool, Tool, ToolRef};
+use crate::channels::View;
+use crate::clipboard::{self, Clipboard};
+use crate::commands;
+use crate::contours::{bounding_box, find_contours};
+use crate::dialogs;
+use crate::edges::EdgeTarget;
+use crate::floating::Floating;
+use crate::image_buffer::ImageBuffer;
+use crate::resample;
+use crate::selection::{self, SelectionMode};
+use crate::state::{AppData, ChannelKind, ToolKind};
+use crate::tools::{
+    BrushSelectionTool, CropTool, DrawTool, FloatingTool, MoveTool, PanTool, ShapeSelectionTool,
+    Tool, ToolRef,
+};
+use crate::transform::{self, Scope};
 use druid::scroll_component::ScrollComponent;
 
 pub struct ImageEditor {
@@ -14,17 +32,135 @@ pub struct ImageEditor {
     mouse_position: Point,
     previous_mouse_position: Point,
     is_mouse_down: bool,
-    state: EditorState,
+    is_panning: bool,
+    selection_mode: SelectionMode,
+    draw_tool: DrawTool,
+    move_tool: MoveTool,
+    crop_tool: CropTool,
+    transform_tool: TransformTool,
+    warp_tool: WarpTool,
+    preview: Option<Preview>,
+    floating_tool: FloatingTool,
+    is_dragging_floating: bool,
+    is_space_down: bool,
     shape_sel_tool: ShapeSelectionTool,
-    moving_tool: MovingTool,
+    pan_tool: PanTool,
     scroll_component: ScrollComponent,
+    outline: SelectionOutline,
+    ants_timer: TimerToken,
+    clipboard: Clipboard,
+}
+
+const ANTS_INTERVAL: Duration = Duration::from_millis(120);
+
+/// How the active layer looks with the previewed effect applied.
+struct Preview {
+    layer: usize,
+    /// Version of the layers the preview was made from.
+    version: u64,
+    image: ImageBuffer,
+}
+
+impl Preview {
+    fn is_current(&self, data: &AppData) -> bool {
+        self.layer == data.active_layer() && self.version == data.layers_version.get()
+    }
 }
 
-enum EditorState {
-    Drawing,
-    Moving,
-    ShapeSelection,
-    BrushSelection,
+/// Traced borders of the selection and the hot selection, refreshed only
+/// when the selection version of the image changes.
+struct SelectionOutline {
+    version: Option<u64>,
+    selection: Vec<BezPath>,
+    hot_selection: Vec<BezPath>,
+    dash_offset: f64,
+}
+
+impl SelectionOutline {
+    fn new() -> Self {
+        Self {
+            version: None,
+            selection: Vec::new(),
+            hot_selection: Vec::new(),
+            dash_offset: 0.0,
+        }
+    }
+
+    fn refresh(&mut self, image: &ImageBuffer) {
+        let version = image.selection_version();
+        if self.version == Some(version) {
+            return;
+        }
+
+        self.selection = trace_outline(image.channel(ChannelKind::Selection));
+        self.hot_selection = trace_outline(image.channel(ChannelKind::HotSelection));
+        self.version = Some(version);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.selection.is_empty() && self.hot_selection.is_empty()
+    }
+
+    fn paint(&self, ctx: &mut PaintCtx, transform: Affine) {
+        let mut ants = StrokeStyle::new();
+        ants.set_dash_pattern(vec![4.0, 4.0]);
+        ants.set_dash_offset(self.dash_offset);
+
+        let mut hot = StrokeStyle::new();
+        hot.set_dash_pattern(vec![2.0, 2.0]);
+        hot.set_dash_offset(0.0);
+
+        for (paths, style) in [(&self.selection, &ants), (&self.hot_selection, &hot)] {
+            for path in paths.iter() {
+                let mut path = path.clone();
+                path.apply_affine(transform);
+                ctx.stroke(&path, &Color::WHITE, 1.0);
+                ctx.stroke_styled(&path, &Color::BLACK, 1.0, style);
+            }
+        }
+    }
+}
+
+/// Builds closed paths passing through the centers of the border pixels.
+fn trace_outline(channel: View<'_, u8>) -> Vec<BezPath> {
+    find_contours(channel)
+        .into_iter()
+        .filter(|contour| contour.points.len() > 1)
+        .map(|contour| {
+            let mut path = BezPath::new();
+            for (i, p) in contour.points.iter().enumerate() {
+                let p = Point::new(p.x as f64 + 0.5, p.y as f64 + 0.5);
+                if i == 0 {
+                    path.move_to(p);
+                } else {
+                    path.line_to(p);
+                }
+            }
+            path.close_path();
+            path
+        })
+        .collect()
+}
+
+/// Moves the floating pixels if there are any, otherwise the selected pixels
+/// of the active layer together with the selection.
+fn nudge(data: &AppData, dx: i32, dy: i32) {
+    if (dx, dy) == (0, 0) {
+        return;
+    }
+    if let Some(floating) = data.floating_mut().as_mut() {
+        floating.x += dx;
+        floating.y += dy;
+        return;
+    }
+
+    let mut layer = data.active_layer_mut();
+    let image = layer.data.as_buffer_mut().unwrap();
+    let mut floating = Floating::lift(image);
+    floating.x += dx;
+    floating.y += dy;
+    floating.commit(image);
+    selection::translate(image, dx, dy);
 }
 
 impl ImageEditor {
@@ -34,39 +170,274 @@ impl ImageEditor {
             mouse_position: Default::default(),
             previous_mouse_position: Default::default(),
             is_mouse_down: false,
-            state: EditorState::Drawing,
+            is_panning: false,
+            selection_mode: SelectionMode::Replace,
+            draw_tool: DrawTool::new(1, [0, 0, 0, 255]),
+            move_tool: MoveTool::new(),
+            crop_tool: CropTool::new(),
+            transform_tool: TransformTool::new(),
+            warp_tool: WarpTool::new(),
+            preview: None,
+            floating_tool: FloatingTool::new(),
+            is_dragging_floating: false,
+            is_space_down: false,
             shape_sel_tool: ShapeSelectionTool::new(),
-            moving_tool: MovingTool::new(),
+            pan_tool: PanTool::new(),
             scroll_component: ScrollComponent::new(),
+            outline: SelectionOutline::new(),
+            ants_timer: TimerToken::INVALID,
+            clipboard: Clipboard::default(),
         }
     }
 
     fn tool_mut(&mut self, data: &AppData) -> ToolRef {
-        match self.state {
-            EditorState::Drawing => ToolRef::Owned(Box::new(DrawTool::new(
-                data.brush_size.round() as u32,
-                [
+        if self.is_panning {
+            return ToolRef::Ref(&mut self.pan_tool);
+        }
+
+        if self.is_dragging_floating {
+            return ToolRef::Ref(&mut self.floating_tool);
+        }
+
+        match data.tool {
+            ToolKind::Draw => {
+                self.draw_tool.brush_size = data.brush_size.round() as u32;
+                self.draw_tool.color = [
                     data.brush_color.r,
                     data.brush_color.g,
                     data.brush_color.b,
                     255,
-                ],
-            ))),
-            EditorState::Moving => ToolRef::Ref(&mut self.moving_tool),
-            EditorState::ShapeSelection => ToolRef::Ref(&mut self.shape_sel_tool),
-            EditorState::BrushSelection => ToolRef::Owned(Box::new(BrushSelectionTool::new(
+                ];
+                ToolRef::Ref(&mut self.draw_tool)
+            }
+            ToolKind::Move => ToolRef::Ref(&mut self.move_tool),
+            ToolKind::Crop => ToolRef::Ref(&mut self.crop_tool),
+            ToolKind::Transform => ToolRef::Ref(&mut self.transform_tool),
+            ToolKind::Warp => ToolRef::Ref(&mut self.warp_tool),
+            ToolKind::ShapeSelection => {
+                self.shape_sel_tool.mode = self.selection_mode;
+                ToolRef::Ref(&mut self.shape_sel_tool)
+            }
+            ToolKind::BrushSelection => ToolRef::Owned(Box::new(BrushSelectionTool::new(
                 data.brush_size.round() as u32,
+                self.selection_mode,
             ))),
         }
     }
 
+    fn handle_command(&mut self, ctx: &mut EventCtx, cmd: &Command, data: &mut AppData) -> bool {
+        if let Some(dialog) = cmd.get(commands::SHOW_DIALOG) {
+            dialogs::open(ctx, *dialog, data);
+            return true;
+        }
+
+        if self.handle_clipboard_command(cmd, data) {
+            return true;
+        }
+
+        if cmd.is(commands::CROP) {
+            self.crop_tool.commit(data);
+            return true;
+        }
+
+        if cmd.is(commands::COMMIT_TRANSFORM) {
+            match data.tool {
+                ToolKind::Warp => self.warp_tool.commit(data),
+                _ => self.transform_tool.commit(data),
+            };
+            return true;
+        }
+
+        if let Some(&previous) = cmd.get(commands::TOOL_CHANGED) {
+            // Switching away from a transformation applies it.
+            match previous {
+                ToolKind::Transform => self.transform_tool.commit(data),
+                ToolKind::Warp => self.warp_tool.commit(data),
+                _ => false,
+            };
+            return true;
+        }
+
+        if cmd.is(commands::CANCEL_TRANSFORM) {
+            match data.tool {
+                ToolKind::Warp => self.warp_tool.cancel(data),
+                _ => self.transform_tool.cancel(data),
+            };
+            return true;
+        }
+
+        if let Some(&(width, height, anchor, fill)) = cmd.get(commands::RESIZE_CANVAS) {
+            // Layers are stored from the top, only the bottom one gets the fill
+            // so that it does not cover the layers below in the new area.
+            let bottom = data.layers.len() - 1;
+            let mut index = 0;
+            data.edit_layers(|image| {
+                let fill = if index == bottom { fill } else { [0, 0, 0, 0] };
+                index += 1;
+                // Growth of the canvas, negative when it shrinks, split around the anchor.
+                let growth = Rect::new(
+                    0.0,
+                    0.0,
+                    width as f64 - image.width() as f64,
+                    height as f64 - image.height() as f64,
+                );
+                let offset = anchor.resolve(growth).round();
+                image.resize_canvas(width, height, offset.x as i32, offset.y as i32, fill);
+            });
+            return true;
+        }
+
+        if let Some(effect) = cmd.get(commands::APPLY_EFFECT) {
+            // Unless floating pixels land first, or the layer was edited after the
+            // preview was made, the preview already shows the very same result.
+            let preview = self.preview.take().filter(|preview| {
+                data.floating.borrow().is_none()
+                    && data.preview.as_ref() == Some(effect)
+                    && preview.is_current(data)
+            });
+            data.commit_floating();
+            let mut layer = data.active_layer_mut();
+            let image = layer.data.as_buffer_mut().unwrap();
+            match preview {
+                Some(preview) => *image = preview.image,
+                None => effect.apply(image),
+            }
+            return true;
+        }
+
+        if let Some(params) = cmd.get(commands::DETECT_EDGES) {
+            data.commit_floating();
+            let edges = {
+                let layer = data.layers[data.active_layer()].borrow();
+                params.detect_in(layer.data.as_buffer().unwrap())
+            };
+            match params.target {
+                EdgeTarget::NewLayer => {
+                    data.add_layer(Some("Edges".to_string()), ImageBuffer::from_gray(edges))
+                }
+                EdgeTarget::Selection => {
+                    let mut layer = data.active_layer_mut();
+                    let image = layer.data.as_buffer_mut().unwrap();
+                    image.replace_channel(ChannelKind::Selection, edges);
+                }
+            }
+            return true;
+        }
+
+        if let Some(&(width, height, filter)) = cmd.get(commands::RESIZE_IMAGE) {
+            data.edit_layers(|image| resample::resize(image, width, height, filter));
+            return true;
+        }
+
+        if let Some(&(degrees, filter, scope)) = cmd.get(commands::ROTATE) {
+            // Layers have to keep the size of the document.
+            let expand = scope == Scope::Document;
+            data.edit_scope(scope, |image| {
+                transform::rotate(image, degrees, filter, expand)
+            });
+            return true;
+        }
+
+        if let Some(&(axis, scope)) = cmd.get(commands::FLIP) {
+            data.edit_scope(scope, |image| transform::flip(image, axis));
+            return true;
+        }
+
+        let edit: Box<dyn FnOnce(&mut ImageBuffer)> = if cmd.is(commands::SELECT_ALL) {
+            Box::new(selection::select_all)
+        } else if cmd.is(commands::DESELECT) {
+            Box::new(selection::deselect)
+        } else if cmd.is(commands::INVERT_SELECTION) {
+            Box::new(selection::invert)
+        } else if let Some(&(refinement, radius)) = cmd.get(commands::REFINE_SELECTION) {
+            Box::new(move |image| selection::refine(image, refinement, radius))
+        } else {
+            return false;
+        };
+
+        let mut layer = data.active_layer_mut();
+        edit(layer.data.as_buffer_mut().unwrap());
+        true
+    }
+
+    fn handle_clipboard_command(&mut self, cmd: &Command, data: &mut AppData) -> bool {
+        if cmd.is(commands::COPY) || cmd.is(commands::CUT) {
+            data.commit_floating();
+            let mut layer = data.active_layer_mut();
+            let image = layer.data.as_buffer_mut().unwrap();
+            let clip = if cmd.is(commands::CUT) {
+                clipboard::cut(image)
+            } else {
+                clipboard::copy(image)
+            };
+            self.clipboard.put(clip);
+            return true;
+        }
+
+        if !(cmd.is(commands::PASTE)
+            || cmd.is(commands::PASTE_INTO)
+            || cmd.is(commands::PASTE_AS_LAYER))
+        {
+            return false;
+        }
+
+        let clip = match self.clipboard.get() {
+            Some(clip) => clip,
+            None => return true,
+        };
+        data.commit_floating();
+
+        if cmd.is(commands::PASTE_AS_LAYER) {
+            let (width, height) = data.document_size();
+            let mut image = ImageBuffer::new(width, height);
+            image.draw_over(&clip.image, clip.x, clip.y, None);
+            data.add_layer(None, image);
+            return true;
+        }
+
+        let mut floating = Floating::new(clip.image, clip.x, clip.y);
+        if cmd.is(commands::PASTE_INTO) {
+            let layer = data.layers[data.active_layer()].borrow();
+            let image = layer.data.as_buffer().unwrap();
+            let selection = image.channel(ChannelKind::Selection);
+            if let Some(bounds) = bounding_box(selection) {
+                floating.x = bounds.x as i32;
+                floating.y = bounds.y as i32;
+                floating.mask = Some(image.channel(ChannelKind::Selection).to_matrix());
+            }
+        }
+        *data.floating_mut() = Some(floating);
+        true
+    }
+
+    fn refresh_preview(&mut self, data: &AppData) {
+        self.preview = data.preview.as_ref().map(|effect| {
+            let layer = data.active_layer();
+            let mut image = data.layers[layer]
+                .borrow()
+                .data
+                .as_buffer()
+                .unwrap()
+                .clone();
+            effect.apply(&mut image);
+            let visible = [ChannelKind::Red, ChannelKind::Green, ChannelKind::Blue]
+                .map(|kind| data.is_channel_visible(kind));
+            image.refresh_interleaved(visible);
+            Preview {
+                layer,
+                version: data.layers_version.get(),
+                image,
+            }
+        });
+    }
+
     fn viewport(&self, data: &AppData, size: Size) -> Viewport {
         let (width, height) = data.layers[0].borrow().data.as_buffer().unwrap().size();
         let content_size = Size::new(width as f64, height as f64);
 
         Viewport {
             content_size,
-            view_origin: Point::new(-self.moving_tool.offset_x, -self.moving_tool.offset_y),
+            view_origin: Point::new(-self.pan_tool.offset_x, -self.pan_tool.offset_y),
             view_size: size,
         }
     }
@@ -77,20 +448,21 @@ impl Widget<AppData> for ImageEditor {
         let mut port = self.viewport(data, ctx.size());
         self.scroll_component.event(&mut port, ctx, event, env);
         if ctx.is_handled() {
-            self.moving_tool.offset_x = -port.view_origin.x;
-            self.moving_tool.offset_y = -port.view_origin.y;
+            self.pan_tool.offset_x = -port.view_origin.x;
+            self.pan_tool.offset_y = -port.view_origin.y;
             return;
         }
 
         match event {
             Event::MouseMove(e) => {
                 ctx.request_focus();
+                self.transform_tool.mods = e.mods;
 
                 self.previous_mouse_position = self.mouse_position;
                 self.mouse_position = e.pos;
 
                 if self.is_mouse_down {
-                    let transform = self.moving_tool.transform();
+                    let transform = self.pan_tool.transform();
                     let pos = self.mouse_position;
                     let prev_pos = self.previous_mouse_position;
                     self.tool_mut(data)
@@ -98,7 +470,11 @@ impl Widget<AppData> for ImageEditor {
                         .mouse_move(pos, prev_pos, transform, data);
                 }
 
-                ctx.set_cursor(&Cursor::Arrow);
+                ctx.set_cursor(if self.is_space_down {
+                    &Cursor::OpenHand
+                } else {
+                    &Cursor::Arrow
+                });
                 ctx.set_handled();
                 ctx.request_paint();
             }
@@ -106,18 +482,30 @@ impl Widget<AppData> for ImageEditor {
                 ctx.request_focus();
 
                 self.is_mouse_down = true;
-                self.state = if e.mods.alt() {
-                    EditorState::Moving
-                } else if e.mods.ctrl() && e.mods.shift() {
-                    EditorState::ShapeSelection
-                } else if e.mods.shift() {
-                    EditorState::BrushSelection
-                } else {
-                    EditorState::Drawing
-                };
+                self.is_panning = self.is_space_down;
+                self.transform_tool.mods = e.mods;
+                self.selection_mode = SelectionMode::from_modifiers(e.mods, data.selection_mode);
 
-                let transform = self.moving_tool.transform();
+                let transform = self.pan_tool.transform();
                 let pos = self.mouse_position;
+
+                // Transform tools take the floating pixels over by themselves.
+                let transforming = matches!(data.tool, ToolKind::Transform | ToolKind::Warp);
+                if !self.is_panning && !transforming {
+                    // Clicking on the floating pixels drags them, clicking anywhere else
+                    // drops them into the layer before the tool takes over.
+                    let image_pos = transform.inverse() * pos;
+                    let floating = data
+                        .floating
+                        .borrow()
+                        .as_ref()
+                        .map(|f| f.contains(image_pos));
+                    match floating {
+                        Some(true) => self.is_dragging_floating = true,
+                        Some(false) => data.commit_floating(),
+                        None => (),
+                    }
+                }
                 self.tool_mut(data)
                     .as_mut()
                     .mouse_down(pos, transform, data);
@@ -125,11 +513,15 @@ impl Widget<AppData> for ImageEditor {
             Event::MouseUp(_e) => {
                 ctx.request_focus();
 
-                let transform = self.moving_tool.transform();
-                self.tool_mut(data).as_mut().mouse_up(transform, data);
+                if self.is_mouse_down {
+                    let transform = self.pan_tool.transform();
+                    self.tool_mut(data).as_mut().mouse_up(transform, data);
+                }
 
-                self.state = EditorState::Drawing;
+                self.is_panning = false;
+                self.is_dragging_floating = false;
                 self.is_mouse_down = false;
+                ctx.request_paint();
             }
             Event::KeyDown(e) => {
                 ctx.request_paint();
@@ -137,28 +529,106 @@ impl Widget<AppData> for ImageEditor {
                 match e.code {
                     Code::BracketLeft => data.brush_size -= 1.0,
                     Code::BracketRight => data.brush_size += 1.0,
+                    Code::Enter | Code::NumpadEnter => {
+                        let committed = match data.tool {
+                            ToolKind::Crop => self.crop_tool.commit(data),
+                            ToolKind::Transform => self.transform_tool.commit(data),
+                            ToolKind::Warp => self.warp_tool.commit(data),
+                            _ => false,
+                        };
+                        if !committed {
+                            data.commit_floating();
+                        }
+                    }
+                    Code::Escape => {
+                        let cancelled = match data.tool {
+                            ToolKind::Crop => self.crop_tool.cancel(),
+                            ToolKind::Transform => self.transform_tool.cancel(data),
+                            ToolKind::Warp => self.warp_tool.cancel(data),
+                            _ => false,
+                        };
+                        if !cancelled {
+                            data.floating_mut().take();
+                        }
+                    }
+                    Code::Space => self.is_space_down = true,
+                    Code::ArrowLeft | Code::ArrowRight | Code::ArrowUp | Code::ArrowDown
+                        if data.tool == ToolKind::Move =>
+                    {
+                        let step = if e.mods.shift() { 10 } else { 1 };
+                        let (dx, dy) = match e.code {
+                            Code::ArrowLeft => (-step, 0),
+                            Code::ArrowRight => (step, 0),
+                            Code::ArrowUp => (0, -step),
+                            _ => (0, step),
+                        };
+                        nudge(data, dx, dy);
+                    }
                     _ => (),
                 }
             }
+            Event::KeyUp(e) => {
+                if e.code == Code::Space {
+                    self.is_space_down = false;
+                    ctx.set_cursor(&Cursor::Arrow);
+                }
+            }
+            Event::Command(cmd) => {
+                if self.handle_command(ctx, cmd, data) {
+                    ctx.set_handled();
+                    ctx.request_paint();
+                }
+            }
+            Event::Timer(token) if *token == self.ants_timer => {
+                self.outline.dash_offset = (self.outline.dash_offset + 1.0) % 8.0;
+                if !self.outline.is_empty() {
+                    ctx.request_paint();
+                }
+                self.ants_timer = ctx.request_timer(ANTS_INTERVAL);
+            }
             Event::Wheel(e) => {
-                self.moving_tool.wheel(e.pos, e.wheel_delta, e.mods);
+                self.pan_tool.wheel(e.pos, e.wheel_delta, e.mods);
                 ctx.set_handled();
                 ctx.request_paint();
             }
             _ => (),
         }
+
+        // Reflect changes made with the handles in the tool options.
+        let params = self.transform_tool.params();
+        if data.transform != params {
+            data.transform = params;
+        }
     }
 
     fn lifecycle(
         &mut self,
-        _ctx: &mut LifeCycleCtx,
-        _event: &LifeCycle,
+        ctx: &mut LifeCycleCtx,
+        event: &LifeCycle,
         _data: &AppData,
         _env: &Env,
     ) {
+        if let LifeCycle::WidgetAdded = event {
+            self.ants_timer = ctx.request_timer(ANTS_INTERVAL);
+        }
     }
 
-    fn update(&mut self, _ctx: &mut UpdateCtx, _old_data: &AppData, _data: &AppData, _env: &Env) {}
+    fn update(&mut self, ctx: &mut UpdateCtx, old_data: &AppData, data: &AppData, _env: &Env) {
+        if old_data.warp_mode != data.warp_mode || old_data.warp_grid != data.warp_grid {
+            self.warp_tool.reset(data);
+        }
+        if old_data.preview != data.preview {
+            self.refresh_preview(data);
+        }
+        if data.transform != self.transform_tool.params() {
+            self.transform_tool.set_params(data.transform);
+        }
+
+        // Tool options may change what the overlay looks like.
+        if !old_data.same(data) {
+            ctx.request_paint();
+        }
+    }
 
     fn layout(
         &mut self,
@@ -172,19 +642,61 @@ impl Widget<AppData> for ImageEditor {
     }
 
     fn paint(&mut self, ctx: &mut PaintCtx, data: &AppData, env: &Env) {
-        let transform = self.moving_tool.transform();
+        let transform = self.pan_tool.transform();
         let clip_rect = Rect::ZERO.with_size(ctx.size());
         ctx.clip(clip_rect);
+        // Background around the image
+        ctx.fill(clip_rect, &Color::rgb8(38, 38, 38));
+
+        // Edits made while the dialog is open show up in the preview as well.
+        let is_stale = self
+            .preview
+            .as_ref()
+            .map_or(false, |preview| !preview.is_current(data));
+        if is_stale {
+            self.refresh_preview(data);
+        }
+
         data.ensure_fresh();
-        data.layers[0].borrow().data.as_buffer().unwrap().to_piet(
-            transform,
-            ctx,
-            self.interpolation,
+        for (index, layer) in data.layers.iter().enumerate().rev() {
+            let layer = layer.borrow();
+            if layer.is_visible {
+                let image = match self.preview {
+                    Some(ref preview) if preview.layer == index => &preview.image,
+                    _ => layer.data.as_buffer().unwrap(),
+                };
+                image.to_piet(transform, ctx, self.interpolation);
+            }
+        }
+
+        if let Some(floating) = data.floating.borrow().as_ref() {
+            let offset = Affine::translate((floating.x as f64, floating.y as f64));
+            floating
+                .image
+                .to_piet(transform * offset, ctx, self.interpolation);
+
+            let mut style = StrokeStyle::new();
+            style.set_dash_pattern(vec![3.0, 3.0]);
+            style.set_dash_offset(0.0);
+            let bounds = transform.transform_rect_bbox(floating.bounds());
+            ctx.stroke_styled(bounds, &Color::WHITE, 1.0, &style);
+        }
+
+        self.outline.refresh(
+            data.layers[data.active_layer()]
+                .borrow()
+                .data
+                .as_buffer()
+                .unwrap(),
         );
+        if data.is_channel_visible(ChannelKind::Selection) {
+            self.outline.paint(ctx, transform);
+        }
 
         let pos = self.mouse_position;
-        let scale = self.moving_tool.scale();
-        self.tool_mut(data).as_mut().overlay(ctx, pos, scale);
+        self.tool_mut(data)
+            .as_mut()
+            .overlay(ctx, pos, transform, data);
 
         self.scroll_component
             .draw_bars(ctx, &self.viewport(data, ctx.size()), env);
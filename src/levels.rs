@@ -0,0 +1,231 @@
+use druid::{Data, Lens};
+
+use crate::histogram;
+use crate::image_buffer::ImageBuffer;
+
+/// Fraction of the darkest and the brightest pixels ignored by the automatic levels.
+pub(crate) const AUTO_CLIP: f64 = 0.001;
+
+/// Levels of a single channel, all values are in the 0-255 range except `gamma`.
+#[derive(Clone, Copy, PartialEq, Data, Debug, Lens)]
+pub(crate) struct Levels {
+    pub(crate) input_black: f64,
+    pub(crate) input_white: f64,
+    /// Above one brightens the midtones, below one darkens them.
+    pub(crate) gamma: f64,
+    pub(crate) output_black: f64,
+    pub(crate) output_white: f64,
+}
+
+impl Levels {
+    pub(crate) const IDENTITY: Levels = Levels {
+        input_black: 0.0,
+        input_white: 255.0,
+        gamma: 1.0,
+        output_black: 0.0,
+        output_white: 255.0,
+    };
+
+    pub(crate) fn map(&self, value: f64) -> f64 {
+        let range = (self.input_white - self.input_black).max(1.0);
+        let t = ((value - self.input_black) / range).max(0.0).min(1.0);
+        let t = t.powf(1.0 / self.gamma.max(0.01));
+        self.output_black + t * (self.output_white - self.output_black)
+    }
+
+    /// Input value that ends up halfway between the output black and white.
+    pub(crate) fn midtone(&self) -> f64 {
+        self.input_black + (self.input_white - self.input_black) * 0.5f64.powf(self.gamma)
+    }
+
+    /// Stretches the histogram to the full range, ignoring `clip` (a fraction)
+    /// of the darkest and the brightest pixels.
+    pub(crate) fn auto(histogram: &[u32; 256], clip: f64) -> Levels {
+        let total: u64 = histogram.iter().map(|&n| n as u64).sum();
+        let limit = (total as f64 * clip) as u64;
+        // First value past the clipped pixels when counting in the given order.
+        let past_limit = |mut values: Box<dyn Iterator<Item = usize>>| {
+            let mut count = 0;
+            values.find(|&value| {
+                count += histogram[value] as u64;
+                count > limit
+            })
+        };
+        let black = past_limit(Box::new(0..256)).unwrap_or(0);
+        let white = past_limit(Box::new((0..256).rev())).unwrap_or(255);
+        if black >= white {
+            return Levels::IDENTITY;
+        }
+        Levels {
+            input_black: black as f64,
+            input_white: white as f64,
+            ..Levels::IDENTITY
+        }
+    }
+}
+
+/// Channel edited by the Levels and Curves dialogs, the composite one affects all colors.
+#[derive(Clone, Copy, PartialEq, Eq, Data, Debug)]
+pub(crate) enum ToneChannel {
+    Composite,
+    Red,
+    Green,
+    Blue,
+}
+
+/// Levels of the individual channels, applied before the composite ones.
+#[derive(Clone, Copy, PartialEq, Data, Debug, Lens)]
+pub(crate) struct LevelsParams {
+    pub(crate) composite: Levels,
+    pub(crate) red: Levels,
+    pub(crate) green: Levels,
+    pub(crate) blue: Levels,
+}
+
+impl LevelsParams {
+    pub(crate) const IDENTITY: LevelsParams = LevelsParams {
+        composite: Levels::IDENTITY,
+        red: Levels::IDENTITY,
+        green: Levels::IDENTITY,
+        blue: Levels::IDENTITY,
+    };
+
+    pub(crate) fn get(&self, channel: ToneChannel) -> &Levels {
+        match channel {
+            ToneChannel::Composite => &self.composite,
+            ToneChannel::Red => &self.red,
+            ToneChannel::Green => &self.green,
+            ToneChannel::Blue => &self.blue,
+        }
+    }
+
+    pub(crate) fn get_mut(&mut self, channel: ToneChannel) -> &mut Levels {
+        match channel {
+            ToneChannel::Composite => &mut self.composite,
+            ToneChannel::Red => &mut self.red,
+            ToneChannel::Green => &mut self.green,
+            ToneChannel::Blue => &mut self.blue,
+        }
+    }
+
+    /// Lookup tables of the red, green and blue channel.
+    pub(crate) fn luts(&self) -> [[u8; 256]; 3] {
+        [self.red, self.green, self.blue].map(|levels| {
+            let mut lut = [0u8; 256];
+            for (value, out) in lut.iter_mut().enumerate() {
+                let value = self.composite.map(levels.map(value as f64));
+                *out = value.round().max(0.0).min(255.0) as u8;
+            }
+            lut
+        })
+    }
+
+    /// Stretches every channel on its own, which also removes color casts.
+    pub(crate) fn auto_color(image: &ImageBuffer, clip: f64) -> LevelsParams {
+        let [red, green, blue] = histogram::count_colors(image).map(|h| Levels::auto(&h, clip));
+        LevelsParams {
+            red,
+            green,
+            blue,
+            ..LevelsParams::IDENTITY
+        }
+    }
+
+    /// Stretches all channels by the same amount, keeping the colors.
+    pub(crate) fn auto_contrast(image: &ImageBuffer, clip: f64) -> LevelsParams {
+        LevelsParams {
+            composite: Levels::auto(&histogram::count_composite(image), clip),
+            ..LevelsParams::IDENTITY
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn identity_keeps_values() {
+        for value in [0.0, 1.0, 127.0, 254.0, 255.0] {
+            assert_close(Levels::IDENTITY.map(value), value);
+        }
+    }
+
+    #[test]
+    fn map_stretches_and_clamps_the_input_range() {
+        let levels = Levels {
+            input_black: 50.0,
+            input_white: 150.0,
+            output_black: 10.0,
+            output_white: 210.0,
+            ..Levels::IDENTITY
+        };
+        assert_close(levels.map(0.0), 10.0);
+        assert_close(levels.map(50.0), 10.0);
+        assert_close(levels.map(100.0), 110.0);
+        assert_close(levels.map(150.0), 210.0);
+        assert_close(levels.map(255.0), 210.0);
+    }
+
+    #[test]
+    fn gamma_above_one_brightens_the_midtones() {
+        let levels = Levels {
+            gamma: 2.0,
+            ..Levels::IDENTITY
+        };
+        assert!(levels.map(127.5) > 127.5);
+        assert_close(levels.map(0.0), 0.0);
+        assert_close(levels.map(255.0), 255.0);
+    }
+
+    #[test]
+    fn midtone_maps_halfway_between_the_outputs() {
+        for &gamma in [0.5, 1.0, 2.0].iter() {
+            let levels = Levels {
+                input_black: 20.0,
+                input_white: 220.0,
+                gamma,
+                output_black: 40.0,
+                output_white: 240.0,
+            };
+            assert_close(levels.map(levels.midtone()), 140.0);
+        }
+        assert_close(Levels::IDENTITY.midtone(), 127.5);
+    }
+
+    #[test]
+    fn auto_stretches_to_the_used_range() {
+        let mut histogram = [0u32; 256];
+        for count in histogram[10..=200].iter_mut() {
+            *count = 100;
+        }
+        let levels = Levels::auto(&histogram, 0.0);
+        assert_close(levels.input_black, 10.0);
+        assert_close(levels.input_white, 200.0);
+        assert_close(levels.gamma, 1.0);
+    }
+
+    #[test]
+    fn auto_ignores_the_clipped_pixels() {
+        let mut histogram = [0u32; 256];
+        histogram[0] = 5;
+        histogram[100] = 10_000;
+        histogram[120] = 10_000;
+        histogram[255] = 5;
+        let levels = Levels::auto(&histogram, AUTO_CLIP);
+        assert_close(levels.input_black, 100.0);
+        assert_close(levels.input_white, 120.0);
+    }
+
+    #[test]
+    fn auto_keeps_a_single_value_unchanged() {
+        let mut histogram = [0u32; 256];
+        histogram[80] = 1000;
+        assert_eq!(Levels::auto(&histogram, AUTO_CLIP), Levels::IDENTITY);
+        assert_eq!(Levels::auto(&[0; 256], AUTO_CLIP), Levels::IDENTITY);
+    }
+}
@@ -6,20 +6,61 @@ use std::sync::Arc;
 
 use druid::{AppLauncher, Color, LocalizedString, WindowDesc};
 
+use crate::channel_mixer::{ChannelMixerParams, MixerOutput};
+use crate::color_balance::{ColorBalanceParams, FilterColor, PhotoFilterParams, TonalRange};
+use crate::convolution::EdgeMode;
+use crate::crop::{CropAspect, CropGuide};
+use crate::curves::CurvesParams;
+use crate::edges::{EdgeDetector, EdgeParams, EdgeTarget};
+use crate::gradient_map::{GradientMapParams, GradientPreset};
+use crate::hue_saturation::{HueRange, HueSaturationParams};
 use crate::image_buffer::ImageBuffer;
-use crate::state::{AppData, Channel, ChannelKind, Layer, LayerData};
+use crate::levels::{LevelsParams, ToneChannel};
+use crate::menu::make_menu;
+use crate::morphology::{Operation, Planes, Shape};
+use crate::resample::Filter;
+use crate::selection::SelectionMode;
+use crate::state::{
+    AppData, CanvasParams, Channel, ChannelKind, Layer, LayerData, ResizeParams, RotateParams,
+    ToolKind,
+};
+use crate::transform::{TransformParams, WarpMode};
 use crate::ui::make_root;
 
 mod brushes;
+mod channel_mixer;
 mod channels;
+mod clipboard;
+mod color_balance;
 mod color_picker;
+mod commands;
 mod contours;
+mod convolution;
+mod crop;
+mod curves;
+mod denoise;
+mod dialogs;
+mod edges;
+mod effects;
+mod equalize;
+mod floating;
+mod gradient_map;
 mod histogram;
+mod hsl;
+mod hue_saturation;
 mod image_buffer;
 mod image_edit;
+mod levels;
+mod menu;
+mod morphology;
 mod ops;
+mod resample;
+mod selection;
 mod state;
+mod threshold;
+mod tone;
 mod tools;
+mod transform;
 mod ui;
 mod utils;
 mod widgets;
@@ -27,8 +68,12 @@ mod widgets;
 fn main() {
     let main_window = WindowDesc::new(make_root())
         .title(LocalizedString::new("Maditor"))
+        .menu(make_menu)
         .window_size((1378.0, 768.0));
 
+    let image = ImageBuffer::from_file("image.jpg").unwrap();
+    let (width, height) = image.size();
+
     let data = AppData {
         channels: Arc::new(vec![
             Channel {
@@ -71,11 +116,95 @@ fn main() {
             name: None,
             is_selected: true,
             is_visible: true,
-            data: LayerData::RasterImage(ImageBuffer::from_file("image.jpg").unwrap()),
+            data: LayerData::RasterImage(image),
         })]),
+        floating: Arc::new(RefCell::new(None)),
         dirty: Cell::new(true),
+        layers_version: Cell::new(0),
         brush_color: color_picker::Color::new(),
         brush_size: 1.0,
+        tool: ToolKind::Draw,
+        selection_mode: SelectionMode::Replace,
+        selection_radius: 5.0,
+        crop_aspect: CropAspect::Free,
+        crop_guide: CropGuide::RuleOfThirds,
+        crop_width: 1920,
+        crop_height: 1080,
+        resize: ResizeParams::new(width, height),
+        canvas: CanvasParams::new(width, height),
+        rotate: RotateParams {
+            angle: 0.0,
+            filter: Filter::Bicubic,
+        },
+        transform: TransformParams::IDENTITY,
+        warp_mode: WarpMode::Perspective,
+        warp_grid: 3,
+        warp_filter: Filter::Bicubic,
+        preview: None,
+        show_preview: true,
+        blur_radius: 6.0,
+        usm_amount: 100.0,
+        usm_radius: 3.0,
+        usm_threshold: 0.0,
+        box_blur_radius: 2.0,
+        median_radius: 2.0,
+        bilateral_sigma_space: 3.0,
+        bilateral_sigma_range: 25.0,
+        nlm_strength: 10.0,
+        nlm_search_radius: 7.0,
+        nlm_patch_radius: 1.0,
+        edges: EdgeParams {
+            detector: EdgeDetector::Canny,
+            blur: 4.2,
+            low: 20.0,
+            high: 50.0,
+            target: EdgeTarget::NewLayer,
+        },
+        morphology_operation: Operation::Erode,
+        morphology_shape: Shape::Disc,
+        morphology_radius: 2.0,
+        morphology_element: "010\n111\n010".to_string(),
+        morphology_planes: Planes::Colors,
+        levels: LevelsParams::IDENTITY,
+        levels_channel: ToneChannel::Composite,
+        curves: CurvesParams::linear(),
+        curves_channel: ToneChannel::Composite,
+        curves_file_error: None,
+        auto_contrast_clip: levels::AUTO_CLIP * 100.0,
+        clahe_tile_size: 64.0,
+        clahe_clip_limit: 2.0,
+        brightness: 0.0,
+        contrast: 0.0,
+        exposure: 0.0,
+        exposure_offset: 0.0,
+        exposure_gamma: 1.0,
+        gamma: 1.0,
+        shadows: 35.0,
+        highlights: 0.0,
+        shadows_highlights_radius: 30.0,
+        hue_saturation: HueSaturationParams::NONE,
+        hue_range: HueRange::Master,
+        color_balance: ColorBalanceParams::NONE,
+        color_balance_range: TonalRange::Midtones,
+        channel_mixer: ChannelMixerParams::IDENTITY,
+        mixer_output: MixerOutput::Red,
+        photo_filter: PhotoFilterParams {
+            color: FilterColor::Warming,
+            density: 25.0,
+            preserve_luminosity: true,
+        },
+        vibrance: 0.0,
+        vibrance_saturation: 0.0,
+        threshold: 128.0,
+        posterize_levels: 4.0,
+        gradient_map: GradientMapParams {
+            preset: GradientPreset::BlackWhite,
+            reverse: false,
+        },
+        custom_kernel: "0 0 0\n0 1 0\n0 0 0".to_string(),
+        custom_divisor: 0.0,
+        custom_offset: 0.0,
+        custom_edges: EdgeMode::Clamp,
     };
 
     AppLauncher::with_window(main_window)
@@ -0,0 +1,212 @@
+use druid::{Env, Menu, MenuItem, SysMods, WindowId};
+
+use crate::commands;
+use crate::dialogs::Dialog;
+use crate::effects::Effect;
+use crate::hue_saturation::Desaturation;
+use crate::resample::Filter;
+use crate::selection::Refinement;
+use crate::state::AppData;
+use crate::transform::{Axis, Scope};
+
+pub(crate) fn make_menu(_window: Option<WindowId>, _data: &AppData, _env: &Env) -> Menu<AppData> {
+    Menu::empty()
+        .entry(make_edit_menu())
+        .entry(make_image_menu())
+        .entry(make_layer_menu())
+        .entry(make_select_menu())
+        .entry(make_filter_menu())
+}
+
+fn make_edit_menu() -> Menu<AppData> {
+    Menu::new("Edit")
+        .entry(
+            MenuItem::new("Cut")
+                .command(commands::CUT)
+                .hotkey(SysMods::Cmd, "x"),
+        )
+        .entry(
+            MenuItem::new("Copy")
+                .command(commands::COPY)
+                .hotkey(SysMods::Cmd, "c"),
+        )
+        .entry(
+            MenuItem::new("Paste")
+                .command(commands::PASTE)
+                .hotkey(SysMods::Cmd, "v"),
+        )
+        .entry(
+            MenuItem::new("Paste Into Selection")
+                .command(commands::PASTE_INTO)
+                .hotkey(SysMods::CmdShift, "v"),
+        )
+        .entry(
+            MenuItem::new("Paste as New Layer")
+                .command(commands::PASTE_AS_LAYER)
+                .hotkey(SysMods::AltCmd, "v"),
+        )
+}
+
+fn make_image_menu() -> Menu<AppData> {
+    Menu::new("Image")
+        .entry(
+            MenuItem::new("Resize...")
+                .command(commands::SHOW_DIALOG.with(Dialog::ResizeImage))
+                .hotkey(SysMods::AltCmd, "i"),
+        )
+        .entry(
+            MenuItem::new("Canvas Size...")
+                .command(commands::SHOW_DIALOG.with(Dialog::ResizeCanvas))
+                .hotkey(SysMods::AltCmd, "c"),
+        )
+        .separator()
+        .entry(make_rotate_menu("Image Rotation", Scope::Document))
+        .separator()
+        .entry(make_adjustments_menu())
+}
+
+fn make_adjustments_menu() -> Menu<AppData> {
+    let item = |title: &'static str, dialog: Dialog| {
+        MenuItem::new(title).command(commands::SHOW_DIALOG.with(dialog))
+    };
+
+    Menu::new("Adjustments")
+        .entry(
+            MenuItem::new("Levels...")
+                .command(commands::SHOW_DIALOG.with(Dialog::Levels))
+                .hotkey(SysMods::Cmd, "l"),
+        )
+        .entry(
+            MenuItem::new("Curves...")
+                .command(commands::SHOW_DIALOG.with(Dialog::Curves))
+                .hotkey(SysMods::CmdShift, "m"),
+        )
+        .entry(item("Auto Contrast...", Dialog::AutoContrast))
+        .entry(MenuItem::new("Equalize").command(commands::APPLY_EFFECT.with(Effect::Equalize)))
+        .entry(item("Adaptive Equalize...", Dialog::AdaptiveEqualize))
+        .separator()
+        .entry(item("Brightness/Contrast...", Dialog::BrightnessContrast))
+        .entry(item("Exposure...", Dialog::Exposure))
+        .entry(item("Gamma...", Dialog::Gamma))
+        .entry(item("Shadows/Highlights...", Dialog::ShadowsHighlights))
+        .separator()
+        .entry(
+            MenuItem::new("Hue/Saturation...")
+                .command(commands::SHOW_DIALOG.with(Dialog::HueSaturation))
+                .hotkey(SysMods::Cmd, "u"),
+        )
+        .entry(item("Vibrance...", Dialog::Vibrance))
+        .entry(item("Color Balance...", Dialog::ColorBalance))
+        .entry(item("Channel Mixer...", Dialog::ChannelMixer))
+        .entry(item("Photo Filter...", Dialog::PhotoFilter))
+        .separator()
+        .entry(
+            MenuItem::new("Invert")
+                .command(commands::APPLY_EFFECT.with(Effect::Invert))
+                .hotkey(SysMods::Cmd, "i"),
+        )
+        .entry(make_desaturate_menu())
+        .entry(item("Threshold...", Dialog::Threshold))
+        .entry(item("Posterize...", Dialog::Posterize))
+        .entry(item("Gradient Map...", Dialog::GradientMap))
+}
+
+fn make_desaturate_menu() -> Menu<AppData> {
+    let item = |title: &'static str, method: Desaturation| {
+        MenuItem::new(title).command(commands::APPLY_EFFECT.with(Effect::Desaturate(method)))
+    };
+
+    Menu::new("Desaturate")
+        .entry(item("Luminosity", Desaturation::Luminosity).hotkey(SysMods::CmdShift, "u"))
+        .entry(item("Average", Desaturation::Average))
+        .entry(item("Lightness", Desaturation::Lightness))
+}
+
+fn make_layer_menu() -> Menu<AppData> {
+    Menu::new("Layer").entry(make_rotate_menu("Rotate and Flip", Scope::Layer))
+}
+
+fn make_rotate_menu(title: &'static str, scope: Scope) -> Menu<AppData> {
+    let rotate = |title: &'static str, degrees: f64| {
+        MenuItem::new(title).command(commands::ROTATE.with((degrees, Filter::Nearest, scope)))
+    };
+    let flip = |title: &'static str, axis: Axis| {
+        MenuItem::new(title).command(commands::FLIP.with((axis, scope)))
+    };
+
+    Menu::new(title)
+        .entry(rotate("180°", 180.0))
+        .entry(rotate("90° Clockwise", 90.0))
+        .entry(rotate("90° Counter Clockwise", 270.0))
+        .entry(
+            MenuItem::new("Arbitrary...")
+                .command(commands::SHOW_DIALOG.with(Dialog::Rotate(scope))),
+        )
+        .separator()
+        .entry(flip("Flip Horizontal", Axis::Horizontal))
+        .entry(flip("Flip Vertical", Axis::Vertical))
+}
+
+fn make_select_menu() -> Menu<AppData> {
+    Menu::new("Select")
+        .entry(
+            MenuItem::new("All")
+                .command(commands::SELECT_ALL)
+                .hotkey(SysMods::Cmd, "a"),
+        )
+        .entry(
+            MenuItem::new("Deselect")
+                .command(commands::DESELECT)
+                .hotkey(SysMods::Cmd, "d"),
+        )
+        .entry(
+            MenuItem::new("Inverse")
+                .command(commands::INVERT_SELECTION)
+                .hotkey(SysMods::CmdShift, "i"),
+        )
+        .separator()
+        .entry(make_modify_menu())
+}
+
+fn make_modify_menu() -> Menu<AppData> {
+    let item = |title: &'static str, refinement: Refinement| {
+        MenuItem::new(title)
+            .command(commands::SHOW_DIALOG.with(Dialog::RefineSelection(refinement)))
+    };
+
+    Menu::new("Modify")
+        .entry(item("Feather...", Refinement::Feather))
+        .entry(item("Grow...", Refinement::Grow))
+        .entry(item("Shrink...", Refinement::Shrink))
+        .entry(item("Border...", Refinement::Border))
+        .entry(item("Smooth...", Refinement::Smooth))
+}
+
+fn make_filter_menu() -> Menu<AppData> {
+    let item = |title: &'static str, dialog: Dialog| {
+        MenuItem::new(title).command(commands::SHOW_DIALOG.with(dialog))
+    };
+
+    Menu::new("Filter")
+        .entry(item("Gaussian Blur...", Dialog::GaussianBlur))
+        .entry(item("Box Blur...", Dialog::BoxBlur))
+        .separator()
+        .entry(MenuItem::new("Sharpen").command(commands::APPLY_EFFECT.with(Effect::Sharpen)))
+        .entry(item("Unsharp Mask...", Dialog::UnsharpMask))
+        .separator()
+        .entry(
+            Menu::new("Noise")
+                .entry(item("Median...", Dialog::Median))
+                .entry(item("Bilateral Filter...", Dialog::Bilateral))
+                .entry(item("Reduce Noise...", Dialog::ReduceNoise)),
+        )
+        .separator()
+        .entry(item("Detect Edges...", Dialog::DetectEdges))
+        .entry(item("Morphology...", Dialog::Morphology))
+        .entry(MenuItem::new("Emboss").command(commands::APPLY_EFFECT.with(Effect::Emboss)))
+        .entry(
+            MenuItem::new("Edge Enhance").command(commands::APPLY_EFFECT.with(Effect::EdgeEnhance)),
+        )
+        .separator()
+        .entry(item("Custom Filter...", Dialog::CustomFilter))
+}
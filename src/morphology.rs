@@ -0,0 +1,432 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use druid::Data;
+
+use crate::channels::Matrix;
+use crate::state::ChannelKind;
+
+#[derive(Clone, Copy, PartialEq, Eq, Data, Debug)]
+pub(crate) enum Operation {
+    Erode,
+    Dilate,
+    /// Erosion followed by dilation, removes small bright spots.
+    Open,
+    /// Dilation followed by erosion, fills small dark holes.
+    Close,
+    /// Difference between the channel and its opening, keeps the small bright details.
+    TopHat,
+    /// Difference between the dilation and the erosion, outlines the shapes.
+    Gradient,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Data, Debug)]
+pub(crate) enum Shape {
+    Square,
+    Disc,
+    /// Typed in by the user.
+    Custom,
+}
+
+/// Channels the operation runs on.
+#[derive(Clone, Copy, PartialEq, Eq, Data, Debug)]
+pub(crate) enum Planes {
+    /// Red, green and blue, each on its own.
+    Colors,
+    Single(ChannelKind),
+}
+
+/// Structuring element stored row by row, its origin lies at (width / 2, height / 2).
+#[derive(Clone, PartialEq, Data, Debug)]
+pub(crate) struct Element {
+    width: u32,
+    height: u32,
+    mask: Arc<Vec<bool>>,
+}
+
+impl Element {
+    fn new(width: u32, height: u32, f: impl Fn(i64, i64) -> bool) -> Self {
+        let (cx, cy) = ((width / 2) as i64, (height / 2) as i64);
+        let mask = (0..height as i64)
+            .flat_map(|y| (0..width as i64).map(move |x| (x, y)))
+            .map(|(x, y)| f(x - cx, y - cy))
+            .collect();
+        Self {
+            width,
+            height,
+            mask: Arc::new(mask),
+        }
+    }
+
+    pub(crate) fn square(radius: u32) -> Self {
+        Self::new(2 * radius + 1, 2 * radius + 1, |_, _| true)
+    }
+
+    pub(crate) fn disc(radius: u32) -> Self {
+        // The extra radius rounds the outline, so that small discs are not just crosses.
+        let limit = (radius * radius + radius) as i64;
+        Self::new(2 * radius + 1, 2 * radius + 1, |x, y| {
+            x * x + y * y <= limit
+        })
+    }
+
+    /// Reads rows of `1`/`#` for the pixels of the element and `0`/`.` for the rest,
+    /// one row per line.
+    pub(crate) fn parse(text: &str) -> Result<Self, String> {
+        let rows = text
+            .lines()
+            .map(|line| {
+                line.chars()
+                    .filter(|c| !c.is_whitespace())
+                    .map(|c| match c {
+                        '1' | '#' => Ok(true),
+                        '0' | '.' => Ok(false),
+                        _ => Err(format!("Unexpected \"{}\", use 1 and 0", c)),
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .filter(|row| !matches!(row, Ok(row) if row.is_empty()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let width = rows.first().map_or(0, |row| row.len());
+        if rows.iter().any(|row| row.len() != width) {
+            return Err("All rows need the same length".to_string());
+        }
+        if !rows.iter().flatten().any(|&set| set) {
+            return Err("The element is empty".to_string());
+        }
+
+        Ok(Self {
+            width: width as u32,
+            height: rows.len() as u32,
+            mask: Arc::new(rows.concat()),
+        })
+    }
+
+    fn is_full(&self) -> bool {
+        self.mask.iter().all(|&set| set)
+    }
+
+    /// Horizontal runs of the element as (dy, dx of the first pixel, length).
+    fn runs(&self) -> Vec<(i64, i64, usize)> {
+        let (cx, cy) = ((self.width / 2) as i64, (self.height / 2) as i64);
+        let mut runs = Vec::new();
+        for (y, row) in self.mask.chunks(self.width as usize).enumerate() {
+            let mut x = 0;
+            while x < row.len() {
+                if !row[x] {
+                    x += 1;
+                    continue;
+                }
+                let start = x;
+                while x < row.len() && row[x] {
+                    x += 1;
+                }
+                runs.push((y as i64 - cy, start as i64 - cx, x - start));
+            }
+        }
+        runs
+    }
+}
+
+impl Operation {
+    pub(crate) fn apply(self, src: &Matrix<u8>, element: &Element) -> Matrix<u8> {
+        match self {
+            Operation::Erode => erode(src, element),
+            Operation::Dilate => dilate(src, element),
+            Operation::Open => dilate(&erode(src, element), element),
+            Operation::Close => erode(&dilate(src, element), element),
+            Operation::TopHat => difference(src, &Operation::Open.apply(src, element)),
+            Operation::Gradient => difference(&dilate(src, element), &erode(src, element)),
+        }
+    }
+}
+
+/// Minimum over the element placed at every pixel.
+pub(crate) fn erode(src: &Matrix<u8>, element: &Element) -> Matrix<u8> {
+    filter(src, element, u8::min, u8::MAX, false)
+}
+
+/// Maximum over the element reflected around its origin.
+pub(crate) fn dilate(src: &Matrix<u8>, element: &Element) -> Matrix<u8> {
+    filter(src, element, u8::max, u8::MIN, true)
+}
+
+fn difference(a: &Matrix<u8>, b: &Matrix<u8>) -> Matrix<u8> {
+    let mut out = Matrix::new(a.width(), a.height());
+    for ((out, a), b) in out
+        .as_slice_mut()
+        .iter_mut()
+        .zip(a.as_slice())
+        .zip(b.as_slice())
+    {
+        *out = a.saturating_sub(*b);
+    }
+    out
+}
+
+/// Combines the pixels under the element with `op`. Pixels outside of the image
+/// are `identity`, so they never win.
+///
+/// Full rectangles are separated into a pass over the rows and one over the
+/// columns. Other shapes are split into horizontal runs, every distinct run is
+/// computed once for the whole image and the rows it covers are merged.
+fn filter(
+    src: &Matrix<u8>,
+    element: &Element,
+    op: fn(u8, u8) -> u8,
+    identity: u8,
+    reflect: bool,
+) -> Matrix<u8> {
+    if element.is_full() {
+        let (cx, cy) = ((element.width / 2) as i64, (element.height / 2) as i64);
+        let (dx, dy) = if reflect {
+            (
+                cx + 1 - element.width as i64,
+                cy + 1 - element.height as i64,
+            )
+        } else {
+            (-cx, -cy)
+        };
+        let rows = filter_rows(src, dx, element.width as usize, op, identity);
+        let columns = filter_rows(&transpose(&rows), dy, element.height as usize, op, identity);
+        return transpose(&columns);
+    }
+
+    // Vertical offsets of every distinct run.
+    let mut groups: BTreeMap<(i64, usize), Vec<i64>> = BTreeMap::new();
+    for (dy, dx, len) in element.runs() {
+        let (dy, dx) = if reflect {
+            (-dy, -(dx + len as i64 - 1))
+        } else {
+            (dy, dx)
+        };
+        groups.entry((dx, len)).or_default().push(dy);
+    }
+
+    let width = src.width() as usize;
+    let height = src.height() as i64;
+    let mut out = Matrix::new(src.width(), src.height());
+    out.as_slice_mut().fill(identity);
+    for ((dx, len), offsets) in groups {
+        let filtered = filter_rows(src, dx, len, op, identity);
+        for y in 0..height {
+            for &dy in &offsets {
+                let sy = y + dy;
+                if sy < 0 || sy >= height {
+                    continue;
+                }
+                let source = &filtered.as_slice()[sy as usize * width..][..width];
+                let target = &mut out.as_slice_mut()[y as usize * width..][..width];
+                for (target, &source) in target.iter_mut().zip(source) {
+                    *target = op(*target, source);
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Every pixel of a row becomes `op` of the `len` pixels starting `offset` away from it.
+fn filter_rows(
+    src: &Matrix<u8>,
+    offset: i64,
+    len: usize,
+    op: fn(u8, u8) -> u8,
+    identity: u8,
+) -> Matrix<u8> {
+    let width = src.width() as usize;
+    let mut out = Matrix::new(src.width(), src.height());
+    if width == 0 {
+        return out;
+    }
+    let mut padded = vec![identity; width + len - 1];
+    let mut forward = vec![identity; padded.len()];
+    let mut backward = vec![identity; padded.len()];
+    for (row, out) in src
+        .as_slice()
+        .chunks(width)
+        .zip(out.as_slice_mut().chunks_mut(width))
+    {
+        for (i, value) in padded.iter_mut().enumerate() {
+            let x = i as i64 + offset;
+            *value = if x >= 0 && x < width as i64 {
+                row[x as usize]
+            } else {
+                identity
+            };
+        }
+        running(&padded, len, op, out, &mut forward, &mut backward);
+    }
+    out
+}
+
+/// van Herk/Gil-Werman: `out[i]` is `op` of `values[i..i + len]`, using three
+/// comparisons per pixel whatever the length. The values are split into blocks
+/// of `len`, every window spans the tail of one block and the head of the next.
+fn running(
+    values: &[u8],
+    len: usize,
+    op: fn(u8, u8) -> u8,
+    out: &mut [u8],
+    forward: &mut [u8],
+    backward: &mut [u8],
+) {
+    let n = values.len();
+    for (i, &value) in values.iter().enumerate() {
+        forward[i] = if i % len == 0 {
+            value
+        } else {
+            op(forward[i - 1], value)
+        };
+    }
+    for (i, &value) in values.iter().enumerate().rev() {
+        backward[i] = if i == n - 1 || (i + 1) % len == 0 {
+            value
+        } else {
+            op(backward[i + 1], value)
+        };
+    }
+    for (i, out) in out.iter_mut().enumerate() {
+        *out = op(backward[i], forward[i + len - 1]);
+    }
+}
+
+fn transpose(src: &Matrix<u8>) -> Matrix<u8> {
+    let mut out = Matrix::new(src.height(), src.width());
+    for y in 0..src.height() {
+        for x in 0..src.width() {
+            out.set(y, x, src.get(x, y));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(width: u32, height: u32) -> Matrix<u8> {
+        let mut m = Matrix::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                m.set(x, y, ((x * 37 + y * 91) ^ (x * y * 13)) as u8);
+            }
+        }
+        m
+    }
+
+    /// `op` over the pixels under the element, reflected or not, skipping the outside.
+    fn brute_force(
+        src: &Matrix<u8>,
+        element: &Element,
+        op: fn(u8, u8) -> u8,
+        identity: u8,
+        reflect: bool,
+    ) -> Matrix<u8> {
+        let (cx, cy) = ((element.width / 2) as i64, (element.height / 2) as i64);
+        let (width, height) = (src.width() as i64, src.height() as i64);
+        let mut out = Matrix::new(src.width(), src.height());
+        for y in 0..height {
+            for x in 0..width {
+                let mut acc = identity;
+                for ey in 0..element.height as i64 {
+                    for ex in 0..element.width as i64 {
+                        if !element.mask[(ey * element.width as i64 + ex) as usize] {
+                            continue;
+                        }
+                        let (dx, dy) = if reflect {
+                            (cx - ex, cy - ey)
+                        } else {
+                            (ex - cx, ey - cy)
+                        };
+                        let (sx, sy) = (x + dx, y + dy);
+                        if sx >= 0 && sx < width && sy >= 0 && sy < height {
+                            acc = op(acc, src.get(sx as u32, sy as u32));
+                        }
+                    }
+                }
+                out.set(x as u32, y as u32, acc);
+            }
+        }
+        out
+    }
+
+    fn elements() -> Vec<Element> {
+        vec![
+            Element::square(0),
+            Element::square(2),
+            Element::disc(2),
+            Element::disc(3),
+            // Full, even sized rectangle.
+            Element::parse("111\n111").unwrap(),
+            // Asymmetric shapes that go through the runs.
+            Element::parse("110\n011\n001").unwrap(),
+            Element::parse("11\n10").unwrap(),
+            Element::parse("1.1.1\n..#..").unwrap(),
+        ]
+    }
+
+    #[test]
+    fn running_matches_naive_windows() {
+        let values: Vec<u8> = (0..13u32).map(|i| ((i * 97) % 251) as u8).collect();
+        for len in 1..=6 {
+            for &(op, identity) in &[(u8::min as fn(u8, u8) -> u8, u8::MAX), (u8::max, u8::MIN)] {
+                let count = values.len() - len + 1;
+                let mut out = vec![identity; count];
+                let mut forward = vec![identity; values.len()];
+                let mut backward = vec![identity; values.len()];
+                running(&values, len, op, &mut out, &mut forward, &mut backward);
+
+                let expected: Vec<u8> = values
+                    .windows(len)
+                    .map(|window| window.iter().copied().fold(identity, op))
+                    .collect();
+                assert_eq!(out, expected, "len {}", len);
+            }
+        }
+    }
+
+    #[test]
+    fn erode_matches_brute_force() {
+        let src = pattern(11, 9);
+        for element in elements() {
+            let expected = brute_force(&src, &element, u8::min, u8::MAX, false);
+            assert_eq!(
+                erode(&src, &element).as_slice(),
+                expected.as_slice(),
+                "{:?}",
+                element
+            );
+        }
+    }
+
+    #[test]
+    fn dilate_matches_brute_force() {
+        let src = pattern(11, 9);
+        for element in elements() {
+            let expected = brute_force(&src, &element, u8::max, u8::MIN, true);
+            assert_eq!(
+                dilate(&src, &element).as_slice(),
+                expected.as_slice(),
+                "{:?}",
+                element
+            );
+        }
+    }
+
+    #[test]
+    fn parse_reads_both_notations() {
+        let element = Element::parse("#.\n\n 0 1 \n").unwrap();
+        assert_eq!((element.width, element.height), (2, 2));
+        assert_eq!(*element.mask, vec![true, false, false, true]);
+    }
+
+    #[test]
+    fn parse_rejects_bad_elements() {
+        assert!(Element::parse("11\n1").is_err());
+        assert!(Element::parse("00\n00").is_err());
+        assert!(Element::parse("").is_err());
+        assert!(Element::parse("1x").is_err());
+    }
+}
@@ -1,22 +1,194 @@
-﻿#[allow(unused)]
-#[rustfmt::skip]
-fn gaussian(bytes: &[u8], width: usize, height: usize, out: &mut [u8]) {
+use crate::channels::{Matrix, View};
+
+/// Builds a normalized Gaussian kernel reaching `radius` pixels to each side.
+/// The standard deviation is a third of the radius, so that the tails that get
+/// cut off are negligible.
+pub(crate) fn gaussian_kernel(radius: f64) -> Vec<f32> {
+    if radius <= 0.0 {
+        return vec![1.0];
+    }
+
+    let sigma = radius / 3.0;
+    let reach = radius.ceil() as i64;
+    let mut kernel: Vec<f32> = (-reach..=reach)
+        .map(|x| (-(x * x) as f64 / (2.0 * sigma * sigma)).exp() as f32)
+        .collect();
+    let sum: f32 = kernel.iter().sum();
+    for k in kernel.iter_mut() {
+        *k /= sum;
+    }
+    kernel
+}
+
+/// Convolves the rows and then the columns with the same odd-sized kernel.
+/// Samples that fall outside of the image are clamped to the nearest edge.
+pub(crate) fn convolve_separable(src: &Matrix<f32>, kernel: &[f32]) -> Matrix<f32> {
+    let width = src.width() as i64;
+    let height = src.height() as i64;
+    let radius = (kernel.len() / 2) as i64;
+
+    let mut horizontal = Matrix::new(src.width(), src.height());
     for y in 0..height {
-        for x in 1..width - 1 {
-            out[y * width + x] = bytes[y * width + x - 1] / 4
-                + bytes[y * width + x] / 4
-                + bytes[y * width + x + 1] / 4;
+        for x in 0..width {
+            let mut acc = 0.0;
+            for (i, k) in kernel.iter().enumerate() {
+                let sx = (x + i as i64 - radius).max(0).min(width - 1);
+                acc += k * src.get(sx as u32, y as u32);
+            }
+            horizontal.set(x as u32, y as u32, acc);
         }
     }
 
-    unsafe {
-        for y in 1..height - 1 {
-            for x in 0..width {
-                *out.get_unchecked_mut(y * width + x) =
-                    *bytes.get_unchecked((y - 1) * width + x) / 4
-                        + *bytes.get_unchecked(y * width + x) / 4
-                        + *bytes.get_unchecked((y + 1) * width + x) / 4;
+    let mut out = Matrix::new(src.width(), src.height());
+    for y in 0..height {
+        for x in 0..width {
+            let mut acc = 0.0;
+            for (i, k) in kernel.iter().enumerate() {
+                let sy = (y + i as i64 - radius).max(0).min(height - 1);
+                acc += k * horizontal.get(x as u32, sy as u32);
             }
+            out.set(x as u32, y as u32, acc);
+        }
+    }
+    out
+}
+
+pub(crate) fn gaussian_blur(src: View<'_, u8>, radius: f64) -> Matrix<u8> {
+    let blurred = convolve_separable(&to_f32(&src), &gaussian_kernel(radius));
+    to_u8(&blurred)
+}
+
+/// Adds the difference between the image and its blurred copy back to the image.
+/// Differences below `threshold` are left alone, so flat areas do not get noisy.
+pub(crate) fn unsharp_mask(
+    src: &Matrix<f32>,
+    amount: f64,
+    radius: f64,
+    threshold: f64,
+) -> Matrix<f32> {
+    let blurred = convolve_separable(src, &gaussian_kernel(radius));
+
+    let mut out = Matrix::new(src.width(), src.height());
+    for ((out, &value), &blurred) in out
+        .as_slice_mut()
+        .iter_mut()
+        .zip(src.as_slice())
+        .zip(blurred.as_slice())
+    {
+        let difference = value - blurred;
+        *out = if difference.abs() as f64 >= threshold {
+            value + amount as f32 * difference
+        } else {
+            value
+        };
+    }
+    out
+}
+
+pub(crate) fn to_f32(src: &View<'_, u8>) -> Matrix<f32> {
+    let mut out = Matrix::new(src.width(), src.height());
+    for y in 0..src.height() {
+        for x in 0..src.width() {
+            out.set(x, y, src.get(x, y) as f32);
+        }
+    }
+    out
+}
+
+pub(crate) fn to_u8(src: &Matrix<f32>) -> Matrix<u8> {
+    let mut out = Matrix::new(src.width(), src.height());
+    for y in 0..src.height() {
+        for x in 0..src.width() {
+            out.set(x, y, src.get(x, y).round().max(0.0).min(255.0) as u8);
         }
     }
+    out
+}
+
+/// Replaces every value with its entry in the lookup table.
+pub(crate) fn lookup(src: View<'_, u8>, lut: &[u8; 256]) -> Matrix<u8> {
+    let mut out = Matrix::new(src.width(), src.height());
+    for y in 0..src.height() {
+        for x in 0..src.width() {
+            out.set(x, y, lut[src.get(x, y) as usize]);
+        }
+    }
+    out
+}
+
+/// Rec. 601 weights of the red, green and blue channel in the perceived brightness.
+pub(crate) const LUMA: [f32; 3] = [0.299, 0.587, 0.114];
+
+/// Perceived brightness of the colors.
+pub(crate) fn luminance(r: &View<'_, u8>, g: &View<'_, u8>, b: &View<'_, u8>) -> Matrix<f32> {
+    let mut out = Matrix::new(r.width(), r.height());
+    for y in 0..r.height() {
+        for x in 0..r.width() {
+            let value = LUMA[0] * r.get(x, y) as f32
+                + LUMA[1] * g.get(x, y) as f32
+                + LUMA[2] * b.get(x, y) as f32;
+            out.set(x, y, value);
+        }
+    }
+    out
+}
+
+/// Perceived brightness of a color with components in the 0-1 range.
+pub(crate) fn luma(r: f32, g: f32, b: f32) -> f32 {
+    LUMA[0] * r + LUMA[1] * g + LUMA[2] * b
+}
+
+/// Runs `f` on the colors of every pixel scaled to the 0-1 range and stores the results.
+pub(crate) fn map_pixels(
+    [r, g, b]: [View<'_, u8>; 3],
+    f: impl Fn(f32, f32, f32) -> (f32, f32, f32),
+) -> [Matrix<u8>; 3] {
+    let (width, height) = (r.width(), r.height());
+    let mut out = [
+        Matrix::new(width, height),
+        Matrix::new(width, height),
+        Matrix::new(width, height),
+    ];
+    let to_u8 = |value: f32| (value * 255.0).round().max(0.0).min(255.0) as u8;
+    for y in 0..height {
+        for x in 0..width {
+            let (nr, ng, nb) = f(
+                r.get(x, y) as f32 / 255.0,
+                g.get(x, y) as f32 / 255.0,
+                b.get(x, y) as f32 / 255.0,
+            );
+            out[0].set(x, y, to_u8(nr));
+            out[1].set(x, y, to_u8(ng));
+            out[2].set(x, y, to_u8(nb));
+        }
+    }
+    out
+}
+
+/// Scales a color channel by the alpha channel, both of them in the 0-255 range.
+pub(crate) fn premultiply(color: &Matrix<f32>, alpha: &Matrix<f32>) -> Matrix<f32> {
+    let mut out = Matrix::new(color.width(), color.height());
+    for ((out, c), a) in out
+        .as_slice_mut()
+        .iter_mut()
+        .zip(color.as_slice())
+        .zip(alpha.as_slice())
+    {
+        *out = c * a / 255.0;
+    }
+    out
+}
+
+/// Inverse of `premultiply`. Filters may overshoot, so the color is limited by the alpha.
+pub(crate) fn unpremultiply(color: &Matrix<f32>, alpha: &Matrix<f32>) -> Matrix<f32> {
+    let mut out = Matrix::new(color.width(), color.height());
+    for ((out, c), a) in out
+        .as_slice_mut()
+        .iter_mut()
+        .zip(color.as_slice())
+        .zip(alpha.as_slice())
+    {
+        *out = if *a > 0.0 { c.min(*a) * 255.0 / a } else { 0.0 };
+    }
+    out
 }
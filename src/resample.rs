@@ -0,0 +1,238 @@
+use std::f64::consts::PI;
+
+use druid::{Data, Point};
+
+use crate::channels::Matrix;
+use crate::image_buffer::ImageBuffer;
+use crate::ops;
+use crate::state::ChannelKind;
+
+/// Reconstruction filter used when pixels are resampled.
+#[derive(Clone, Copy, PartialEq, Eq, Data, Debug)]
+pub(crate) enum Filter {
+    Nearest,
+    Bilinear,
+    Bicubic,
+    Lanczos3,
+}
+
+impl Filter {
+    /// Radius of the kernel in source pixels.
+    pub(crate) fn support(self) -> f64 {
+        match self {
+            Filter::Nearest => 0.5,
+            Filter::Bilinear => 1.0,
+            Filter::Bicubic => 2.0,
+            Filter::Lanczos3 => 3.0,
+        }
+    }
+
+    pub(crate) fn weight(self, x: f64) -> f64 {
+        let x = x.abs();
+        match self {
+            Filter::Nearest => {
+                if x < 0.5 {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            Filter::Bilinear => (1.0 - x).max(0.0),
+            // Catmull-Rom spline, i.e. Keys' cubic with a = -0.5.
+            Filter::Bicubic => {
+                if x < 1.0 {
+                    (1.5 * x - 2.5) * x * x + 1.0
+                } else if x < 2.0 {
+                    ((-0.5 * x + 2.5) * x - 4.0) * x + 2.0
+                } else {
+                    0.0
+                }
+            }
+            Filter::Lanczos3 => {
+                if x < 3.0 {
+                    sinc(x) * sinc(x / 3.0)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        let x = x * PI;
+        x.sin() / x
+    }
+}
+
+/// Source pixels, and their weights, that make up a single destination pixel.
+struct Contribution {
+    start: usize,
+    weights: Vec<f32>,
+}
+
+/// When downsampling the kernel is stretched, so every source pixel contributes.
+fn contributions(src_len: u32, dst_len: u32, filter: Filter) -> Vec<Contribution> {
+    let scale = src_len as f64 / dst_len as f64;
+    let filter_scale = scale.max(1.0);
+    let support = filter.support() * filter_scale;
+
+    (0..dst_len)
+        .map(|i| {
+            let center = (i as f64 + 0.5) * scale;
+            if filter == Filter::Nearest {
+                return Contribution {
+                    start: (center as usize).min(src_len as usize - 1),
+                    weights: vec![1.0],
+                };
+            }
+
+            let start = (center - support).floor().max(0.0) as usize;
+            let end = ((center + support).ceil() as usize).min(src_len as usize);
+            let mut weights: Vec<f32> = (start..end)
+                .map(|j| filter.weight((j as f64 + 0.5 - center) / filter_scale) as f32)
+                .collect();
+            let sum: f32 = weights.iter().sum();
+            if sum != 0.0 {
+                for weight in weights.iter_mut() {
+                    *weight /= sum;
+                }
+            }
+            Contribution { start, weights }
+        })
+        .collect()
+}
+
+/// Scales the image, and its selection, to the given size.
+pub(crate) fn resize(image: &mut ImageBuffer, width: u32, height: u32, filter: Filter) {
+    resample_planes(image, |plane| resize_plane(plane, width, height, filter));
+}
+
+/// Rescales a single plane, first along the rows and then along the columns.
+fn resize_plane(src: &Matrix<f32>, width: u32, height: u32, filter: Filter) -> Matrix<f32> {
+    let src_width = src.width() as usize;
+    let rows = src.height() as usize;
+    let columns = contributions(src.width(), width, filter);
+    let src = src.as_slice();
+
+    let mut horizontal = Matrix::new(width, rows as u32);
+    let h = horizontal.as_slice_mut();
+    for y in 0..rows {
+        let row = &src[y * src_width..(y + 1) * src_width];
+        for (x, c) in columns.iter().enumerate() {
+            h[y * width as usize + x] = c
+                .weights
+                .iter()
+                .zip(&row[c.start..])
+                .map(|(w, v)| w * v)
+                .sum();
+        }
+    }
+
+    let mut out = Matrix::new(width, height);
+    let o = out.as_slice_mut();
+    let width = width as usize;
+    for (y, c) in contributions(rows as u32, height, filter)
+        .iter()
+        .enumerate()
+    {
+        for x in 0..width {
+            o[y * width + x] = c
+                .weights
+                .iter()
+                .enumerate()
+                .map(|(i, w)| w * h[(c.start + i) * width + x])
+                .sum();
+        }
+    }
+    out
+}
+
+/// Rebuilds every plane of the image with `f`. Colors are premultiplied by alpha while
+/// being resampled, so transparent pixels do not bleed their color into the visible ones.
+/// The selection is resampled along with the pixels and the hot selection is dropped.
+pub(crate) fn resample_planes(image: &mut ImageBuffer, f: impl Fn(&Matrix<f32>) -> Matrix<f32>) {
+    let alpha = ops::to_f32(&image.channel(ChannelKind::Alpha));
+    let resampled_alpha = f(&alpha);
+
+    let mut planes = [ChannelKind::Red, ChannelKind::Green, ChannelKind::Blue].map(|kind| {
+        let color = ops::premultiply(&ops::to_f32(&image.channel(kind)), &alpha);
+        Some(ops::to_u8(&ops::unpremultiply(
+            &f(&color),
+            &resampled_alpha,
+        )))
+    });
+    let mut alpha = Some(ops::to_u8(&resampled_alpha));
+    let mut selection = Some(ops::to_u8(&f(&ops::to_f32(
+        &image.channel(ChannelKind::Selection),
+    ))));
+
+    let (width, height) = (resampled_alpha.width(), resampled_alpha.height());
+    image.map_planes(|kind, _| match kind {
+        ChannelKind::Red => planes[0].take().unwrap(),
+        ChannelKind::Green => planes[1].take().unwrap(),
+        ChannelKind::Blue => planes[2].take().unwrap(),
+        ChannelKind::Alpha => alpha.take().unwrap(),
+        ChannelKind::Selection => selection.take().unwrap(),
+        ChannelKind::HotSelection => Matrix::new(width, height),
+    });
+}
+
+/// Value of the plane at a fractional position, where pixel (i, j) covers the area from
+/// (i, j) to (i + 1, j + 1). Everything outside of the plane is zero.
+pub(crate) fn sample(src: &Matrix<f32>, x: f64, y: f64, filter: Filter) -> f32 {
+    let (width, height) = (src.width() as i64, src.height() as i64);
+    if filter == Filter::Nearest {
+        let (sx, sy) = (x.floor() as i64, y.floor() as i64);
+        if sx < 0 || sy < 0 || sx >= width || sy >= height {
+            return 0.0;
+        }
+        return src.get(sx as u32, sy as u32);
+    }
+
+    let support = filter.support();
+    let taps = |center: f64| {
+        let first = (center - 0.5 - support).floor() as i64 + 1;
+        let last = (center - 0.5 + support).floor() as i64;
+        (first..=last).map(move |i| (i, filter.weight(i as f64 + 0.5 - center)))
+    };
+
+    let mut acc = 0.0;
+    let mut total = 0.0;
+    for (sy, wy) in taps(y) {
+        for (sx, wx) in taps(x) {
+            let weight = wx * wy;
+            total += weight;
+            if sx >= 0 && sy >= 0 && sx < width && sy < height {
+                acc += weight * src.get(sx as u32, sy as u32) as f64;
+            }
+        }
+    }
+    if total == 0.0 {
+        0.0
+    } else {
+        (acc / total) as f32
+    }
+}
+
+/// Builds a `width` × `height` plane by looking up the center of every pixel in `src`
+/// through `inverse`, a mapping from the destination to the source coordinates.
+pub(crate) fn warp(
+    src: &Matrix<f32>,
+    width: u32,
+    height: u32,
+    inverse: impl Fn(Point) -> Point,
+    filter: Filter,
+) -> Matrix<f32> {
+    let mut out = Matrix::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let p = inverse(Point::new(x as f64 + 0.5, y as f64 + 0.5));
+            out.set(x, y, sample(src, p.x, p.y, filter));
+        }
+    }
+    out
+}
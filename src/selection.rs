@@ -0,0 +1,370 @@
+use druid::{Data, Modifiers, Point};
+
+use crate::channels::{Matrix, View};
+use crate::image_buffer::ImageBuffer;
+use crate::ops;
+use crate::resample::{self, Filter};
+use crate::state::ChannelKind;
+
+/// Describes how a freshly made selection is merged into the current one.
+#[derive(Clone, Copy, PartialEq, Eq, Data, Debug)]
+pub(crate) enum SelectionMode {
+    Replace,
+    Add,
+    Subtract,
+    Intersect,
+    Xor,
+}
+
+impl SelectionMode {
+    /// Modifiers override the mode chosen in tool options: Shift adds,
+    /// Ctrl subtracts and both of them together intersect.
+    pub(crate) fn from_modifiers(mods: Modifiers, default: SelectionMode) -> Self {
+        match (mods.shift(), mods.ctrl()) {
+            (true, false) => SelectionMode::Add,
+            (false, true) => SelectionMode::Subtract,
+            (true, true) => SelectionMode::Intersect,
+            (false, false) => default,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn combine(self, current: u8, new: u8) -> u8 {
+        match self {
+            SelectionMode::Replace => new,
+            SelectionMode::Add => current.max(new),
+            SelectionMode::Subtract => current.min(255 - new),
+            SelectionMode::Intersect => current.min(new),
+            SelectionMode::Xor => (current as i16 - new as i16).unsigned_abs() as u8,
+        }
+    }
+}
+
+/// Operations from the Select > Modify menu.
+#[derive(Clone, Copy, PartialEq, Eq, Data, Debug)]
+pub(crate) enum Refinement {
+    Feather,
+    Grow,
+    Shrink,
+    Border,
+    Smooth,
+}
+
+impl Refinement {
+    pub(crate) fn title(self) -> &'static str {
+        match self {
+            Refinement::Feather => "Feather Selection",
+            Refinement::Grow => "Grow Selection",
+            Refinement::Shrink => "Shrink Selection",
+            Refinement::Border => "Border Selection",
+            Refinement::Smooth => "Smooth Selection",
+        }
+    }
+}
+
+/// Merges the hot selection into the selection and clears it afterwards.
+pub(crate) fn commit_hot_selection(image: &mut ImageBuffer, mode: SelectionMode) {
+    let (mut sel, mut hot_sel) = image.selection_mut();
+
+    for y in 0..sel.height() {
+        for x in 0..sel.width() {
+            sel.set(x, y, mode.combine(sel.get(x, y), hot_sel.get(x, y)));
+            hot_sel.set(x, y, 0);
+        }
+    }
+}
+
+pub(crate) fn select_all(image: &mut ImageBuffer) {
+    fill(image, 255);
+}
+
+pub(crate) fn deselect(image: &mut ImageBuffer) {
+    fill(image, 0);
+}
+
+pub(crate) fn invert(image: &mut ImageBuffer) {
+    let mut sel = image.channel_mut(ChannelKind::Selection);
+    for y in 0..sel.height() {
+        for x in 0..sel.width() {
+            sel.set(x, y, 255 - sel.get(x, y));
+        }
+    }
+}
+
+/// Shifts the selection by the given offset, the uncovered area becomes unselected.
+pub(crate) fn translate(image: &mut ImageBuffer, dx: i32, dy: i32) {
+    let sel = image.channel(ChannelKind::Selection);
+    let (width, height) = (sel.width() as i32, sel.height() as i32);
+    let mut moved = Matrix::new(sel.width(), sel.height());
+    for y in 0..height {
+        for x in 0..width {
+            let (sx, sy) = (x - dx, y - dy);
+            if sx >= 0 && sx < width && sy >= 0 && sy < height {
+                moved.set(x as u32, y as u32, sel.get(sx as u32, sy as u32));
+            }
+        }
+    }
+    image.replace_channel(ChannelKind::Selection, moved);
+}
+
+/// Distorts the selection, `source` tells where every point of the new one comes from.
+pub(crate) fn warp(image: &mut ImageBuffer, source: impl Fn(Point) -> Point) {
+    let (width, height) = image.size();
+    let sel = ops::to_f32(&image.channel(ChannelKind::Selection));
+    let warped = resample::warp(&sel, width, height, source, Filter::Bilinear);
+    image.replace_channel(ChannelKind::Selection, ops::to_u8(&warped));
+}
+
+fn fill(image: &mut ImageBuffer, value: u8) {
+    let mut sel = image.channel_mut(ChannelKind::Selection);
+    for y in 0..sel.height() {
+        for x in 0..sel.width() {
+            sel.set(x, y, value);
+        }
+    }
+}
+
+pub(crate) fn refine(image: &mut ImageBuffer, refinement: Refinement, radius: f64) {
+    let sel = image.channel(ChannelKind::Selection);
+    let refined = match refinement {
+        // Feathering spreads by the radius as the standard deviation, so the blur reaches three
+        // times as far.
+        Refinement::Feather => ops::gaussian_blur(sel, 3.0 * radius),
+        Refinement::Grow => grow(sel, radius),
+        Refinement::Shrink => shrink(sel, radius),
+        Refinement::Border => border(sel, radius),
+        Refinement::Smooth => smooth(sel, radius),
+    };
+    image.replace_channel(ChannelKind::Selection, refined);
+}
+
+#[inline]
+fn is_selected(value: u8) -> bool {
+    value >= 128
+}
+
+fn grow(sel: View<'_, u8>, radius: f64) -> Matrix<u8> {
+    let distances = squared_distances(&sel, is_selected);
+    let mut out = sel.to_matrix();
+    for y in 0..out.height() {
+        for x in 0..out.width() {
+            if distances.get(x, y) <= radius * radius {
+                out.set(x, y, 255);
+            }
+        }
+    }
+    out
+}
+
+/// Pixels outside of the image are considered selected, so a selection touching
+/// the edges does not shrink away from them.
+fn shrink(sel: View<'_, u8>, radius: f64) -> Matrix<u8> {
+    let distances = squared_distances(&sel, |value| !is_selected(value));
+    let mut out = sel.to_matrix();
+    for y in 0..out.height() {
+        for x in 0..out.width() {
+            if distances.get(x, y) <= radius * radius {
+                out.set(x, y, 0);
+            }
+        }
+    }
+    out
+}
+
+/// Selects a ring of the given width centered on the edge of the current selection.
+fn border(sel: View<'_, u8>, width: f64) -> Matrix<u8> {
+    let half = width / 2.0;
+    let to_selected = squared_distances(&sel, is_selected);
+    let to_unselected = squared_distances(&sel, |value| !is_selected(value));
+
+    let mut out = Matrix::new(sel.width(), sel.height());
+    for y in 0..out.height() {
+        for x in 0..out.width() {
+            let distance = if is_selected(sel.get(x, y)) {
+                to_unselected.get(x, y)
+            } else {
+                to_selected.get(x, y)
+            };
+            if distance <= half * half {
+                out.set(x, y, 255);
+            }
+        }
+    }
+    out
+}
+
+/// Majority filter: a pixel stays selected if most of its neighbourhood is selected.
+/// The neighbourhood is cropped at the image edges.
+fn smooth(sel: View<'_, u8>, radius: f64) -> Matrix<u8> {
+    let r = radius.round().max(1.0) as usize;
+    let width = sel.width() as usize;
+    let height = sel.height() as usize;
+    let stride = width + 1;
+
+    let mut sums = vec![0u64; stride * (height + 1)];
+    for y in 0..height {
+        let mut row_sum = 0;
+        for x in 0..width {
+            row_sum += sel.get(x as u32, y as u32) as u64;
+            sums[(y + 1) * stride + x + 1] = sums[y * stride + x + 1] + row_sum;
+        }
+    }
+
+    let mut out = Matrix::new(sel.width(), sel.height());
+    for y in 0..height {
+        let y0 = y.saturating_sub(r);
+        let y1 = (y + r + 1).min(height);
+        for x in 0..width {
+            let x0 = x.saturating_sub(r);
+            let x1 = (x + r + 1).min(width);
+            let sum = sums[y1 * stride + x1] + sums[y0 * stride + x0]
+                - sums[y0 * stride + x1]
+                - sums[y1 * stride + x0];
+            let count = ((x1 - x0) * (y1 - y0)) as u64;
+            if sum * 2 >= count * 255 {
+                out.set(x as u32, y as u32, 255);
+            }
+        }
+    }
+    out
+}
+
+/// Squared Euclidean distance from every pixel to the nearest pixel for which `is_feature`
+/// holds, computed with the separable algorithm by Felzenszwalb and Huttenlocher.
+fn squared_distances(src: &View<'_, u8>, is_feature: impl Fn(u8) -> bool) -> Matrix<f64> {
+    // Large enough to never be reached, small enough to keep the arithmetic finite.
+    const FAR: f64 = 1e20;
+
+    let width = src.width() as usize;
+    let height = src.height() as usize;
+    let mut distances = Matrix::new(src.width(), src.height());
+    if width == 0 || height == 0 {
+        return distances;
+    }
+
+    let d = distances.as_slice_mut();
+    for y in 0..height {
+        for x in 0..width {
+            d[y * width + x] = if is_feature(src.get(x as u32, y as u32)) {
+                0.0
+            } else {
+                FAR
+            };
+        }
+    }
+
+    let n = width.max(height);
+    let mut f = vec![0.0; n];
+    let mut column = vec![0.0; n];
+    let mut v = vec![0; n];
+    let mut z = vec![0.0; n + 1];
+
+    for x in 0..width {
+        for (y, value) in f[..height].iter_mut().enumerate() {
+            *value = d[y * width + x];
+        }
+        distance_transform_1d(&f[..height], &mut column[..height], &mut v, &mut z);
+        for (y, value) in column[..height].iter().enumerate() {
+            d[y * width + x] = *value;
+        }
+    }
+
+    for y in 0..height {
+        let row = &mut d[y * width..(y + 1) * width];
+        f[..width].copy_from_slice(row);
+        distance_transform_1d(&f[..width], row, &mut v, &mut z);
+    }
+
+    distances
+}
+
+/// Lower envelope of parabolas rooted at every sample of `f`.
+fn distance_transform_1d(f: &[f64], d: &mut [f64], v: &mut [usize], z: &mut [f64]) {
+    let intersection = |q: usize, p: usize| {
+        ((f[q] + (q * q) as f64) - (f[p] + (p * p) as f64)) / (2 * (q - p)) as f64
+    };
+
+    let mut k = 0;
+    v[0] = 0;
+    z[0] = f64::NEG_INFINITY;
+    z[1] = f64::INFINITY;
+    for q in 1..f.len() {
+        let mut s = intersection(q, v[k]);
+        while s <= z[k] {
+            k -= 1;
+            s = intersection(q, v[k]);
+        }
+        k += 1;
+        v[k] = q;
+        z[k] = s;
+        z[k + 1] = f64::INFINITY;
+    }
+
+    k = 0;
+    for (q, out) in d.iter_mut().enumerate() {
+        while z[k + 1] < q as f64 {
+            k += 1;
+        }
+        let r = q as f64 - v[k] as f64;
+        *out = r * r + f[v[k]];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn combine_fully_selected_and_unselected() {
+        let cases = [
+            (SelectionMode::Replace, [0, 255, 0, 255]),
+            (SelectionMode::Add, [0, 255, 255, 255]),
+            (SelectionMode::Subtract, [0, 0, 255, 0]),
+            (SelectionMode::Intersect, [0, 0, 0, 255]),
+            (SelectionMode::Xor, [0, 255, 255, 0]),
+        ];
+        // (current, new) pairs in the order of the expected values.
+        let inputs = [(0, 0), (0, 255), (255, 0), (255, 255)];
+        for &(mode, expected) in cases.iter() {
+            for (&(current, new), &expected) in inputs.iter().zip(expected.iter()) {
+                assert_eq!(
+                    mode.combine(current, new),
+                    expected,
+                    "{:?} of {} and {}",
+                    mode,
+                    current,
+                    new
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn combine_partial_selection() {
+        assert_eq!(SelectionMode::Add.combine(100, 50), 100);
+        assert_eq!(SelectionMode::Subtract.combine(200, 100), 155);
+        assert_eq!(SelectionMode::Intersect.combine(100, 50), 50);
+        assert_eq!(SelectionMode::Xor.combine(50, 200), 150);
+    }
+
+    #[test]
+    fn modifiers_override_the_mode() {
+        let mode = SelectionMode::Xor;
+        assert_eq!(
+            SelectionMode::from_modifiers(Modifiers::empty(), mode),
+            mode
+        );
+        assert_eq!(
+            SelectionMode::from_modifiers(Modifiers::SHIFT, mode),
+            SelectionMode::Add
+        );
+        assert_eq!(
+            SelectionMode::from_modifiers(Modifiers::CONTROL, mode),
+            SelectionMode::Subtract
+        );
+        assert_eq!(
+            SelectionMode::from_modifiers(Modifiers::SHIFT | Modifiers::CONTROL, mode),
+            SelectionMode::Intersect
+        );
+    }
+}
@@ -2,11 +2,25 @@ use std::cell::{Cell, RefCell, RefMut};
 use std::fmt::Formatter;
 use std::sync::Arc;
 
-use druid::{Color, Data, Lens};
+use druid::{Color, Data, Lens, UnitPoint};
 
-use crate::channels::Matrix;
+use crate::channel_mixer::{ChannelMixerParams, MixerOutput};
+use crate::color_balance::{ColorBalanceParams, PhotoFilterParams, TonalRange};
 use crate::color_picker;
-use crate::image_buffer::{merge_channels, ImageBuffer};
+use crate::convolution::EdgeMode;
+use crate::crop::{CropAspect, CropGuide};
+use crate::curves::CurvesParams;
+use crate::edges::EdgeParams;
+use crate::effects::Effect;
+use crate::floating::Floating;
+use crate::gradient_map::GradientMapParams;
+use crate::hue_saturation::{HueRange, HueSaturationParams};
+use crate::image_buffer::ImageBuffer;
+use crate::levels::{LevelsParams, ToneChannel};
+use crate::morphology::{Operation, Planes, Shape};
+use crate::resample::Filter;
+use crate::selection::SelectionMode;
+use crate::transform::{Scope, TransformParams, WarpMode};
 
 #[derive(Clone, Copy, PartialEq, Eq, Data, Debug)]
 pub(crate) enum ChannelKind {
@@ -35,6 +49,17 @@ impl std::fmt::Display for ChannelKind {
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Data, Debug)]
+pub(crate) enum ToolKind {
+    Draw,
+    Move,
+    BrushSelection,
+    ShapeSelection,
+    Crop,
+    Transform,
+    Warp,
+}
+
 #[derive(Clone, Debug, Data, Lens)]
 pub(crate) struct Channel {
     pub(crate) name: Option<String>,
@@ -71,22 +96,339 @@ impl LayerData {
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Data, Debug)]
+pub(crate) enum SizeUnit {
+    Pixels,
+    Percent,
+}
+
+/// Settings of the Image > Resize dialog.
+#[derive(Clone, Debug, Data, Lens)]
+pub(crate) struct ResizeParams {
+    /// Size of the document when the dialog was opened.
+    pub(crate) original_width: u32,
+    pub(crate) original_height: u32,
+    /// Requested size in the current unit.
+    pub(crate) width: f64,
+    pub(crate) height: f64,
+    pub(crate) unit: SizeUnit,
+    pub(crate) keep_aspect: bool,
+    pub(crate) filter: Filter,
+}
+
+impl ResizeParams {
+    pub(crate) fn new(width: u32, height: u32) -> Self {
+        Self {
+            original_width: width,
+            original_height: height,
+            width: width as f64,
+            height: height as f64,
+            unit: SizeUnit::Pixels,
+            keep_aspect: true,
+            filter: Filter::Bicubic,
+        }
+    }
+
+    /// Starts over from the given document size, keeping the unit and the filter.
+    pub(crate) fn reset(&mut self, width: u32, height: u32) {
+        let unit = self.unit;
+        *self = Self {
+            filter: self.filter,
+            keep_aspect: self.keep_aspect,
+            ..Self::new(width, height)
+        };
+        self.set_unit(unit);
+    }
+
+    pub(crate) fn set_width(&mut self, width: f64) {
+        self.width = width;
+        if self.keep_aspect {
+            self.height = match self.unit {
+                SizeUnit::Pixels => {
+                    width * self.original_height as f64 / self.original_width as f64
+                }
+                SizeUnit::Percent => width,
+            };
+        }
+    }
+
+    pub(crate) fn set_height(&mut self, height: f64) {
+        self.height = height;
+        if self.keep_aspect {
+            self.width = match self.unit {
+                SizeUnit::Pixels => {
+                    height * self.original_width as f64 / self.original_height as f64
+                }
+                SizeUnit::Percent => height,
+            };
+        }
+    }
+
+    pub(crate) fn set_unit(&mut self, unit: SizeUnit) {
+        let (width, height) = self.pixel_size();
+        self.unit = unit;
+        let (width, height) = match unit {
+            SizeUnit::Pixels => (width as f64, height as f64),
+            SizeUnit::Percent => (
+                width as f64 * 100.0 / self.original_width as f64,
+                height as f64 * 100.0 / self.original_height as f64,
+            ),
+        };
+        self.width = width;
+        self.height = height;
+    }
+
+    /// Requested size in pixels, at least one pixel in each direction.
+    pub(crate) fn pixel_size(&self) -> (u32, u32) {
+        let (width, height) = match self.unit {
+            SizeUnit::Pixels => (self.width, self.height),
+            SizeUnit::Percent => (
+                self.width * self.original_width as f64 / 100.0,
+                self.height * self.original_height as f64 / 100.0,
+            ),
+        };
+        (
+            width.round().max(1.0) as u32,
+            height.round().max(1.0) as u32,
+        )
+    }
+}
+
+/// What the area added by Image > Canvas Size is filled with.
+#[derive(Clone, Copy, PartialEq, Eq, Data, Debug)]
+pub(crate) enum CanvasFill {
+    Transparent,
+    BrushColor,
+    White,
+    Black,
+}
+
+/// Settings of the Image > Canvas Size dialog.
+#[derive(Clone, Debug, Data, Lens)]
+pub(crate) struct CanvasParams {
+    pub(crate) original_width: u32,
+    pub(crate) original_height: u32,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    /// Point of the current image that keeps its place on the new canvas.
+    pub(crate) anchor: UnitPoint,
+    pub(crate) fill: CanvasFill,
+}
+
+impl CanvasParams {
+    pub(crate) fn new(width: u32, height: u32) -> Self {
+        Self {
+            original_width: width,
+            original_height: height,
+            width,
+            height,
+            anchor: UnitPoint::CENTER,
+            fill: CanvasFill::Transparent,
+        }
+    }
+
+    /// Starts over from the given document size, keeping the anchor and the fill.
+    pub(crate) fn reset(&mut self, width: u32, height: u32) {
+        *self = Self {
+            anchor: self.anchor,
+            fill: self.fill,
+            ..Self::new(width, height)
+        };
+    }
+}
+
+/// Settings of the Rotate Arbitrary dialog.
+#[derive(Clone, Debug, Data, Lens)]
+pub(crate) struct RotateParams {
+    /// Clockwise, in degrees.
+    pub(crate) angle: f64,
+    pub(crate) filter: Filter,
+}
+
 #[derive(Clone, Debug, Data, Lens)]
 pub(crate) struct AppData {
     pub(crate) channels: Arc<Vec<Channel>>,
     pub(crate) layers: Arc<Vec<RefCell<Layer>>>,
+    pub(crate) floating: Arc<RefCell<Option<Floating>>>,
     #[data(ignore)]
     pub(crate) dirty: Cell<bool>,
+    /// Grows with every edit of the layers, so that what is computed from
+    /// them can tell when it is out of date.
+    #[data(ignore)]
+    pub(crate) layers_version: Cell<u64>,
     pub(crate) brush_color: color_picker::Color,
     pub(crate) brush_size: f64,
+    pub(crate) tool: ToolKind,
+    pub(crate) selection_mode: SelectionMode,
+    pub(crate) selection_radius: f64,
+    pub(crate) crop_aspect: CropAspect,
+    pub(crate) crop_guide: CropGuide,
+    pub(crate) crop_width: u32,
+    pub(crate) crop_height: u32,
+    pub(crate) resize: ResizeParams,
+    pub(crate) canvas: CanvasParams,
+    pub(crate) rotate: RotateParams,
+    pub(crate) transform: TransformParams,
+    pub(crate) warp_mode: WarpMode,
+    /// Number of mesh cells along each side.
+    pub(crate) warp_grid: u32,
+    pub(crate) warp_filter: Filter,
+    /// Effect shown on the active layer while its dialog is open.
+    pub(crate) preview: Option<Effect>,
+    pub(crate) show_preview: bool,
+    pub(crate) blur_radius: f64,
+    pub(crate) usm_amount: f64,
+    pub(crate) usm_radius: f64,
+    pub(crate) usm_threshold: f64,
+    pub(crate) box_blur_radius: f64,
+    pub(crate) median_radius: f64,
+    pub(crate) bilateral_sigma_space: f64,
+    pub(crate) bilateral_sigma_range: f64,
+    pub(crate) nlm_strength: f64,
+    pub(crate) nlm_search_radius: f64,
+    pub(crate) nlm_patch_radius: f64,
+    pub(crate) edges: EdgeParams,
+    pub(crate) morphology_operation: Operation,
+    pub(crate) morphology_shape: Shape,
+    pub(crate) morphology_radius: f64,
+    /// Text of the custom structuring element.
+    pub(crate) morphology_element: String,
+    pub(crate) morphology_planes: Planes,
+    pub(crate) levels: LevelsParams,
+    /// Channel whose levels the Levels dialog shows.
+    pub(crate) levels_channel: ToneChannel,
+    pub(crate) curves: CurvesParams,
+    pub(crate) curves_channel: ToneChannel,
+    /// Why the last curves file could not be saved or loaded.
+    pub(crate) curves_file_error: Option<String>,
+    /// In percent.
+    pub(crate) auto_contrast_clip: f64,
+    pub(crate) clahe_tile_size: f64,
+    pub(crate) clahe_clip_limit: f64,
+    pub(crate) brightness: f64,
+    pub(crate) contrast: f64,
+    /// In stops.
+    pub(crate) exposure: f64,
+    pub(crate) exposure_offset: f64,
+    pub(crate) exposure_gamma: f64,
+    pub(crate) gamma: f64,
+    pub(crate) shadows: f64,
+    pub(crate) highlights: f64,
+    pub(crate) shadows_highlights_radius: f64,
+    pub(crate) hue_saturation: HueSaturationParams,
+    /// Colors whose shift the Hue/Saturation dialog shows.
+    pub(crate) hue_range: HueRange,
+    pub(crate) color_balance: ColorBalanceParams,
+    /// Tonal range whose balance the Color Balance dialog shows.
+    pub(crate) color_balance_range: TonalRange,
+    pub(crate) channel_mixer: ChannelMixerParams,
+    /// Output channel whose mix the Channel Mixer dialog shows.
+    pub(crate) mixer_output: MixerOutput,
+    pub(crate) photo_filter: PhotoFilterParams,
+    pub(crate) vibrance: f64,
+    pub(crate) vibrance_saturation: f64,
+    pub(crate) threshold: f64,
+    pub(crate) posterize_levels: f64,
+    pub(crate) gradient_map: GradientMapParams,
+    /// Text of the kernel typed into the Custom Filter dialog.
+    pub(crate) custom_kernel: String,
+    /// Zero divides by the sum of the weights.
+    pub(crate) custom_divisor: f64,
+    pub(crate) custom_offset: f64,
+    pub(crate) custom_edges: EdgeMode,
 }
 
 impl AppData {
     pub fn layer_mut(&self, index: usize) -> RefMut<'_, Layer> {
         self.dirty.set(true);
+        self.layers_version.set(self.layers_version.get() + 1);
         self.layers[index].borrow_mut()
     }
 
+    /// Index of the first selected layer, which is the one being edited.
+    /// Layers are ordered from the topmost one.
+    pub(crate) fn active_layer(&self) -> usize {
+        self.layers
+            .iter()
+            .position(|layer| layer.borrow().is_selected)
+            .unwrap_or(0)
+    }
+
+    pub(crate) fn active_layer_mut(&self) -> RefMut<'_, Layer> {
+        self.layer_mut(self.active_layer())
+    }
+
+    pub(crate) fn document_size(&self) -> (u32, u32) {
+        self.layers[0].borrow().data.as_buffer().unwrap().size()
+    }
+
+    /// Puts a new layer on top of the others and makes it the only selected one.
+    pub(crate) fn add_layer(&mut self, name: Option<String>, image: ImageBuffer) {
+        let layers = Arc::make_mut(&mut self.layers);
+        for layer in layers.iter() {
+            layer.borrow_mut().is_selected = false;
+        }
+        layers.insert(
+            0,
+            RefCell::new(Layer {
+                name,
+                is_selected: true,
+                is_visible: true,
+                data: LayerData::RasterImage(image),
+            }),
+        );
+        self.dirty.set(true);
+        self.layers_version.set(self.layers_version.get() + 1);
+    }
+
+    /// Runs `edit` on the image of every layer, dropping the floating selection first.
+    pub(crate) fn edit_layers(&self, mut edit: impl FnMut(&mut ImageBuffer)) {
+        self.commit_floating();
+        for index in 0..self.layers.len() {
+            let mut layer = self.layer_mut(index);
+            edit(layer.data.as_buffer_mut().unwrap());
+        }
+    }
+
+    /// Fill color for new areas of the canvas as RGBA.
+    pub(crate) fn canvas_fill_color(&self, fill: CanvasFill) -> [u8; 4] {
+        match fill {
+            CanvasFill::Transparent => [0, 0, 0, 0],
+            CanvasFill::BrushColor => [
+                self.brush_color.r,
+                self.brush_color.g,
+                self.brush_color.b,
+                255,
+            ],
+            CanvasFill::White => [255, 255, 255, 255],
+            CanvasFill::Black => [0, 0, 0, 255],
+        }
+    }
+
+    /// Applies an edit to every layer or only to the active one.
+    pub(crate) fn edit_scope(&self, scope: Scope, mut edit: impl FnMut(&mut ImageBuffer)) {
+        match scope {
+            Scope::Document => self.edit_layers(edit),
+            Scope::Layer => {
+                self.commit_floating();
+                edit(self.active_layer_mut().data.as_buffer_mut().unwrap());
+            }
+        }
+    }
+
+    pub(crate) fn floating_mut(&self) -> RefMut<'_, Option<Floating>> {
+        self.dirty.set(true);
+        self.floating.borrow_mut()
+    }
+
+    /// Drops the floating pixels into the active layer.
+    pub(crate) fn commit_floating(&self) {
+        let floating = self.floating_mut().take();
+        if let Some(floating) = floating {
+            floating.commit(self.active_layer_mut().data.as_buffer_mut().unwrap());
+        }
+    }
+
     fn channel(&self, kind: ChannelKind) -> Option<&Channel> {
         match kind {
             ChannelKind::Red => self.channels.get(0),
@@ -98,7 +440,7 @@ impl AppData {
         }
     }
 
-    fn is_channel_visible(&self, kind: ChannelKind) -> bool {
+    pub(crate) fn is_channel_visible(&self, kind: ChannelKind) -> bool {
         self.channel(kind).map_or(false, |ch| ch.is_visible)
     }
 
@@ -107,46 +449,15 @@ impl AppData {
             return;
         }
 
-        let layer = self.layers[0].borrow();
-        let buff = layer.data.as_buffer().unwrap();
-        let r = buff.channel(ChannelKind::Red).as_slice().unwrap();
-        let g = buff.channel(ChannelKind::Green).as_slice().unwrap();
-        let b = buff.channel(ChannelKind::Blue).as_slice().unwrap();
-        let a = buff.channel(ChannelKind::Alpha).as_slice().unwrap();
-        let s = buff.channel(ChannelKind::Selection);
-        let hs = buff.channel(ChannelKind::HotSelection);
-
-        let overlay = if self.is_channel_visible(ChannelKind::Selection) {
-            let mut overlay = buff.channel(ChannelKind::Alpha).to_matrix();
-            for y in 0..overlay.height() {
-                for x in 0..overlay.width() {
-                    let s = s.get(x, y);
-                    let hs = hs.get(x, y);
-
-                    match (hs, s) {
-                        (255, _) => overlay.set(x, y, 96),
-                        (_, 255) => overlay.set(x, y, 128),
-                        _ => (),
-                    }
-                }
-            }
-            Some(overlay)
-        } else {
-            None
-        };
-
-        let alpha = overlay.as_ref().map(|x| x.as_slice()).unwrap_or(a);
-        let zeros = Matrix::new(buff.width(), buff.height());
-        let zeros = zeros.as_slice();
-        let rgba = &mut *layer.data.as_buffer().unwrap().interleaved.borrow_mut();
-        #[rustfmt::skip]
-        merge_channels(
-            if self.is_channel_visible(ChannelKind::Red) { r } else { zeros },
-            if self.is_channel_visible(ChannelKind::Green) { g } else { zeros },
-            if self.is_channel_visible(ChannelKind::Blue) { b } else { zeros },
-            alpha,
-            rgba,
-        );
+        let visible = [ChannelKind::Red, ChannelKind::Green, ChannelKind::Blue]
+            .map(|kind| self.is_channel_visible(kind));
+        for layer in self.layers.iter() {
+            let layer = layer.borrow();
+            layer.data.as_buffer().unwrap().refresh_interleaved(visible);
+        }
+        if let Some(floating) = self.floating.borrow().as_ref() {
+            floating.image.refresh_interleaved(visible);
+        }
 
         self.dirty.set(false);
     }
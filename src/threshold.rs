@@ -0,0 +1,91 @@
+use crate::channels::{Matrix, View};
+use crate::ops;
+
+/// Turns pixels with luminance of at least `level` white and all others black.
+pub(crate) fn threshold(channels: [View<'_, u8>; 3], level: u8) -> [Matrix<u8>; 3] {
+    let luminance = ops::luminance(&channels[0], &channels[1], &channels[2]);
+    let mut out = Matrix::new(luminance.width(), luminance.height());
+    for (out, &value) in out.as_slice_mut().iter_mut().zip(luminance.as_slice()) {
+        *out = if value.round() >= level as f32 {
+            255
+        } else {
+            0
+        };
+    }
+    [out.clone(), out.clone(), out]
+}
+
+/// Level that best separates the histogram into two classes, found with
+/// Otsu's method by maximizing the variance between the classes.
+pub(crate) fn otsu(histogram: &[u32; 256]) -> u8 {
+    let total: f64 = histogram.iter().map(|&n| n as f64).sum();
+    let total_sum: f64 = histogram
+        .iter()
+        .enumerate()
+        .map(|(value, &n)| value as f64 * n as f64)
+        .sum();
+
+    let mut best = (0.0, 128);
+    let mut below = 0.0;
+    let mut below_sum = 0.0;
+    // Pixels below the threshold are those up to `value`, so the level is one more.
+    for (value, &n) in histogram.iter().enumerate().take(255) {
+        below += n as f64;
+        below_sum += value as f64 * n as f64;
+        let above = total - below;
+        if below == 0.0 || above == 0.0 {
+            continue;
+        }
+        let difference = below_sum / below - (total_sum - below_sum) / above;
+        let variance = below * above * difference * difference;
+        if variance > best.0 {
+            best = (variance, value + 1);
+        }
+    }
+    best.1 as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn otsu_splits_bimodal_histogram() {
+        let mut histogram = [0u32; 256];
+        for offset in 0..10 {
+            histogram[40 + offset] = 100;
+            histogram[190 + offset] = 300;
+        }
+        let level = otsu(&histogram);
+        assert!((50..=190).contains(&level), "level {}", level);
+    }
+
+    #[test]
+    fn otsu_separates_two_values() {
+        let mut histogram = [0u32; 256];
+        histogram[30] = 500;
+        histogram[31] = 7;
+        let level = otsu(&histogram);
+        assert_eq!(level, 31);
+    }
+
+    #[test]
+    fn otsu_defaults_without_split() {
+        assert_eq!(otsu(&[0; 256]), 128);
+        let mut histogram = [0u32; 256];
+        histogram[77] = 1000;
+        assert_eq!(otsu(&histogram), 128);
+    }
+
+    #[test]
+    fn threshold_includes_level() {
+        let mut gray = Matrix::new(3, 1);
+        gray.set(0, 0, 99);
+        gray.set(1, 0, 100);
+        gray.set(2, 0, 101);
+        let out = threshold([gray.as_view(), gray.as_view(), gray.as_view()], 100);
+        for channel in &out {
+            assert_eq!(channel.as_slice(), &[0, 255, 255]);
+        }
+    }
+}
@@ -0,0 +1,169 @@
+use std::f64::consts::FRAC_PI_4;
+
+use crate::channels::{Matrix, View};
+use crate::ops;
+
+/// Builds a lookup table from a function of values in the 0-1 range.
+fn make_lut(f: impl Fn(f64) -> f64) -> [u8; 256] {
+    let mut lut = [0u8; 256];
+    for (value, out) in lut.iter_mut().enumerate() {
+        *out = (f(value as f64 / 255.0) * 255.0)
+            .round()
+            .max(0.0)
+            .min(255.0) as u8;
+    }
+    lut
+}
+
+/// Decodes the sRGB transfer curve.
+pub(crate) fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub(crate) fn linear_to_srgb(value: f64) -> f64 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Both amounts are in percent from -100 to 100. Brightness moves the values
+/// towards white or black, contrast tilts them around the middle gray.
+pub(crate) fn brightness_contrast(brightness: f64, contrast: f64) -> [u8; 256] {
+    let brightness = brightness / 100.0;
+    // From flat at -100 to a vertical step at 100.
+    let slope = ((contrast / 100.0).max(-1.0).min(0.99) * FRAC_PI_4 + FRAC_PI_4).tan();
+    make_lut(|value| {
+        let value = if brightness < 0.0 {
+            value * (1.0 + brightness)
+        } else {
+            value + (1.0 - value) * brightness
+        };
+        (value - 0.5) * slope + 0.5
+    })
+}
+
+/// Exposure change in stops done in linear light, the offset shifts the linear
+/// values and the gamma is applied to the result.
+pub(crate) fn exposure(stops: f64, offset: f64, gamma: f64) -> [u8; 256] {
+    let gain = 2f64.powf(stops);
+    make_lut(|value| {
+        let linear = (srgb_to_linear(value) * gain + offset).max(0.0);
+        linear_to_srgb(linear.min(1.0)).powf(1.0 / gamma.max(0.01))
+    })
+}
+
+/// Above one brightens the midtones, below one darkens them.
+pub(crate) fn gamma(gamma: f64) -> [u8; 256] {
+    make_lut(|value| value.powf(1.0 / gamma.max(0.01)))
+}
+
+/// Lifts the dark areas and darkens the bright ones. Whether an area is dark is
+/// decided by the luminance blurred with `radius`, so that details keep their
+/// contrast. Amounts are in percent.
+pub(crate) fn shadows_highlights(
+    channels: [View<'_, u8>; 3],
+    shadows: f64,
+    highlights: f64,
+    radius: f64,
+) -> [Matrix<u8>; 3] {
+    let luminance = ops::luminance(&channels[0], &channels[1], &channels[2]);
+    let surroundings = ops::convolve_separable(&luminance, &ops::gaussian_kernel(radius));
+    let shadows = (shadows / 100.0) as f32;
+    let highlights = (highlights / 100.0) as f32;
+
+    channels.map(|channel| {
+        let mut out = Matrix::new(channel.width(), channel.height());
+        for y in 0..channel.height() {
+            for x in 0..channel.width() {
+                let value = channel.get(x, y) as f32 / 255.0;
+                let around = surroundings.get(x, y) / 255.0;
+                let shadow = (1.0 - around) * (1.0 - around);
+                let highlight = around * around;
+                let value =
+                    value + shadows * shadow * (1.0 - value) - highlights * highlight * value;
+                out.set(x, y, value * 255.0);
+            }
+        }
+        ops::to_u8(&out)
+    })
+}
+
+pub(crate) fn invert() -> [u8; 256] {
+    make_lut(|value| 1.0 - value)
+}
+
+/// Reduces every channel to `levels` evenly spaced values, at least two.
+pub(crate) fn posterize(levels: u32) -> [u8; 256] {
+    let steps = levels.max(2) as f64 - 1.0;
+    make_lut(|value| (value * steps).round() / steps)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_identity(lut: &[u8; 256]) -> bool {
+        lut.iter()
+            .enumerate()
+            .all(|(value, &out)| out as usize == value)
+    }
+
+    #[test]
+    fn srgb_round_trip() {
+        for value in 0..=255 {
+            let value = value as f64 / 255.0;
+            assert!((linear_to_srgb(srgb_to_linear(value)) - value).abs() < 1e-9);
+        }
+        assert_eq!(srgb_to_linear(0.0), 0.0);
+        assert!((srgb_to_linear(1.0) - 1.0).abs() < 1e-12);
+        assert!((srgb_to_linear(0.5) - 0.214).abs() < 1e-3);
+    }
+
+    #[test]
+    fn neutral_settings_keep_values() {
+        assert!(is_identity(&brightness_contrast(0.0, 0.0)));
+        assert!(is_identity(&exposure(0.0, 0.0, 1.0)));
+        assert!(is_identity(&gamma(1.0)));
+    }
+
+    #[test]
+    fn brightness_moves_towards_white_or_black() {
+        let brighter = brightness_contrast(50.0, 0.0);
+        assert_eq!((brighter[0], brighter[255]), (128, 255));
+        let darker = brightness_contrast(-50.0, 0.0);
+        assert_eq!((darker[0], darker[255]), (0, 128));
+    }
+
+    #[test]
+    fn contrast_tilts_around_the_middle() {
+        let flat = brightness_contrast(0.0, -100.0);
+        assert!(flat.iter().all(|&v| v == 128));
+        let steep = brightness_contrast(0.0, 50.0);
+        assert!(steep[64] < 64 && steep[192] > 192);
+        assert_eq!(steep[127] as u32 + steep[128] as u32, 255);
+    }
+
+    #[test]
+    fn one_stop_doubles_linear_light() {
+        let lut = exposure(1.0, 0.0, 1.0);
+        for value in [10u8, 50, 100] {
+            let linear = srgb_to_linear(value as f64 / 255.0) * 2.0;
+            let expected = (linear_to_srgb(linear) * 255.0).round() as u8;
+            assert_eq!(lut[value as usize], expected);
+        }
+        assert_eq!(lut[255], 255);
+    }
+
+    #[test]
+    fn gamma_above_one_brightens() {
+        let lut = gamma(2.0);
+        assert_eq!((lut[0], lut[255]), (0, 255));
+        assert_eq!(lut[64], ((64.0f64 / 255.0).sqrt() * 255.0).round() as u8);
+    }
+}
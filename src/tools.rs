@@ -1,11 +1,18 @@
 use std::ops::Neg;
 
-use druid::kurbo::Circle;
-use druid::piet::StrokeStyle;
+use druid::kurbo::{BezPath, Circle, Line};
+use druid::piet::{InterpolationMode, StrokeStyle};
 use druid::{Affine, Color, Modifiers, PaintCtx, Point, Rect, RenderContext, Vec2};
 
 use crate::brushes::{BasicBrush, Brush};
+use crate::channels::Matrix;
+use crate::crop::{self, CropAspect, Edge, Handle};
+use crate::floating::Floating;
+use crate::image_buffer::ImageBuffer;
+use crate::resample::{self, Filter};
+use crate::selection::{self, SelectionMode};
 use crate::state::{AppData, ChannelKind};
+use crate::transform::{self, Mesh, TransformParams, WarpMode};
 use crate::utils::interpolate_points;
 
 pub(crate) trait Tool {
@@ -13,17 +20,40 @@ pub(crate) trait Tool {
     fn mouse_down(&mut self, pos: Point, transform: Affine, data: &AppData);
     fn mouse_up(&mut self, transform: Affine, data: &AppData);
     fn wheel(&mut self, pos: Point, delta: Vec2, mods: Modifiers);
-    fn overlay(&mut self, ctx: &mut PaintCtx, pos: Point, scale: f64);
+    fn overlay(&mut self, ctx: &mut PaintCtx, pos: Point, transform: Affine, data: &AppData);
 }
 
 pub struct DrawTool {
-    brush_size: u32,
-    color: [u8; 4],
+    pub(crate) brush_size: u32,
+    pub(crate) color: [u8; 4],
+    /// Channels as they were before the stroke, kept only when the painting is masked.
+    origin: Option<Vec<Matrix<u8>>>,
 }
 
 impl DrawTool {
     pub(crate) fn new(brush_size: u32, color: [u8; 4]) -> Self {
-        DrawTool { brush_size, color }
+        DrawTool {
+            brush_size,
+            color,
+            origin: None,
+        }
+    }
+
+    fn dab(&self, image: &mut ImageBuffer, kind: ChannelKind, index: usize, p: Point) {
+        let brush = BasicBrush::new(self.brush_size, self.color[index]);
+        match self.origin {
+            Some(ref origin) => {
+                let (channel, mask) = image.channel_and_selection_mut(kind);
+                brush.apply_masked(
+                    channel,
+                    origin[index].as_view(),
+                    mask,
+                    p.x as u32,
+                    p.y as u32,
+                );
+            }
+            None => brush.apply(image.channel_mut(kind), p.x as u32, p.y as u32),
+        }
     }
 }
 
@@ -33,17 +63,11 @@ impl Tool for DrawTool {
         let begin = transform * previous_pos;
         let end = transform * pos;
 
+        let mut layer = data.active_layer_mut();
+        let image = layer.data.as_buffer_mut().unwrap();
         for index in 0..4 {
-            let mut layer = data.layer_mut(0);
-            let image = layer.data.as_buffer_mut().unwrap();
             let kind = data.channels[index].kind;
-            interpolate_points(begin, end, |p| {
-                BasicBrush::new(self.brush_size, self.color[index]).apply(
-                    image.channel_mut(kind),
-                    p.x as u32,
-                    p.y as u32,
-                );
-            });
+            interpolate_points(begin, end, |p| self.dab(image, kind, index, p));
         }
     }
 
@@ -51,25 +75,31 @@ impl Tool for DrawTool {
         let transform = transform.inverse();
         let p = transform * pos;
 
+        let mut layer = data.active_layer_mut();
+        let image = layer.data.as_buffer_mut().unwrap();
+        self.origin = if image.has_selection() {
+            Some(
+                (0..4)
+                    .map(|index| image.channel(data.channels[index].kind).to_matrix())
+                    .collect(),
+            )
+        } else {
+            None
+        };
+
         for index in 0..4 {
-            BasicBrush::new(self.brush_size, self.color[index]).apply(
-                data.layers[0]
-                    .borrow_mut()
-                    .data
-                    .as_buffer_mut()
-                    .unwrap()
-                    .channel_mut(data.channels[index].kind),
-                p.x as u32,
-                p.y as u32,
-            );
+            self.dab(image, data.channels[index].kind, index, p);
         }
     }
 
-    fn mouse_up(&mut self, _transform: Affine, _data: &AppData) {}
+    fn mouse_up(&mut self, _transform: Affine, _data: &AppData) {
+        self.origin = None;
+    }
 
     fn wheel(&mut self, _pos: Point, _delta: Vec2, _mods: Modifiers) {}
 
-    fn overlay(&mut self, ctx: &mut PaintCtx, pos: Point, scale: f64) {
+    fn overlay(&mut self, ctx: &mut PaintCtx, pos: Point, transform: Affine, _data: &AppData) {
+        let scale = transform.as_coeffs()[0];
         ctx.with_save(|ctx| {
             let c = Color::rgb8(90, 100, 20);
             ctx.stroke(
@@ -83,11 +113,12 @@ impl Tool for DrawTool {
 
 pub(crate) struct BrushSelectionTool {
     brush_size: u32,
+    mode: SelectionMode,
 }
 
 impl BrushSelectionTool {
-    pub(crate) fn new(brush_size: u32) -> Self {
-        Self { brush_size }
+    pub(crate) fn new(brush_size: u32, mode: SelectionMode) -> Self {
+        Self { brush_size, mode }
     }
 }
 
@@ -97,7 +128,7 @@ impl Tool for BrushSelectionTool {
         let begin = transform * previous_pos;
         let end = transform * pos;
 
-        let mut layer = data.layer_mut(0);
+        let mut layer = data.active_layer_mut();
         let image = layer.data.as_buffer_mut().unwrap();
         interpolate_points(begin, end, |p| {
             BasicBrush::new(self.brush_size, 255).apply(
@@ -108,28 +139,50 @@ impl Tool for BrushSelectionTool {
         });
     }
 
-    fn mouse_down(&mut self, _pos: Point, _transform: Affine, _data: &AppData) {}
+    fn mouse_down(&mut self, pos: Point, transform: Affine, data: &AppData) {
+        let p = transform.inverse() * pos;
 
-    fn mouse_up(&mut self, _transform: Affine, data: &AppData) {
-        let mut layer = data.layer_mut(0);
-        let (mut sel, mut hot_sel) = layer.data.as_buffer_mut().unwrap().selection_mut();
+        let mut layer = data.active_layer_mut();
+        BasicBrush::new(self.brush_size, 255).apply(
+            layer
+                .data
+                .as_buffer_mut()
+                .unwrap()
+                .channel_mut(ChannelKind::HotSelection),
+            p.x as u32,
+            p.y as u32,
+        );
+    }
 
-        for y in 0..sel.height() {
-            for x in 0..sel.width() {
-                sel.set(x, y, sel.get(x, y).saturating_add(hot_sel.get(x, y)));
-                hot_sel.set(x, y, 0);
-            }
-        }
+    fn mouse_up(&mut self, _transform: Affine, data: &AppData) {
+        let mut layer = data.active_layer_mut();
+        selection::commit_hot_selection(layer.data.as_buffer_mut().unwrap(), self.mode);
     }
 
     fn wheel(&mut self, _pos: Point, _delta: Vec2, _mods: Modifiers) {}
 
-    fn overlay(&mut self, _ctx: &mut PaintCtx, _pos: Point, _scale: f64) {}
+    fn overlay(&mut self, ctx: &mut PaintCtx, pos: Point, transform: Affine, _data: &AppData) {
+        let scale = transform.as_coeffs()[0];
+        ctx.with_save(|ctx| {
+            let c = Color::rgb8(0, 0, 0);
+            let mut ss = StrokeStyle::new();
+            ss.set_dash_pattern(vec![3.0, 1.0]);
+            ss.set_dash_offset(0.0);
+
+            ctx.stroke_styled(
+                Circle::new(pos, (self.brush_size as f64) / 2.0 * scale),
+                &c,
+                1.0,
+                &ss,
+            );
+        });
+    }
 }
 
 pub(crate) struct ShapeSelectionTool {
     pub(crate) start_moving_pos: Option<Point>,
     pub(crate) end_moving_pos: Option<Point>,
+    pub(crate) mode: SelectionMode,
 }
 
 impl ShapeSelectionTool {
@@ -137,6 +190,7 @@ impl ShapeSelectionTool {
         Self {
             start_moving_pos: None,
             end_moving_pos: None,
+            mode: SelectionMode::Replace,
         }
     }
 }
@@ -154,50 +208,834 @@ impl Tool for ShapeSelectionTool {
 
     fn mouse_down(&mut self, pos: Point, _transform: Affine, _data: &AppData) {
         self.start_moving_pos = Some(pos);
+        self.end_moving_pos = None;
     }
 
     fn mouse_up(&mut self, transform: Affine, data: &AppData) {
+        let mut layer = data.active_layer_mut();
+        let image = layer.data.as_buffer_mut().unwrap();
+
+        let (start, end) = match (self.start_moving_pos.take(), self.end_moving_pos.take()) {
+            (Some(start), Some(end)) => (start, end),
+            _ => {
+                // A plain click drops the selection, just like in other editors.
+                if self.mode == SelectionMode::Replace {
+                    selection::deselect(image);
+                }
+                return;
+            }
+        };
+
         let transform = transform.inverse();
-        let start = transform * self.start_moving_pos.unwrap();
-        let end = transform * self.end_moving_pos.unwrap();
-        let x1 = (start.x.min(end.x)) as u32;
-        let x2 = (start.x.max(end.x)) as u32;
-        let y1 = (start.y.min(end.y)) as u32;
-        let y2 = (start.y.max(end.y)) as u32;
-
-        let mut layer = data.layer_mut(0);
-        let mut v = layer
-            .data
-            .as_buffer_mut()
-            .unwrap()
-            .channel_mut(ChannelKind::Selection);
+        let start = transform * start;
+        let end = transform * end;
+
+        let (width, height) = image.size();
+        let clamp_x = |x: f64| x.max(0.0).min(width as f64 - 1.0) as u32;
+        let clamp_y = |y: f64| y.max(0.0).min(height as f64 - 1.0) as u32;
+        let x1 = clamp_x(start.x.min(end.x));
+        let x2 = clamp_x(start.x.max(end.x));
+        let y1 = clamp_y(start.y.min(end.y));
+        let y2 = clamp_y(start.y.max(end.y));
+
+        let mut v = image.channel_mut(ChannelKind::HotSelection);
         for y in y1..=y2 {
             for x in x1..=x2 {
                 v.set(x, y, 255);
             }
         }
+
+        selection::commit_hot_selection(image, self.mode);
     }
 
     fn wheel(&mut self, _pos: Point, _delta: Vec2, _mods: Modifiers) {}
 
-    fn overlay(&mut self, ctx: &mut PaintCtx, _pos: Point, _scale: f64) {
+    fn overlay(&mut self, ctx: &mut PaintCtx, _pos: Point, _transform: Affine, _data: &AppData) {
+        let (start, end) = match (self.start_moving_pos, self.end_moving_pos) {
+            (Some(start), Some(end)) => (start, end),
+            _ => return,
+        };
+
         ctx.with_save(|ctx| {
             let c = Color::rgb8(0, 0, 0);
             let mut ss = StrokeStyle::new();
             ss.set_dash_pattern(vec![3.0, 1.0]);
             ss.set_dash_offset(0.0);
 
-            ctx.stroke_styled(
-                Rect::from_points(self.start_moving_pos.unwrap(), self.end_moving_pos.unwrap()),
-                &c,
-                1.0,
-                &ss,
+            ctx.stroke_styled(Rect::from_points(start, end), &c, 1.0, &ss);
+        });
+    }
+}
+
+/// Moves the pixels of the active layer, or only the selected ones. The pixels float
+/// while being dragged and land in the layer, along with the selection, on release.
+pub(crate) struct MoveTool {
+    drag: FloatingTool,
+    origin: (i32, i32),
+    /// Active layer before the pixels were lifted, put back when they end up where they were.
+    unmoved: Option<ImageBuffer>,
+}
+
+impl MoveTool {
+    pub(crate) fn new() -> Self {
+        Self {
+            drag: FloatingTool::new(),
+            origin: (0, 0),
+            unmoved: None,
+        }
+    }
+}
+
+impl Tool for MoveTool {
+    fn mouse_move(&mut self, pos: Point, previous_pos: Point, transform: Affine, data: &AppData) {
+        self.drag.mouse_move(pos, previous_pos, transform, data);
+    }
+
+    fn mouse_down(&mut self, pos: Point, transform: Affine, data: &AppData) {
+        let floating = {
+            let mut layer = data.active_layer_mut();
+            let image = layer.data.as_buffer_mut().unwrap();
+            self.unmoved = Some(image.clone());
+            Floating::lift(image)
+        };
+        self.origin = (floating.x, floating.y);
+        *data.floating_mut() = Some(floating);
+        self.drag.mouse_down(pos, transform, data);
+    }
+
+    fn mouse_up(&mut self, _transform: Affine, data: &AppData) {
+        let offset = data
+            .floating
+            .borrow()
+            .as_ref()
+            .map(|floating| (floating.x - self.origin.0, floating.y - self.origin.1));
+        let unmoved = self.unmoved.take();
+
+        if let (Some((0, 0)), Some(unmoved)) = (offset, unmoved) {
+            // Drawing the lifted pixels back over what the cut left behind would
+            // fade the partially selected ones.
+            data.floating_mut().take();
+            *data.active_layer_mut().data.as_buffer_mut().unwrap() = unmoved;
+        } else if let Some((dx, dy)) = offset {
+            data.commit_floating();
+            let mut layer = data.active_layer_mut();
+            selection::translate(layer.data.as_buffer_mut().unwrap(), dx, dy);
+        }
+    }
+
+    fn wheel(&mut self, _pos: Point, _delta: Vec2, _mods: Modifiers) {}
+
+    fn overlay(&mut self, _ctx: &mut PaintCtx, _pos: Point, _transform: Affine, _data: &AppData) {}
+}
+
+enum CropDrag {
+    Create(Point),
+    Move(Point),
+    Resize(Handle),
+}
+
+/// Frames the part of the document to keep. The rectangle can be adjusted by its handles
+/// until it is committed, which crops every layer.
+pub(crate) struct CropTool {
+    /// In the document coordinates.
+    rect: Option<Rect>,
+    drag: Option<CropDrag>,
+    start_rect: Rect,
+}
+
+impl CropTool {
+    /// Distance in screen pixels at which a handle can be grabbed.
+    const HANDLE_RADIUS: f64 = 6.0;
+
+    pub(crate) fn new() -> Self {
+        Self {
+            rect: None,
+            drag: None,
+            start_rect: Rect::ZERO,
+        }
+    }
+
+    /// Crops the document to the current rectangle, returns false when there is none.
+    pub(crate) fn commit(&mut self, data: &AppData) -> bool {
+        let rect = match self.rect.take() {
+            Some(rect) => rect,
+            None => return false,
+        };
+
+        let rect = rect.round().intersect(Self::document_rect(data));
+        if rect.width() >= 1.0 && rect.height() >= 1.0 {
+            let (x, y) = (rect.x0 as u32, rect.y0 as u32);
+            let (width, height) = (rect.width() as u32, rect.height() as u32);
+            data.edit_layers(|image| image.crop(x, y, width, height));
+        }
+        true
+    }
+
+    pub(crate) fn cancel(&mut self) -> bool {
+        self.drag = None;
+        self.rect.take().is_some()
+    }
+
+    fn handles(data: &AppData) -> &'static [Handle] {
+        match data.crop_aspect {
+            CropAspect::Free => &Handle::ALL,
+            CropAspect::FixedSize => &[],
+            _ => &Handle::CORNERS,
+        }
+    }
+
+    fn ratio(data: &AppData) -> Option<f64> {
+        data.crop_aspect
+            .ratio(data.document_size(), (data.crop_width, data.crop_height))
+    }
+
+    fn document_rect(data: &AppData) -> Rect {
+        let (width, height) = data.document_size();
+        Rect::new(0.0, 0.0, width as f64, height as f64)
+    }
+
+    fn fixed_rect(center: Point, data: &AppData) -> Rect {
+        Rect::from_center_size(center, (data.crop_width as f64, data.crop_height as f64))
+    }
+}
+
+impl Tool for CropTool {
+    fn mouse_move(&mut self, pos: Point, _previous_pos: Point, transform: Affine, data: &AppData) {
+        let bounds = Self::document_rect(data);
+        let p = transform.inverse() * pos;
+        let clamped = Point::new(p.x.max(0.0).min(bounds.x1), p.y.max(0.0).min(bounds.y1));
+
+        let rect = match self.drag {
+            Some(CropDrag::Create(_)) if data.crop_aspect == CropAspect::FixedSize => {
+                crop::keep_inside(Self::fixed_rect(p, data), bounds)
+            }
+            Some(CropDrag::Create(start)) => match Self::ratio(data) {
+                Some(ratio) => crop::constrain(start, clamped, ratio),
+                None => Rect::from_points(start, clamped),
+            },
+            Some(CropDrag::Move(start)) => crop::keep_inside(self.start_rect + (p - start), bounds),
+            Some(CropDrag::Resize(handle)) => match Self::ratio(data) {
+                Some(ratio) if handle.is_corner() => {
+                    crop::constrain(handle.opposite(self.start_rect), clamped, ratio)
+                }
+                _ => handle.drag(self.start_rect, clamped),
+            },
+            None => return,
+        };
+        self.rect = Some(rect);
+    }
+
+    fn mouse_down(&mut self, pos: Point, transform: Affine, data: &AppData) {
+        let p = transform.inverse() * pos;
+
+        let grabbed = self.rect.and_then(|rect| {
+            let screen_rect = transform.transform_rect_bbox(rect);
+            Self::handles(data)
+                .iter()
+                .find(|handle| handle.position(screen_rect).distance(pos) <= Self::HANDLE_RADIUS)
+                .map(|&handle| CropDrag::Resize(handle))
+                .or_else(|| rect.contains(p).then(|| CropDrag::Move(p)))
+        });
+
+        self.drag = Some(match grabbed {
+            Some(drag) => drag,
+            None => {
+                let bounds = Self::document_rect(data);
+                let clamped = Point::new(p.x.max(0.0).min(bounds.x1), p.y.max(0.0).min(bounds.y1));
+                self.rect = None;
+                CropDrag::Create(clamped)
+            }
+        });
+        self.start_rect = self.rect.unwrap_or(Rect::ZERO);
+
+        if data.crop_aspect == CropAspect::FixedSize && self.rect.is_none() {
+            let rect = Self::fixed_rect(p, data);
+            self.rect = Some(crop::keep_inside(rect, Self::document_rect(data)));
+        }
+    }
+
+    fn mouse_up(&mut self, _transform: Affine, _data: &AppData) {
+        self.drag = None;
+        if let Some(rect) = self.rect {
+            if rect.width() < 1.0 || rect.height() < 1.0 {
+                self.rect = None;
+            }
+        }
+    }
+
+    fn wheel(&mut self, _pos: Point, _delta: Vec2, _mods: Modifiers) {}
+
+    fn overlay(&mut self, ctx: &mut PaintCtx, _pos: Point, transform: Affine, data: &AppData) {
+        let rect = match self.rect {
+            Some(rect) => transform.transform_rect_bbox(rect),
+            None => return,
+        };
+
+        ctx.with_save(|ctx| {
+            // Dim everything around the rectangle.
+            let size = ctx.size();
+            let shade = Color::rgba8(0, 0, 0, 160);
+            ctx.fill(Rect::new(0.0, 0.0, size.width, rect.y0), &shade);
+            ctx.fill(Rect::new(0.0, rect.y1, size.width, size.height), &shade);
+            ctx.fill(Rect::new(0.0, rect.y0, rect.x0, rect.y1), &shade);
+            ctx.fill(Rect::new(rect.x1, rect.y0, size.width, rect.y1), &shade);
+
+            let guide = Color::rgba8(255, 255, 255, 128);
+            for fraction in data.crop_guide.fractions() {
+                let x = rect.x0 + rect.width() * fraction;
+                let y = rect.y0 + rect.height() * fraction;
+                ctx.stroke(Line::new((x, rect.y0), (x, rect.y1)), &guide, 1.0);
+                ctx.stroke(Line::new((rect.x0, y), (rect.x1, y)), &guide, 1.0);
+            }
+
+            ctx.stroke(rect, &Color::WHITE, 1.0);
+
+            for handle in Self::handles(data) {
+                let grip = Rect::from_center_size(handle.position(rect), (7.0, 7.0));
+                ctx.fill(grip, &Color::WHITE);
+                ctx.stroke(grip, &Color::BLACK, 1.0);
+            }
+        });
+    }
+}
+
+/// Takes the floating pixels, or lifts the selected ones out of the active layer.
+/// Tells whether they were lifted.
+fn take_floating(data: &AppData) -> (Floating, bool) {
+    let floating = data.floating_mut().take();
+    let (floating, lifted) = match floating {
+        Some(floating) => (floating, false),
+        None => {
+            let mut layer = data.active_layer_mut();
+            (Floating::lift(layer.data.as_buffer_mut().unwrap()), true)
+        }
+    };
+
+    let visible = [ChannelKind::Red, ChannelKind::Green, ChannelKind::Blue]
+        .map(|kind| data.is_channel_visible(kind));
+    floating.image.refresh_interleaved(visible);
+    (floating, lifted)
+}
+
+/// Resamples the floating pixels into `bounds` of the layer, with `scale` pixels per layer
+/// pixel. `source` maps points of the layer back to where they come from.
+fn render(
+    floating: &Floating,
+    bounds: Rect,
+    scale: f64,
+    source: impl Fn(Point) -> Point,
+    filter: Filter,
+) -> ImageBuffer {
+    let width = (bounds.width() * scale).ceil().max(1.0) as u32;
+    let height = (bounds.height() * scale).ceil().max(1.0) as u32;
+    let origin = bounds.origin();
+    let offset = Vec2::new(floating.x as f64, floating.y as f64);
+
+    let mut image = floating.image.clone();
+    resample::resample_planes(&mut image, |plane| {
+        resample::warp(
+            plane,
+            width,
+            height,
+            |p| source(origin + p.to_vec2() / scale) - offset,
+            filter,
+        )
+    });
+    image
+}
+
+/// Puts the resampled pixels at `bounds` back as floating ones. Pixels lifted out of
+/// the layer land in it right away and the selection follows them.
+fn land(
+    data: &AppData,
+    floating: Floating,
+    image: ImageBuffer,
+    bounds: Rect,
+    lifted: bool,
+    source: impl Fn(Point) -> Point,
+) {
+    let mut resampled = Floating::new(image, bounds.x0 as i32, bounds.y0 as i32);
+    resampled.mask = floating.mask;
+    *data.floating_mut() = Some(resampled);
+
+    if lifted {
+        data.commit_floating();
+        let mut layer = data.active_layer_mut();
+        selection::warp(layer.data.as_buffer_mut().unwrap(), source);
+    }
+}
+
+enum TransformDrag {
+    Move,
+    Rotate,
+    Scale(Handle),
+    Skew(Handle),
+}
+
+/// Pixels being transformed, kept untouched until the transformation is committed.
+struct TransformSession {
+    floating: Floating,
+    /// Whether the pixels were lifted out of the layer rather than floating already.
+    lifted: bool,
+    matrix: Affine,
+}
+
+/// Scales, rotates, skews and moves the floating pixels, or the selected part of
+/// the active layer, with handles on the bounding box. Dragging a corner scales,
+/// Shift keeps the proportions; dragging an edge with Ctrl skews; dragging outside
+/// of the box rotates, Shift snaps the angle; dragging inside moves.
+pub(crate) struct TransformTool {
+    session: Option<TransformSession>,
+    drag: Option<TransformDrag>,
+    start_pos: Point,
+    start_matrix: Affine,
+    pub(crate) mods: Modifiers,
+}
+
+impl TransformTool {
+    const HANDLE_RADIUS: f64 = 6.0;
+    const ROTATION_STEP: f64 = 15.0;
+
+    pub(crate) fn new() -> Self {
+        Self {
+            session: None,
+            drag: None,
+            start_pos: Point::ZERO,
+            start_matrix: Affine::IDENTITY,
+            mods: Modifiers::empty(),
+        }
+    }
+
+    fn begin(&mut self, data: &AppData) {
+        let (floating, lifted) = take_floating(data);
+        self.session = Some(TransformSession {
+            floating,
+            lifted,
+            matrix: Affine::IDENTITY,
+        });
+    }
+
+    /// Resamples the pixels with the final transformation. Pixels lifted out of the layer
+    /// land back in it along with the selection, floating ones keep floating.
+    /// Returns false when nothing is being transformed.
+    pub(crate) fn commit(&mut self, data: &AppData) -> bool {
+        let TransformSession {
+            floating,
+            lifted,
+            matrix,
+        } = match self.session.take() {
+            Some(session) => session,
+            None => return false,
+        };
+        self.drag = None;
+
+        let bounds = matrix.transform_rect_bbox(floating.bounds()).expand();
+        let inverse = matrix.inverse();
+        let source = |p: Point| inverse * p;
+        let image = render(&floating, bounds, 1.0, source, Filter::Bicubic);
+        land(data, floating, image, bounds, lifted, source);
+        true
+    }
+
+    /// Puts the pixels back where they were. Returns false when nothing is being transformed.
+    pub(crate) fn cancel(&mut self, data: &AppData) -> bool {
+        let session = match self.session.take() {
+            Some(session) => session,
+            None => return false,
+        };
+        self.drag = None;
+
+        *data.floating_mut() = Some(session.floating);
+        if session.lifted {
+            data.commit_floating();
+        }
+        true
+    }
+
+    pub(crate) fn params(&self) -> TransformParams {
+        match self.session {
+            Some(ref session) => {
+                TransformParams::from_affine(session.matrix, session.floating.bounds().center())
+            }
+            None => TransformParams::IDENTITY,
+        }
+    }
+
+    pub(crate) fn set_params(&mut self, params: TransformParams) {
+        if let Some(ref mut session) = self.session {
+            session.matrix = params.to_affine(session.floating.bounds().center());
+        }
+    }
+
+    fn scale(&self, bounds: Rect, handle: Handle, p: Point) -> Affine {
+        let anchor = handle.opposite(bounds);
+        let old = handle.position(bounds) - anchor;
+        let new = self.start_matrix.inverse() * p - anchor;
+
+        let factor = |new: f64, old: f64| if old.abs() < 1e-9 { 1.0 } else { new / old };
+        let mut sx = if handle.x == Edge::None {
+            1.0
+        } else {
+            factor(new.x, old.x)
+        };
+        let mut sy = if handle.y == Edge::None {
+            1.0
+        } else {
+            factor(new.y, old.y)
+        };
+        if handle.is_corner() && self.mods.shift() {
+            let s = sx.abs().max(sy.abs());
+            sx = s.copysign(sx);
+            sy = s.copysign(sy);
+        }
+
+        Affine::translate(anchor.to_vec2())
+            * Affine::scale_non_uniform(sx, sy)
+            * Affine::translate(-anchor.to_vec2())
+    }
+
+    fn skew(&self, bounds: Rect, handle: Handle, p: Point) -> Affine {
+        let anchor = handle.opposite(bounds);
+        let arm = handle.position(bounds) - anchor;
+        let inverse = self.start_matrix.inverse();
+        let delta = inverse * p - inverse * self.start_pos;
+
+        // Bounds without width or height would divide by zero.
+        let reach = |length: f64| {
+            if length.abs() < 1.0 {
+                1f64.copysign(length)
+            } else {
+                length
+            }
+        };
+
+        let shear = if handle.x == Edge::None {
+            Affine::new([1.0, 0.0, delta.x / reach(arm.y), 1.0, 0.0, 0.0])
+        } else {
+            Affine::new([1.0, delta.y / reach(arm.x), 0.0, 1.0, 0.0, 0.0])
+        };
+        Affine::translate(anchor.to_vec2()) * shear * Affine::translate(-anchor.to_vec2())
+    }
+
+    fn rotate(&self, center: Point, p: Point) -> Affine {
+        let mut angle = (p - center).atan2() - (self.start_pos - center).atan2();
+        if self.mods.shift() {
+            let step = Self::ROTATION_STEP.to_radians();
+            angle = (angle / step).round() * step;
+        }
+        Affine::translate(center.to_vec2())
+            * Affine::rotate(angle)
+            * Affine::translate(-center.to_vec2())
+    }
+}
+
+impl Tool for TransformTool {
+    fn mouse_move(&mut self, pos: Point, _previous_pos: Point, transform: Affine, _data: &AppData) {
+        let p = transform.inverse() * pos;
+        let bounds = match self.session {
+            Some(ref session) => session.floating.bounds(),
+            None => return,
+        };
+
+        let start = self.start_matrix;
+        let matrix = match self.drag {
+            Some(TransformDrag::Move) => Affine::translate(p - self.start_pos) * start,
+            Some(TransformDrag::Rotate) => self.rotate(start * bounds.center(), p) * start,
+            Some(TransformDrag::Scale(handle)) => start * self.scale(bounds, handle, p),
+            Some(TransformDrag::Skew(handle)) => start * self.skew(bounds, handle, p),
+            None => return,
+        };
+        if let Some(ref mut session) = self.session {
+            session.matrix = matrix;
+        }
+    }
+
+    fn mouse_down(&mut self, pos: Point, transform: Affine, data: &AppData) {
+        if self.session.is_none() {
+            self.begin(data);
+        }
+        let session = self.session.as_ref().unwrap();
+        let bounds = session.floating.bounds();
+        let screen = transform * session.matrix;
+
+        let handle = Handle::ALL
+            .iter()
+            .find(|handle| (screen * handle.position(bounds)).distance(pos) <= Self::HANDLE_RADIUS);
+        let p = transform.inverse() * pos;
+        self.drag = Some(match handle {
+            Some(&handle) if !handle.is_corner() && self.mods.ctrl() => TransformDrag::Skew(handle),
+            Some(&handle) => TransformDrag::Scale(handle),
+            None if bounds.contains(session.matrix.inverse() * p) => TransformDrag::Move,
+            None => TransformDrag::Rotate,
+        });
+        self.start_pos = p;
+        self.start_matrix = session.matrix;
+    }
+
+    fn mouse_up(&mut self, _transform: Affine, _data: &AppData) {
+        self.drag = None;
+    }
+
+    fn wheel(&mut self, _pos: Point, _delta: Vec2, _mods: Modifiers) {}
+
+    fn overlay(&mut self, ctx: &mut PaintCtx, _pos: Point, transform: Affine, _data: &AppData) {
+        let session = match self.session {
+            Some(ref session) => session,
+            None => return,
+        };
+        let bounds = session.floating.bounds();
+        let screen = transform * session.matrix;
+        let offset = Affine::translate((session.floating.x as f64, session.floating.y as f64));
+
+        ctx.with_save(|ctx| {
+            session
+                .floating
+                .image
+                .to_piet(screen * offset, ctx, InterpolationMode::Bilinear);
+
+            let mut outline = BezPath::new();
+            for (i, handle) in Handle::CORNERS.iter().enumerate() {
+                let p = screen * handle.position(bounds);
+                if i == 0 {
+                    outline.move_to(p);
+                } else {
+                    outline.line_to(p);
+                }
+            }
+            outline.close_path();
+            ctx.stroke(outline, &Color::WHITE, 1.0);
+
+            for handle in Handle::ALL.iter() {
+                let grip = Rect::from_center_size(screen * handle.position(bounds), (7.0, 7.0));
+                ctx.fill(grip, &Color::WHITE);
+                ctx.stroke(grip, &Color::BLACK, 1.0);
+            }
+        });
+    }
+}
+
+/// Pixels being distorted along with the control points.
+struct WarpSession {
+    floating: Floating,
+    lifted: bool,
+    mode: WarpMode,
+    mesh: Mesh,
+    /// Rough rendering of the current distortion, scaled down to be fast enough
+    /// to follow the pointer, and its transform into the layer coordinates.
+    preview: ImageBuffer,
+    preview_transform: Affine,
+}
+
+/// Distorts the floating pixels, or the selected part of the active layer, by dragging
+/// the corners of a perspective frame or the points of a mesh.
+pub(crate) struct WarpTool {
+    session: Option<WarpSession>,
+    dragged: Option<usize>,
+}
+
+impl WarpTool {
+    const HANDLE_RADIUS: f64 = 6.0;
+    /// Longer side of the preview in pixels.
+    const PREVIEW_SIZE: f64 = 512.0;
+
+    pub(crate) fn new() -> Self {
+        Self {
+            session: None,
+            dragged: None,
+        }
+    }
+
+    fn mesh(source: Rect, data: &AppData) -> Mesh {
+        match data.warp_mode {
+            WarpMode::Perspective => Mesh::new(source, 1, 1),
+            WarpMode::Mesh => {
+                let cells = data.warp_grid.max(1) as usize;
+                Mesh::new(source, cells, cells)
+            }
+        }
+    }
+
+    fn begin(&mut self, data: &AppData) {
+        let (floating, lifted) = take_floating(data);
+        let mesh = Self::mesh(floating.bounds(), data);
+        self.session = Some(WarpSession {
+            preview: floating.image.clone(),
+            preview_transform: Affine::translate((floating.x as f64, floating.y as f64)),
+            floating,
+            lifted,
+            mode: data.warp_mode,
+            mesh,
+        });
+    }
+
+    /// Starts over with an undistorted mesh, after the mode or the grid size changed.
+    pub(crate) fn reset(&mut self, data: &AppData) {
+        if let Some(ref mut session) = self.session {
+            session.mode = data.warp_mode;
+            session.mesh = Self::mesh(session.floating.bounds(), data);
+            Self::refresh_preview(session, data);
+        }
+    }
+
+    fn refresh_preview(session: &mut WarpSession, data: &AppData) {
+        let bounds = session.mesh.bounds();
+        let scale = (Self::PREVIEW_SIZE / bounds.width().max(bounds.height())).min(1.0);
+        let source = transform::warp_source(session.mode, &session.mesh);
+        session.preview = render(&session.floating, bounds, scale, source, Filter::Nearest);
+
+        let visible = [ChannelKind::Red, ChannelKind::Green, ChannelKind::Blue]
+            .map(|kind| data.is_channel_visible(kind));
+        session.preview.refresh_interleaved(visible);
+        session.preview_transform =
+            Affine::translate(bounds.origin().to_vec2()) * Affine::scale(1.0 / scale);
+    }
+
+    /// Resamples the pixels with the final distortion, returns false when nothing
+    /// is being warped.
+    pub(crate) fn commit(&mut self, data: &AppData) -> bool {
+        let session = match self.session.take() {
+            Some(session) => session,
+            None => return false,
+        };
+        self.dragged = None;
+
+        let bounds = session.mesh.bounds().expand();
+        let source = transform::warp_source(session.mode, &session.mesh);
+        let image = render(&session.floating, bounds, 1.0, &source, data.warp_filter);
+        land(
+            data,
+            session.floating,
+            image,
+            bounds,
+            session.lifted,
+            &source,
+        );
+        true
+    }
+
+    /// Puts the pixels back where they were, returns false when nothing is being warped.
+    pub(crate) fn cancel(&mut self, data: &AppData) -> bool {
+        let session = match self.session.take() {
+            Some(session) => session,
+            None => return false,
+        };
+        self.dragged = None;
+
+        *data.floating_mut() = Some(session.floating);
+        if session.lifted {
+            data.commit_floating();
+        }
+        true
+    }
+}
+
+impl Tool for WarpTool {
+    fn mouse_move(&mut self, pos: Point, _previous_pos: Point, transform: Affine, data: &AppData) {
+        if let (Some(session), Some(index)) = (self.session.as_mut(), self.dragged) {
+            session.mesh.points[index] = transform.inverse() * pos;
+            Self::refresh_preview(session, data);
+        }
+    }
+
+    fn mouse_down(&mut self, pos: Point, transform: Affine, data: &AppData) {
+        if self.session.is_none() {
+            self.begin(data);
+        }
+
+        let session = self.session.as_ref().unwrap();
+        self.dragged = session
+            .mesh
+            .points
+            .iter()
+            .position(|p| (transform * *p).distance(pos) <= Self::HANDLE_RADIUS);
+    }
+
+    fn mouse_up(&mut self, _transform: Affine, _data: &AppData) {
+        self.dragged = None;
+    }
+
+    fn wheel(&mut self, _pos: Point, _delta: Vec2, _mods: Modifiers) {}
+
+    fn overlay(&mut self, ctx: &mut PaintCtx, _pos: Point, transform: Affine, _data: &AppData) {
+        let session = match self.session {
+            Some(ref session) => session,
+            None => return,
+        };
+        let mesh = &session.mesh;
+
+        ctx.with_save(|ctx| {
+            session.preview.to_piet(
+                transform * session.preview_transform,
+                ctx,
+                InterpolationMode::Bilinear,
             );
+
+            let mut lines = BezPath::new();
+            for row in 0..=mesh.rows {
+                lines.move_to(transform * mesh.point(0, row));
+                for column in 1..=mesh.columns {
+                    lines.line_to(transform * mesh.point(column, row));
+                }
+            }
+            for column in 0..=mesh.columns {
+                lines.move_to(transform * mesh.point(column, 0));
+                for row in 1..=mesh.rows {
+                    lines.line_to(transform * mesh.point(column, row));
+                }
+            }
+            ctx.stroke(lines, &Color::WHITE, 1.0);
+
+            for p in mesh.points.iter() {
+                let grip = Rect::from_center_size(transform * *p, (7.0, 7.0));
+                ctx.fill(grip, &Color::WHITE);
+                ctx.stroke(grip, &Color::BLACK, 1.0);
+            }
         });
     }
 }
 
-pub(crate) struct MovingTool {
+/// Drags the floating pixels around.
+pub(crate) struct FloatingTool {
+    start_pos: Point,
+    start_offset: (i32, i32),
+}
+
+impl FloatingTool {
+    pub(crate) fn new() -> Self {
+        Self {
+            start_pos: Point::ZERO,
+            start_offset: (0, 0),
+        }
+    }
+}
+
+impl Tool for FloatingTool {
+    fn mouse_move(&mut self, pos: Point, _previous_pos: Point, transform: Affine, data: &AppData) {
+        let transform = transform.inverse();
+        let delta = transform * pos - transform * self.start_pos;
+
+        // Moving does not change the pixels, so there is no need to mark anything dirty.
+        if let Some(floating) = data.floating.borrow_mut().as_mut() {
+            floating.x = self.start_offset.0 + delta.x.round() as i32;
+            floating.y = self.start_offset.1 + delta.y.round() as i32;
+        }
+    }
+
+    fn mouse_down(&mut self, pos: Point, _transform: Affine, data: &AppData) {
+        self.start_pos = pos;
+        if let Some(floating) = data.floating.borrow().as_ref() {
+            self.start_offset = (floating.x, floating.y);
+        }
+    }
+
+    fn mouse_up(&mut self, _transform: Affine, _data: &AppData) {}
+
+    fn wheel(&mut self, _pos: Point, _delta: Vec2, _mods: Modifiers) {}
+
+    fn overlay(&mut self, _ctx: &mut PaintCtx, _pos: Point, _transform: Affine, _data: &AppData) {}
+}
+
+pub(crate) struct PanTool {
     pub(crate) offset_x: f64,
     pub(crate) offset_y: f64,
     start_moving_pos: Point,
@@ -206,7 +1044,7 @@ pub(crate) struct MovingTool {
     scale: f64,
 }
 
-impl MovingTool {
+impl PanTool {
     pub(crate) fn new() -> Self {
         Self {
             offset_x: 0.0,
@@ -228,13 +1066,9 @@ impl MovingTool {
             self.offset_y,
         ])
     }
-
-    pub(crate) fn scale(&self) -> f64 {
-        self.scale
-    }
 }
 
-impl Tool for MovingTool {
+impl Tool for PanTool {
     fn mouse_move(
         &mut self,
         pos: Point,
@@ -283,7 +1117,7 @@ impl Tool for MovingTool {
         }
     }
 
-    fn overlay(&mut self, _ctx: &mut PaintCtx, _pos: Point, _scale: f64) {}
+    fn overlay(&mut self, _ctx: &mut PaintCtx, _pos: Point, _transform: Affine, _data: &AppData) {}
 }
 
 pub(crate) enum ToolRef<'a> {
@@ -0,0 +1,372 @@
+use druid::{Affine, Data, Lens, Point, Rect, Vec2};
+
+use crate::channels::{Matrix, View};
+use crate::image_buffer::ImageBuffer;
+use crate::resample::{self, Filter};
+
+/// Whether a transformation applies to the whole document or to the active layer only.
+#[derive(Clone, Copy, PartialEq, Eq, Data, Debug)]
+pub(crate) enum Scope {
+    Document,
+    Layer,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Data, Debug)]
+pub(crate) enum Axis {
+    Horizontal,
+    Vertical,
+}
+
+/// Free transformation broken down into parts that can be entered numerically.
+/// Scaling, skewing and rotation happen around the center of the transformed area.
+#[derive(Clone, Copy, PartialEq, Data, Debug, Lens)]
+pub(crate) struct TransformParams {
+    /// Movement of the center, in pixels.
+    pub(crate) x: f64,
+    pub(crate) y: f64,
+    /// In percent.
+    pub(crate) scale_x: f64,
+    pub(crate) scale_y: f64,
+    /// Clockwise, in degrees.
+    pub(crate) angle: f64,
+    /// Horizontal skew in degrees.
+    pub(crate) skew: f64,
+}
+
+impl TransformParams {
+    pub(crate) const IDENTITY: TransformParams = TransformParams {
+        x: 0.0,
+        y: 0.0,
+        scale_x: 100.0,
+        scale_y: 100.0,
+        angle: 0.0,
+        skew: 0.0,
+    };
+
+    pub(crate) fn to_affine(self, center: Point) -> Affine {
+        let center = center.to_vec2();
+        let skew = Affine::new([1.0, 0.0, self.skew.to_radians().tan(), 1.0, 0.0, 0.0]);
+        Affine::translate(center + Vec2::new(self.x, self.y))
+            * Affine::rotate(self.angle.to_radians())
+            * skew
+            * Affine::scale_non_uniform(self.scale_x / 100.0, self.scale_y / 100.0)
+            * Affine::translate(-center)
+    }
+
+    /// Inverse of `to_affine`, rounded to two decimal places for display.
+    pub(crate) fn from_affine(matrix: Affine, center: Point) -> Self {
+        let [a, b, c, d, _, _] = matrix.as_coeffs();
+        // The first column is the rotated horizontal scale, undoing the rotation of
+        // the second one leaves the skew and the vertical scale.
+        let scale_x = a.hypot(b);
+        let angle = b.atan2(a);
+        let (sin, cos) = angle.sin_cos();
+        let sheared = cos * c + sin * d;
+        let scale_y = cos * d - sin * c;
+        let offset = matrix * center - center;
+
+        let round = |value: f64| (value * 100.0).round() / 100.0;
+        Self {
+            x: round(offset.x),
+            y: round(offset.y),
+            scale_x: round(scale_x * 100.0),
+            scale_y: round(scale_y * 100.0),
+            angle: round(angle.to_degrees()),
+            skew: round((sheared / scale_y).atan().to_degrees()),
+        }
+    }
+}
+
+/// Mirrors the image, along with its selection, across the given axis.
+pub(crate) fn flip(image: &mut ImageBuffer, axis: Axis) {
+    image.map_planes(|_, plane| {
+        let (width, height) = (plane.width(), plane.height());
+        remap(plane, width, height, |x, y| match axis {
+            Axis::Horizontal => (width - 1 - x, y),
+            Axis::Vertical => (x, height - 1 - y),
+        })
+    });
+}
+
+/// Rotates the image clockwise by the given angle in degrees.
+///
+/// With `expand` the canvas grows to fit the whole rotated image, quarter turns are exact
+/// then and only swap the dimensions. Otherwise the image turns around the center of
+/// the canvas, which keeps its size.
+pub(crate) fn rotate(image: &mut ImageBuffer, degrees: f64, filter: Filter, expand: bool) {
+    let degrees = degrees.rem_euclid(360.0);
+    if degrees == 0.0 {
+        return;
+    }
+
+    if expand && degrees % 90.0 == 0.0 {
+        image.map_planes(|_, plane| {
+            let (width, height) = (plane.width(), plane.height());
+            match degrees as u32 {
+                90 => remap(plane, height, width, |x, y| (y, height - 1 - x)),
+                180 => remap(plane, width, height, |x, y| (width - 1 - x, height - 1 - y)),
+                _ => remap(plane, height, width, |x, y| (width - 1 - y, x)),
+            }
+        });
+        return;
+    }
+
+    let (width, height) = image.size();
+    let rotation = Affine::rotate(degrees.to_radians());
+    let (new_width, new_height) = if expand {
+        let bounds = rotation.transform_rect_bbox(Rect::new(0.0, 0.0, width as f64, height as f64));
+        // Tolerate rounding errors, so that nearly exact sizes are not padded by a pixel.
+        (
+            (bounds.width() - 1e-6).ceil() as u32,
+            (bounds.height() - 1e-6).ceil() as u32,
+        )
+    } else {
+        (width, height)
+    };
+
+    let old_center = Vec2::new(width as f64, height as f64) / 2.0;
+    let new_center = Vec2::new(new_width as f64, new_height as f64) / 2.0;
+    let inverse =
+        (Affine::translate(new_center) * rotation * Affine::translate(-old_center)).inverse();
+    resample::resample_planes(image, |plane| {
+        resample::warp(plane, new_width, new_height, |p| inverse * p, filter)
+    });
+}
+
+/// Builds a `width` × `height` plane, where every pixel is copied from the coordinates
+/// given by `source`.
+fn remap(
+    plane: View<'_, u8>,
+    width: u32,
+    height: u32,
+    source: impl Fn(u32, u32) -> (u32, u32),
+) -> Matrix<u8> {
+    let mut out = Matrix::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let (sx, sy) = source(x, y);
+            out.set(x, y, plane.get(sx, sy));
+        }
+    }
+    out
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Data, Debug)]
+pub(crate) enum WarpMode {
+    /// Four corners moved independently, straight lines stay straight.
+    Perspective,
+    /// Grid of points, each cell is bent bilinearly.
+    Mesh,
+}
+
+/// Grid of control points laid over a rectangle of the source image.
+/// Points are stored row by row, in the coordinates of the layer.
+#[derive(Clone, Debug)]
+pub(crate) struct Mesh {
+    pub(crate) source: Rect,
+    pub(crate) columns: usize,
+    pub(crate) rows: usize,
+    pub(crate) points: Vec<Point>,
+}
+
+impl Mesh {
+    /// Regular grid, which leaves the image as it is.
+    pub(crate) fn new(source: Rect, columns: usize, rows: usize) -> Self {
+        let mut points = Vec::with_capacity((columns + 1) * (rows + 1));
+        for row in 0..=rows {
+            for column in 0..=columns {
+                points.push(Point::new(
+                    source.x0 + source.width() * column as f64 / columns as f64,
+                    source.y0 + source.height() * row as f64 / rows as f64,
+                ));
+            }
+        }
+        Self {
+            source,
+            columns,
+            rows,
+            points,
+        }
+    }
+
+    pub(crate) fn point(&self, column: usize, row: usize) -> Point {
+        self.points[row * (self.columns + 1) + column]
+    }
+
+    /// Corners of the cell in clockwise order starting at the top left.
+    fn cell(&self, column: usize, row: usize) -> [Point; 4] {
+        [
+            self.point(column, row),
+            self.point(column + 1, row),
+            self.point(column + 1, row + 1),
+            self.point(column, row + 1),
+        ]
+    }
+
+    pub(crate) fn corners(&self) -> [Point; 4] {
+        [
+            self.point(0, 0),
+            self.point(self.columns, 0),
+            self.point(self.columns, self.rows),
+            self.point(0, self.rows),
+        ]
+    }
+
+    /// Area covered by the warped image.
+    pub(crate) fn bounds(&self) -> Rect {
+        self.points
+            .iter()
+            .fold(Rect::from_points(self.points[0], self.points[0]), |r, p| {
+                r.union_pt(*p)
+            })
+    }
+
+    /// Position in the source of a point of the warped image, if any cell covers it.
+    pub(crate) fn source_of(&self, p: Point) -> Option<Point> {
+        let cell_width = self.source.width() / self.columns as f64;
+        let cell_height = self.source.height() / self.rows as f64;
+        for row in 0..self.rows {
+            for column in 0..self.columns {
+                let quad = self.cell(column, row);
+                let bounds = quad
+                    .iter()
+                    .fold(Rect::from_points(quad[0], quad[0]), |r, q| r.union_pt(*q));
+                if !bounds.inflate(0.5, 0.5).contains(p) {
+                    continue;
+                }
+                if let Some((u, v)) = inverse_bilinear(p, quad) {
+                    return Some(Point::new(
+                        self.source.x0 + (column as f64 + u) * cell_width,
+                        self.source.y0 + (row as f64 + v) * cell_height,
+                    ));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Finds (u, v) in the unit square that the bilinear patch spanned by `quad` maps to `p`,
+/// using Newton's method.
+fn inverse_bilinear(p: Point, quad: [Point; 4]) -> Option<(f64, f64)> {
+    let [q00, q10, q11, q01] = quad;
+    let (mut u, mut v) = (0.5, 0.5);
+    for _ in 0..10 {
+        let at = |u: f64, v: f64| {
+            q00.to_vec2() * ((1.0 - u) * (1.0 - v))
+                + q10.to_vec2() * (u * (1.0 - v))
+                + q11.to_vec2() * (u * v)
+                + q01.to_vec2() * ((1.0 - u) * v)
+        };
+        let error = at(u, v) - p.to_vec2();
+        if error.hypot() < 1e-3 {
+            break;
+        }
+
+        let du = (q10 - q00) * (1.0 - v) + (q11 - q01) * v;
+        let dv = (q01 - q00) * (1.0 - u) + (q11 - q10) * u;
+        let det = du.cross(dv);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        u -= error.cross(dv) / det;
+        v -= du.cross(error) / det;
+    }
+
+    const EPSILON: f64 = 1e-6;
+    if (-EPSILON..=1.0 + EPSILON).contains(&u) && (-EPSILON..=1.0 + EPSILON).contains(&v) {
+        Some((u.max(0.0).min(1.0), v.max(0.0).min(1.0)))
+    } else {
+        None
+    }
+}
+
+/// Projective transformation, stored row by row with the last coefficient fixed at one.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Homography([f64; 9]);
+
+impl Homography {
+    /// Maps the four `from` points to the four `to` points. None when three
+    /// of the points are collinear.
+    pub(crate) fn from_points(from: [Point; 4], to: [Point; 4]) -> Option<Self> {
+        // Two equations for every pair of points, the last column is the right hand side.
+        let mut system = [[0.0; 9]; 8];
+        for (i, (f, t)) in from.iter().zip(to.iter()).enumerate() {
+            system[2 * i] = [f.x, f.y, 1.0, 0.0, 0.0, 0.0, -t.x * f.x, -t.x * f.y, t.x];
+            system[2 * i + 1] = [0.0, 0.0, 0.0, f.x, f.y, 1.0, -t.y * f.x, -t.y * f.y, t.y];
+        }
+
+        // Gaussian elimination with partial pivoting.
+        for column in 0..8 {
+            let pivot = (column..8)
+                .max_by(|&a, &b| {
+                    system[a][column]
+                        .abs()
+                        .partial_cmp(&system[b][column].abs())
+                        .unwrap()
+                })
+                .unwrap();
+            if system[pivot][column].abs() < 1e-12 {
+                return None;
+            }
+            system.swap(column, pivot);
+
+            for row in 0..8 {
+                if row != column {
+                    let factor = system[row][column] / system[column][column];
+                    for k in column..9 {
+                        system[row][k] -= factor * system[column][k];
+                    }
+                }
+            }
+        }
+
+        let mut h = [1.0; 9];
+        for (i, coefficient) in h.iter_mut().take(8).enumerate() {
+            *coefficient = system[i][8] / system[i][i];
+        }
+        // Scale so the points being mapped are in front, which is where `apply` expects them.
+        if h[6] * from[0].x + h[7] * from[0].y + h[8] < 0.0 {
+            h.iter_mut()
+                .for_each(|coefficient| *coefficient = -*coefficient);
+        }
+        Some(Self(h))
+    }
+
+    /// None for points mapped to infinity or past it.
+    pub(crate) fn apply(&self, p: Point) -> Option<Point> {
+        let h = &self.0;
+        let w = h[6] * p.x + h[7] * p.y + h[8];
+        if w <= 1e-12 {
+            return None;
+        }
+        Some(Point::new(
+            (h[0] * p.x + h[1] * p.y + h[2]) / w,
+            (h[3] * p.x + h[4] * p.y + h[5]) / w,
+        ))
+    }
+}
+
+/// Where a point of the warped image comes from. Perspective uses only the corners
+/// of the mesh. Points without a source are sent far outside of the image.
+pub(crate) fn warp_source(mode: WarpMode, mesh: &Mesh) -> impl Fn(Point) -> Point + '_ {
+    const NOWHERE: Point = Point::new(-1e9, -1e9);
+    let source = mesh.source;
+    let homography = match mode {
+        WarpMode::Perspective => Homography::from_points(
+            mesh.corners(),
+            [
+                Point::new(source.x0, source.y0),
+                Point::new(source.x1, source.y0),
+                Point::new(source.x1, source.y1),
+                Point::new(source.x0, source.y1),
+            ],
+        ),
+        WarpMode::Mesh => None,
+    };
+
+    move |p| match mode {
+        WarpMode::Perspective => homography.and_then(|h| h.apply(p)).unwrap_or(NOWHERE),
+        WarpMode::Mesh => mesh.source_of(p).unwrap_or(NOWHERE),
+    }
+}
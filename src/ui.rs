@@ -1,10 +1,19 @@
-use druid::widget::{Checkbox, Flex, FlexParams, Label, LabelText, List, Scroll, SizedBox, Slider};
-use druid::{Color, UnitPoint, Widget, WidgetExt};
+use druid::text::ParseFormatter;
+use druid::widget::{
+    Button, Checkbox, Controller, CrossAxisAlignment, Flex, FlexParams, Label, LabelText, List,
+    RadioGroup, Scroll, SizedBox, Slider, TextBox, ViewSwitcher,
+};
+use druid::{Color, Env, Event, EventCtx, UnitPoint, Widget, WidgetExt};
 
 use crate::color_picker::ColorPicker;
+use crate::commands;
+use crate::crop::{CropAspect, CropGuide};
 use crate::histogram::Histogram;
 use crate::image_edit::ImageEditor;
-use crate::state::{AppData, Channel, Layer};
+use crate::resample::Filter;
+use crate::selection::SelectionMode;
+use crate::state::{AppData, Channel, Layer, ToolKind};
+use crate::transform::{TransformParams, WarpMode};
 use crate::widgets::{ChannelThumbnail, LayerThumbnail};
 
 fn make_channel_item() -> impl Widget<Channel> {
@@ -63,12 +72,188 @@ fn make_layer_item() -> impl Widget<Layer> {
         .padding(5.0)
 }
 
+fn make_toolbox() -> impl Widget<AppData> {
+    RadioGroup::column(vec![
+        ("Brush", ToolKind::Draw),
+        ("Move", ToolKind::Move),
+        ("Brush selection", ToolKind::BrushSelection),
+        ("Rectangle selection", ToolKind::ShapeSelection),
+        ("Crop", ToolKind::Crop),
+        ("Free transform", ToolKind::Transform),
+        ("Warp", ToolKind::Warp),
+    ])
+    .lens(AppData::tool)
+    .controller(ToolSwitchController)
+}
+
+/// Lets the editor finish the work of the previous tool once another one is picked.
+struct ToolSwitchController;
+
+impl<W: Widget<AppData>> Controller<AppData, W> for ToolSwitchController {
+    fn event(
+        &mut self,
+        child: &mut W,
+        ctx: &mut EventCtx,
+        event: &Event,
+        data: &mut AppData,
+        env: &Env,
+    ) {
+        let previous = data.tool;
+        child.event(ctx, event, data, env);
+        if data.tool != previous {
+            ctx.submit_command(commands::TOOL_CHANGED.with(previous));
+        }
+    }
+}
+
+fn make_selection_options() -> impl Widget<AppData> {
+    RadioGroup::column(vec![
+        ("Replace", SelectionMode::Replace),
+        ("Add (Shift)", SelectionMode::Add),
+        ("Subtract (Ctrl)", SelectionMode::Subtract),
+        ("Intersect (Ctrl+Shift)", SelectionMode::Intersect),
+        ("Xor", SelectionMode::Xor),
+    ])
+    .lens(AppData::selection_mode)
+}
+
+fn make_crop_options() -> impl Widget<AppData> {
+    let size = Flex::row()
+        .with_child(
+            TextBox::new()
+                .with_formatter(ParseFormatter::new())
+                .fix_width(60.0)
+                .lens(AppData::crop_width),
+        )
+        .with_child(Label::new("×").padding((5.0, 0.0)))
+        .with_child(
+            TextBox::new()
+                .with_formatter(ParseFormatter::new())
+                .fix_width(60.0)
+                .lens(AppData::crop_height),
+        )
+        .with_child(Label::new("px").padding((5.0, 0.0)));
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            RadioGroup::column(vec![
+                ("Free", CropAspect::Free),
+                ("Original ratio", CropAspect::Original),
+                ("1:1", CropAspect::Ratio(1, 1)),
+                ("4:3", CropAspect::Ratio(4, 3)),
+                ("3:4", CropAspect::Ratio(3, 4)),
+                ("16:9", CropAspect::Ratio(16, 9)),
+                ("9:16", CropAspect::Ratio(9, 16)),
+                ("Fixed size", CropAspect::FixedSize),
+            ])
+            .lens(AppData::crop_aspect),
+        )
+        .with_child(size.padding((0.0, 5.0)))
+        .with_child(
+            RadioGroup::column(vec![
+                ("No guides", CropGuide::None),
+                ("Rule of thirds", CropGuide::RuleOfThirds),
+                ("Golden ratio", CropGuide::GoldenRatio),
+            ])
+            .lens(AppData::crop_guide),
+        )
+        .with_child(
+            Button::new("Crop")
+                .on_click(|ctx, _data: &mut AppData, _env| ctx.submit_command(commands::CROP)),
+        )
+}
+
+fn make_transform_options() -> impl Widget<AppData> {
+    let field = |label: &'static str, unit: &'static str| {
+        Flex::row()
+            .with_child(Label::new(label).fix_width(50.0))
+            .with_child(
+                TextBox::new()
+                    .with_formatter(ParseFormatter::new())
+                    .fix_width(80.0),
+            )
+            .with_child(Label::new(unit).padding((5.0, 0.0)))
+    };
+
+    let params = Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(field("X", "px").lens(TransformParams::x))
+        .with_child(field("Y", "px").lens(TransformParams::y))
+        .with_child(field("Width", "%").lens(TransformParams::scale_x))
+        .with_child(field("Height", "%").lens(TransformParams::scale_y))
+        .with_child(field("Angle", "°").lens(TransformParams::angle))
+        .with_child(field("Skew", "°").lens(TransformParams::skew))
+        .lens(AppData::transform);
+
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(params)
+        .with_spacer(5.0)
+        .with_child(make_apply_buttons())
+}
+
+fn make_warp_options() -> impl Widget<AppData> {
+    Flex::column()
+        .cross_axis_alignment(CrossAxisAlignment::Start)
+        .with_child(
+            RadioGroup::column(vec![
+                ("Perspective", WarpMode::Perspective),
+                ("Mesh", WarpMode::Mesh),
+            ])
+            .lens(AppData::warp_mode),
+        )
+        .with_child(
+            RadioGroup::row(vec![("2×2", 2), ("3×3", 3), ("4×4", 4)]).lens(AppData::warp_grid),
+        )
+        .with_child(
+            RadioGroup::row(vec![
+                ("Bilinear", Filter::Bilinear),
+                ("Bicubic", Filter::Bicubic),
+            ])
+            .lens(AppData::warp_filter),
+        )
+        .with_spacer(5.0)
+        .with_child(make_apply_buttons())
+}
+
+fn make_apply_buttons() -> impl Widget<AppData> {
+    Flex::row()
+        .with_child(
+            Button::new("Apply").on_click(|ctx, _data: &mut AppData, _env| {
+                ctx.submit_command(commands::COMMIT_TRANSFORM)
+            }),
+        )
+        .with_spacer(5.0)
+        .with_child(
+            Button::new("Cancel").on_click(|ctx, _data: &mut AppData, _env| {
+                ctx.submit_command(commands::CANCEL_TRANSFORM)
+            }),
+        )
+}
+
+fn make_tool_options() -> impl Widget<AppData> {
+    ViewSwitcher::new(
+        |data: &AppData, _env| data.tool,
+        |tool, _data, _env| match tool {
+            ToolKind::BrushSelection | ToolKind::ShapeSelection => make_selection_options().boxed(),
+            ToolKind::Crop => make_crop_options().boxed(),
+            ToolKind::Transform => make_transform_options().boxed(),
+            ToolKind::Warp => make_warp_options().boxed(),
+            ToolKind::Draw | ToolKind::Move => SizedBox::empty().boxed(),
+        },
+    )
+}
+
 pub(crate) fn make_root() -> impl Widget<AppData> {
     Flex::row()
         .with_flex_child(ImageEditor::new(), 1.0)
         .with_child(
             SizedBox::new(
                 Flex::column()
+                    .cross_axis_alignment(CrossAxisAlignment::Start)
+                    .with_child(make_toolbox().padding(5.0))
+                    .with_child(make_tool_options().padding(5.0))
                     .with_flex_child(
                         SizedBox::new(ColorPicker::new()).lens(AppData::brush_color),
                         1.0,
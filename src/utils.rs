@@ -74,3 +74,10 @@ pub(crate) fn interpolate_points(begin: Point, end: Point, mut f: impl FnMut(Poi
 
     bresenham::plot_line(x0, y0, x1, y1, |x, y| f(Point::new(x as f64, y as f64)));
 }
+
+/// Linear interpolation between `from` and `to`, where `weight` of 255 gives `to`.
+#[inline]
+pub(crate) fn blend(from: u8, to: u8, weight: u8) -> u8 {
+    let weight = weight as u32;
+    ((from as u32 * (255 - weight) + to as u32 * weight + 127) / 255) as u8
+}